@@ -0,0 +1,109 @@
+//! Times the brute force CPU detection on the scenes of the test cases in run_tests.ipynb, against the `Arc<Mutex<Vec>>` collection it replaced, `detect_pairs_with_mutexes`. `fresh` is `detect_pairs`, which allocates its buffers every call, and `reused` is `detect_pairs_into` with the same buffers every call, like `detect_collisions_cpu`.
+//!
+//! `cargo bench --bench cpu_detection --features bench` runs the cases up to 60 wide. Pass widths to run others, like `cargo bench --bench cpu_detection --features bench -- 80 100`. The larger ones need several GB for the pairs
+
+use std::time::{Duration, Instant};
+
+use bevy::math::{Vec2, bounding::BoundingCircle};
+use gpu_accelerated_collision_detection::{
+    collision_core::detect::{
+        Collidable, DetectOptions, JobBuffers, detect_pairs, detect_pairs_into,
+        detect_pairs_with_mutexes,
+    },
+    cpu_collision_detection::collider::Collider,
+};
+
+/// (width, frames) of the test cases, all with a sensor radius of 21 and a body radius of 3
+const TEST_CASES: [(i32, u32); 8] = [
+    (3, 1000),
+    (24, 200),
+    (40, 100),
+    (60, 20),
+    (80, 6),
+    (100, 4),
+    (120, 5),
+    (160, 5),
+];
+const DEFAULT_MAX_WIDTH: i32 = 60;
+/// Enough runs to get a stable median without taking minutes on the small cases
+const MAX_RUNS: u32 = 50;
+
+/// A body and a sensor on every point of the grid, like `spawn_entities`, on the default layers so every overlapping pair counts
+fn scene(width: i32) -> Vec<Collidable> {
+    let half_width = width / 2;
+    let mut collidables = Vec::new();
+    for x in -half_width..half_width {
+        for y in -half_width..half_width {
+            let center = Vec2::new(x as f32, y as f32);
+            collidables.push(Collidable::new(Collider::Circle(BoundingCircle::new(
+                center, 3.,
+            ))));
+            collidables.push(Collidable::new(Collider::Circle(BoundingCircle::new(
+                center, 21.,
+            ))));
+        }
+    }
+    collidables
+}
+
+/// Median of `runs` runs of `run`, which returns the number of pairs found
+fn time(runs: u32, mut run: impl FnMut() -> usize) -> (Duration, usize) {
+    let mut pairs = 0;
+    let mut times: Vec<Duration> = (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            pairs = run();
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    (times[times.len() / 2], pairs)
+}
+
+fn main() {
+    let widths: Vec<i32> = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse().ok())
+        .collect();
+    let options = DetectOptions::default();
+    println!(
+        "{} rayon threads on {} cores\n{:>6} {:>11} {:>10} {:>12} {:>12} {:>12} {:>8}",
+        rayon::current_num_threads(),
+        std::thread::available_parallelism().map_or(1, |cores| cores.get()),
+        "width",
+        "collidables",
+        "pairs",
+        "mutexes",
+        "fresh",
+        "reused",
+        "speedup"
+    );
+    for (width, frames) in TEST_CASES {
+        if !(widths.contains(&width) || widths.is_empty() && width <= DEFAULT_MAX_WIDTH) {
+            continue;
+        }
+        let collidables = scene(width);
+        let runs = frames.min(MAX_RUNS);
+        let (mutexes, expected) = time(runs, || {
+            detect_pairs_with_mutexes(&collidables, &options).len()
+        });
+        let (fresh, pairs) = time(runs, || detect_pairs(&collidables, &options).len());
+        assert_eq!(pairs, expected);
+        let (mut buffers, mut pairs_buffer) = (JobBuffers::default(), Vec::new());
+        let (reused_time, pairs) = time(runs, || {
+            detect_pairs_into(&collidables, &options, &mut buffers, &mut pairs_buffer);
+            pairs_buffer.len()
+        });
+        assert_eq!(pairs, expected);
+        println!(
+            "{:>6} {:>11} {:>10} {:>12.3?} {:>12.3?} {:>12.3?} {:>7.2}x",
+            width,
+            collidables.len(),
+            pairs,
+            mutexes,
+            fresh,
+            reused_time,
+            mutexes.as_secs_f64() / reused_time.as_secs_f64()
+        );
+    }
+}
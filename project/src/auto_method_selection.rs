@@ -0,0 +1,299 @@
+use std::time::{Duration, Instant};
+
+use bevy::{log, prelude::*};
+
+use crate::{
+    collision_detection_plugin::{CollisionDetectionMethod, CpuDetectionSet, GpuDetectionSet},
+    cpu_collision_detection::cpu_collision_detection::WithCpuCollider,
+    gpu_collision_detection::multi_batch_manager::population::CollidablePopulation,
+};
+
+/// How long the method that ran this frame took, smoothed over frames, and how many collidables there were
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MethodTiming {
+    pub seconds: f32,
+    pub population: usize,
+}
+
+impl MethodTiming {
+    /// What the measured time would be at `population`, assuming it grows with the square of the population. That is right for the brute force broadphases, but overestimates the change for the others, which are closer to O(n) while collidables are spread out. Either way the estimate is only used for the method that isn't running, and is replaced by a measurement once it runs
+    fn estimate_at(&self, population: usize) -> f32 {
+        let scale = population as f32 / self.population.max(1) as f32;
+        self.seconds * scale * scale
+    }
+}
+
+/// Picks between the CPU and the GPU every frame for `CollisionDetectionMethod::Auto`, with `current` being the one that runs.
+///
+/// Outside of the population bounds the choice is fixed. Between them the method with the lower timing wins, where the timing of the one not running is its last measurement scaled to the current population. A method that was never measured is tried once. To avoid flipping back and forth, the other method has to look `switch_ratio` times as fast, and after the first frame a method runs at least `min_frames_between_switches` frames in a row, even when the population crosses a bound.
+#[derive(Debug, Clone, Resource)]
+pub struct AutoMethodSelection {
+    /// `Cpu` or `Gpu`
+    pub current: CollisionDetectionMethod,
+    /// Always the CPU below this many collidables
+    pub min_gpu_population: usize,
+    /// Always the GPU from this many collidables on
+    pub max_cpu_population: usize,
+    pub switch_ratio: f32,
+    pub min_frames_between_switches: u32,
+    /// How much each frame's measurement counts in the smoothed timing, from 0 to 1
+    pub smoothing: f32,
+    pub cpu_timing: Option<MethodTiming>,
+    pub gpu_timing: Option<MethodTiming>,
+    frames_since_switch: u32,
+}
+
+impl Default for AutoMethodSelection {
+    fn default() -> Self {
+        AutoMethodSelection {
+            current: CollisionDetectionMethod::Cpu,
+            min_gpu_population: 1_000,
+            max_cpu_population: 20_000,
+            switch_ratio: 0.8,
+            min_frames_between_switches: 30,
+            smoothing: 0.2,
+            cpu_timing: None,
+            gpu_timing: None,
+            // so the first frame can pick by the bounds straight away
+            frames_since_switch: u32::MAX,
+        }
+    }
+}
+
+impl AutoMethodSelection {
+    fn timing_mut(&mut self, method: CollisionDetectionMethod) -> &mut Option<MethodTiming> {
+        match method {
+            CollisionDetectionMethod::Gpu => &mut self.gpu_timing,
+            _ => &mut self.cpu_timing,
+        }
+    }
+
+    fn record(&mut self, elapsed: Duration, population: usize) {
+        let smoothing = self.smoothing;
+        let seconds = elapsed.as_secs_f32();
+        let timing = self.timing_mut(self.current);
+        *timing = Some(match *timing {
+            // the smoothed time is kept at the latest population
+            Some(previous) => MethodTiming {
+                seconds: previous.estimate_at(population) * (1. - smoothing) + seconds * smoothing,
+                population,
+            },
+            None => MethodTiming {
+                seconds,
+                population,
+            },
+        });
+        self.frames_since_switch = self.frames_since_switch.saturating_add(1);
+    }
+
+    /// The method for the next frame
+    fn choose(&self, population: usize) -> CollisionDetectionMethod {
+        // a population going back and forth over a bound would otherwise switch every frame
+        if self.frames_since_switch < self.min_frames_between_switches {
+            return self.current;
+        }
+        if population < self.min_gpu_population {
+            return CollisionDetectionMethod::Cpu;
+        }
+        if population >= self.max_cpu_population {
+            return CollisionDetectionMethod::Gpu;
+        }
+        let (current, other, other_method) = match self.current {
+            CollisionDetectionMethod::Gpu => (
+                self.gpu_timing,
+                self.cpu_timing,
+                CollisionDetectionMethod::Cpu,
+            ),
+            _ => (
+                self.cpu_timing,
+                self.gpu_timing,
+                CollisionDetectionMethod::Gpu,
+            ),
+        };
+        match (current, other) {
+            (_, None) => other_method,
+            (Some(current), Some(other))
+                if other.estimate_at(population)
+                    < current.estimate_at(population) * self.switch_ratio =>
+            {
+                other_method
+            }
+            _ => self.current,
+        }
+    }
+}
+
+/// Adds the `AutoMethodSelection` and the systems that update it while the `CollisionDetectionMethod` is `Auto`. Added by `CollisionDetectionPlugin`, whose `CpuDetectionSet` and `GpuDetectionSet` run conditions read it
+pub struct AutoMethodSelectionPlugin;
+
+impl Plugin for AutoMethodSelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AutoMethodSelection>()
+            .init_resource::<DetectionStart>()
+            .add_systems(
+                Update,
+                (
+                    start_detection_timer
+                        .before(CpuDetectionSet)
+                        .before(GpuDetectionSet),
+                    select_method.after(CpuDetectionSet).after(GpuDetectionSet),
+                )
+                    .run_if(resource_equals(CollisionDetectionMethod::Auto)),
+            );
+    }
+}
+
+#[derive(Debug, Default, Resource)]
+struct DetectionStart(Option<Instant>);
+
+fn start_detection_timer(mut start: ResMut<DetectionStart>) {
+    start.0 = Some(Instant::now());
+}
+
+/// Records how long the detection took, keeps `CollidablePopulation` up to date whichever method ran, and switches methods for the next frame
+fn select_method(
+    start: Res<DetectionStart>,
+    collidables: Query<(), WithCpuCollider>,
+    mut population: ResMut<CollidablePopulation>,
+    mut selection: ResMut<AutoMethodSelection>,
+) {
+    population.0 = collidables.iter().len();
+    if let Some(start) = start.0 {
+        selection.record(start.elapsed(), population.0);
+    }
+    let next = selection.choose(population.0);
+    if next != selection.current {
+        log::info!(
+            "Switching collision detection from {:?} to {:?} at {} collidables",
+            selection.current,
+            next,
+            population.0
+        );
+        selection.current = next;
+        selection.frames_since_switch = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::bounding::BoundingCircle;
+
+    use super::*;
+    use crate::{
+        colliding_pair::CollidingPairs, collision_detection_plugin::CollisionDetectionPlugin,
+        components_and_resources::BoundingCircleComponent,
+        gpu_collision_detection::resources::AllCollidablesThisFrame,
+    };
+
+    fn timing(seconds: f32, population: usize) -> Option<MethodTiming> {
+        Some(MethodTiming {
+            seconds,
+            population,
+        })
+    }
+
+    #[test]
+    fn test_choice_with_hysteresis() {
+        let mut selection = AutoMethodSelection {
+            min_frames_between_switches: 2,
+            ..Default::default()
+        };
+        let is_gpu = |method| matches!(method, CollisionDetectionMethod::Gpu);
+        assert!(!is_gpu(selection.choose(10)));
+        assert!(is_gpu(selection.choose(50_000)));
+        // not long enough since the last switch, even past a bound
+        selection.frames_since_switch = 0;
+        assert!(!is_gpu(selection.choose(50_000)));
+        assert!(!is_gpu(selection.choose(5_000)));
+        selection.record(Duration::from_millis(10), 5_000);
+        selection.record(Duration::from_millis(10), 5_000);
+        // never measured, so it is tried
+        assert!(is_gpu(selection.choose(5_000)));
+        // only a little faster is not enough
+        selection.gpu_timing = timing(0.009, 5_000);
+        assert!(!is_gpu(selection.choose(5_000)));
+        // neither is being faster at a smaller population
+        selection.gpu_timing = timing(0.002, 1_000);
+        assert!(!is_gpu(selection.choose(5_000)));
+        selection.gpu_timing = timing(0.005, 5_000);
+        assert!(is_gpu(selection.choose(5_000)));
+        // smoothed towards this frame's time
+        selection.record(Duration::from_millis(20), 10_000);
+        let cpu = selection.cpu_timing.unwrap();
+        assert_eq!(cpu.population, 10_000);
+        assert!((cpu.seconds - (0.04 * 0.8 + 0.02 * 0.2)).abs() < 1e-6);
+    }
+
+    fn spawn_row(app: &mut App, count: usize) {
+        let start = app
+            .world_mut()
+            .query::<&Transform>()
+            .iter(app.world())
+            .len();
+        for i in start..start + count {
+            let center = Vec2::new(i as f32 * 1.5, 0.);
+            app.world_mut().spawn((
+                Transform::from_translation(center.extend(0.)),
+                BoundingCircleComponent(BoundingCircle::new(center, 1.)),
+            ));
+        }
+    }
+
+    #[test]
+    fn test_switches_with_the_population() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            CollisionDetectionPlugin::builder()
+                .method(CollisionDetectionMethod::Auto)
+                .build(),
+        ))
+        .insert_resource(AutoMethodSelection {
+            min_gpu_population: 50,
+            max_cpu_population: 100,
+            min_frames_between_switches: 1,
+            ..Default::default()
+        });
+        let ran_on_gpu = |app: &App| {
+            !app.world()
+                .resource::<AllCollidablesThisFrame>()
+                .0
+                .is_empty()
+        };
+        spawn_row(&mut app, 20);
+        app.update();
+        // every circle touches the next one in the row
+        assert_eq!(app.world().resource::<CollidingPairs>().0.len(), 19);
+        assert!(!ran_on_gpu(&app));
+        assert!(
+            app.world()
+                .resource::<AutoMethodSelection>()
+                .cpu_timing
+                .is_some()
+        );
+
+        spawn_row(&mut app, 130);
+        app.update();
+        assert!(matches!(
+            app.world().resource::<AutoMethodSelection>().current,
+            CollisionDetectionMethod::Gpu
+        ));
+        assert_eq!(app.world().resource::<CollidablePopulation>().0, 150);
+        app.update();
+        assert!(ran_on_gpu(&app));
+        assert_eq!(app.world().resource::<CollidingPairs>().0.len(), 149);
+
+        let mut query = app.world_mut().query_filtered::<Entity, With<Transform>>();
+        let entities: Vec<Entity> = query.iter(app.world()).skip(10).collect();
+        for entity in entities {
+            app.world_mut().despawn(entity);
+        }
+        app.update();
+        app.update();
+        assert!(matches!(
+            app.world().resource::<AutoMethodSelection>().current,
+            CollisionDetectionMethod::Cpu
+        ));
+        assert_eq!(app.world().resource::<CollidingPairs>().0.len(), 9);
+    }
+}
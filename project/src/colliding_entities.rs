@@ -0,0 +1,141 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use bevy::prelude::*;
+
+use crate::{
+    colliding_pair::CollidingPairs,
+    components_and_resources::{
+        BoundingBox3dComponent, BoundingBoxComponent, BoundingCircleComponent,
+        BoundingSphereComponent, CapsuleComponent, ConvexPolygonComponent, Sensor,
+    },
+};
+
+/// Every entity this one is colliding with this frame, in no particular order. Only on the collidables picked by `TrackCollidingEntities`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Component)]
+pub struct CollidingEntities(pub Vec<Entity>);
+
+/// Which collidables get a `CollidingEntities` component. Set by the detection plugins, and can be changed at runtime, but `Off` leaves the components where they are instead of removing them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Resource)]
+pub enum TrackCollidingEntities {
+    #[default]
+    Off,
+    /// Only entities with a `Sensor`, which is all `process_collisions` needs
+    Sensors,
+    All,
+}
+
+impl TrackCollidingEntities {
+    fn tracks(&self, is_sensor: bool) -> bool {
+        match self {
+            TrackCollidingEntities::Off => false,
+            TrackCollidingEntities::Sensors => is_sensor,
+            TrackCollidingEntities::All => true,
+        }
+    }
+}
+
+type WithCollider = Or<(
+    With<BoundingCircleComponent>,
+    With<BoundingBoxComponent>,
+    With<CapsuleComponent>,
+    With<ConvexPolygonComponent>,
+    With<BoundingSphereComponent>,
+    With<BoundingBox3dComponent>,
+)>;
+
+/// Components are only written when their entities changed, so `Changed<CollidingEntities>` means a contact started or ended
+pub fn update_colliding_entities(
+    mut commands: Commands,
+    collisions: Res<CollidingPairs>,
+    tracking: Res<TrackCollidingEntities>,
+    mut tracked: Query<(Entity, &mut CollidingEntities, Has<Sensor>)>,
+    untracked: Query<(Entity, Has<Sensor>), (WithCollider, Without<CollidingEntities>)>,
+) {
+    let mut colliding: HashMap<Entity, Vec<Entity>> = HashMap::new();
+    for collision in collisions.0.iter() {
+        let m = &collision.metadata1;
+        let m2 = &collision.metadata2;
+        if tracking.tracks(m.is_sensor) {
+            colliding.entry(m.entity).or_default().push(m2.entity);
+        }
+        if tracking.tracks(m2.is_sensor) {
+            colliding.entry(m2.entity).or_default().push(m.entity);
+        }
+    }
+    // the collidables that are no longer picked, since tracking changed or a sensor stopped being one
+    let untracked_now = Mutex::new(Vec::new());
+    tracked
+        .par_iter_mut()
+        .for_each(|(entity, mut colliding_entities, is_sensor)| {
+            if !tracking.tracks(is_sensor) {
+                untracked_now.lock().unwrap().push(entity);
+                return;
+            }
+            let current = colliding.get(&entity).map_or(&[][..], Vec::as_slice);
+            if colliding_entities.0 != current {
+                colliding_entities.0.clear();
+                colliding_entities.0.extend_from_slice(current);
+            }
+        });
+    for entity in untracked_now.into_inner().unwrap() {
+        commands.entity(entity).remove::<CollidingEntities>();
+    }
+    for (entity, is_sensor) in untracked.iter() {
+        if tracking.tracks(is_sensor) {
+            commands.entity(entity).insert(CollidingEntities(
+                colliding.remove(&entity).unwrap_or_default(),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::bounding::BoundingCircle;
+
+    use super::*;
+    use crate::cpu_collision_detection::cpu_collision_detection::CpuCollisionDetectionPlugin;
+
+    #[test]
+    fn test_colliding_entities_follow_the_pairs() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            CpuCollisionDetectionPlugin::default()
+                .with_colliding_entities(TrackCollidingEntities::Sensors),
+        ))
+        .insert_resource(CollidingPairs(Vec::new()));
+        let circle = |x: f32| {
+            (
+                Transform::from_xyz(x, 0., 0.),
+                BoundingCircleComponent(BoundingCircle::new(Vec2::new(x, 0.), 1.)),
+            )
+        };
+        let sensor = app.world_mut().spawn((circle(0.), Sensor {})).id();
+        let body = app.world_mut().spawn(circle(1.)).id();
+        let other_body = app.world_mut().spawn(circle(20.)).id();
+        let colliding_entities = |app: &App, entity: Entity| {
+            app.world()
+                .get::<CollidingEntities>(entity)
+                .map(|colliding| colliding.0.clone())
+        };
+        app.update();
+        assert_eq!(colliding_entities(&app, sensor), Some(vec![body]));
+        assert_eq!(colliding_entities(&app, body), None);
+
+        app.world_mut().entity_mut(other_body).insert(circle(-1.));
+        app.world_mut().entity_mut(body).insert(circle(20.));
+        app.update();
+        assert_eq!(colliding_entities(&app, sensor), Some(vec![other_body]));
+
+        *app.world_mut().resource_mut::<TrackCollidingEntities>() = TrackCollidingEntities::All;
+        app.update();
+        assert_eq!(colliding_entities(&app, other_body), Some(vec![sensor]));
+        assert_eq!(colliding_entities(&app, body), Some(vec![]));
+
+        *app.world_mut().resource_mut::<TrackCollidingEntities>() = TrackCollidingEntities::Sensors;
+        app.update();
+        assert_eq!(colliding_entities(&app, body), None);
+        assert_eq!(colliding_entities(&app, sensor), Some(vec![other_body]));
+    }
+}
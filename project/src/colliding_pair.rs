@@ -1,4 +1,7 @@
-use bevy::prelude::Resource;
+use bevy::{
+    math::Vec3,
+    prelude::{Entity, Resource},
+};
 
 use super::gpu_collision_detection::entity_metadata::CollidableMetadata;
 
@@ -6,6 +9,79 @@ use super::gpu_collision_detection::entity_metadata::CollidableMetadata;
 pub struct CollidingPair {
     pub metadata1: CollidableMetadata,
     pub metadata2: CollidableMetadata,
+    /// Only computed while `ComputeContacts` is on
+    pub contact: Option<Contact>,
+    /// Only for pairs of circles swept by `ContinuousCollisionDetection`: how far through the frame's movement they first overlapped, from 0 to 1
+    pub time_of_impact: Option<f32>,
 }
-#[derive(Debug, Resource)]
+
+impl CollidingPair {
+    pub fn entity_pair(&self) -> EntityPair {
+        EntityPair::new(self.metadata1.entity, self.metadata2.entity)
+    }
+
+    /// Swaps the collidables if needed so the lower entity is `metadata1`, flipping the contact normal with them
+    pub fn canonicalize(&mut self) {
+        if self.metadata1.entity > self.metadata2.entity {
+            std::mem::swap(&mut self.metadata1, &mut self.metadata2);
+            if let Some(contact) = &mut self.contact {
+                contact.normal = -contact.normal;
+            }
+        }
+    }
+}
+
+/// Two entities in a fixed order, so a pair is the same key whichever of its collidables came first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EntityPair(Entity, Entity);
+
+impl EntityPair {
+    pub fn new(a: Entity, b: Entity) -> Self {
+        if a <= b {
+            EntityPair(a, b)
+        } else {
+            EntityPair(b, a)
+        }
+    }
+
+    pub fn first(&self) -> Entity {
+        self.0
+    }
+
+    pub fn second(&self) -> Entity {
+        self.1
+    }
+
+    pub fn entities(&self) -> [Entity; 2] {
+        [self.0, self.1]
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.0 == entity || self.1 == entity
+    }
+
+    /// The entity paired with `entity`, if it is in the pair
+    pub fn other(&self, entity: Entity) -> Option<Entity> {
+        if self.0 == entity {
+            Some(self.1)
+        } else if self.1 == entity {
+            Some(self.0)
+        } else {
+            None
+        }
+    }
+}
+
+/// How a colliding pair overlaps, enough to push the two apart. In 2D every z is 0
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Contact {
+    /// Unit vector pointing from the first collidable towards the second
+    pub normal: Vec3,
+    /// How far the second collidable has to move along `normal` for the pair to only touch. 0 when they only touch
+    pub depth: f32,
+    /// Halfway between the two surfaces along the normal
+    pub point: Vec3,
+}
+
+#[derive(Debug, Default, Resource)]
 pub struct CollidingPairs(pub Vec<CollidingPair>);
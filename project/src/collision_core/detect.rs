@@ -0,0 +1,284 @@
+use bevy::math::{Vec2, bounding::BoundingCircle};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+
+use crate::{
+    colliding_pair::Contact,
+    components_and_resources::CollisionLayers,
+    cpu_collision_detection::collider::{Collider, swept_circles_time_of_impact},
+};
+
+/// One collidable for the functions that work on plain slices. Pairs refer to it by its index in the slice
+#[derive(Debug, Clone)]
+pub struct Collidable {
+    pub collider: Collider,
+    pub layers: CollisionLayers,
+    /// Where the collider's center was at the start of the movement, for continuous detection. `None` when it didn't move
+    pub previous_center: Option<Vec2>,
+}
+
+impl Collidable {
+    pub fn new(collider: Collider) -> Self {
+        Collidable {
+            collider,
+            layers: CollisionLayers::ALL,
+            previous_center: None,
+        }
+    }
+
+    pub fn with_layers(mut self, layers: CollisionLayers) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    pub fn with_previous_center(mut self, previous_center: Vec2) -> Self {
+        self.previous_center = Some(previous_center);
+        self
+    }
+
+    pub fn previous_center(&self) -> Vec2 {
+        self.previous_center
+            .unwrap_or_else(|| self.collider.center())
+    }
+
+    /// Around everything the narrow phase can test against, which with `continuous` is the whole sweep of a circle
+    pub fn broadphase_circle(&self, continuous: bool) -> BoundingCircle {
+        let bounding_circle = self.collider.bounding_circle();
+        match self.collider {
+            Collider::Circle(_) if continuous => {
+                let previous = self.previous_center();
+                BoundingCircle::new(
+                    (previous + bounding_circle.center) / 2.,
+                    bounding_circle.radius() + previous.distance(bounding_circle.center) / 2.,
+                )
+            }
+            _ => bounding_circle,
+        }
+    }
+}
+
+/// The same switches as `ComputeContacts` and `ContinuousCollisionDetection`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DetectOptions {
+    pub contacts: bool,
+    /// Sweep pairs of circles from their `previous_center`
+    pub continuous: bool,
+}
+
+/// A colliding pair of collidables, by their index in the slice. `a` is always lower than `b`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DetectedPair {
+    pub a: u32,
+    pub b: u32,
+    /// Only computed with `DetectOptions::contacts`, pointing from `a` towards `b`
+    pub contact: Option<Contact>,
+    /// See `CollidingPair::time_of_impact`
+    pub time_of_impact: Option<f32>,
+}
+
+/// Every colliding pair of `collidables` by index, in no particular order. Runs in parallel with rayon
+pub fn detect(collidables: &[Collidable], options: &DetectOptions) -> Vec<(u32, u32)> {
+    detect_pairs(collidables, options)
+        .iter()
+        .map(|pair| (pair.a, pair.b))
+        .collect()
+}
+
+/// `detect`, with the contacts and times of impact `options` asked for
+pub fn detect_pairs(collidables: &[Collidable], options: &DetectOptions) -> Vec<DetectedPair> {
+    let mut pairs = Vec::new();
+    detect_pairs_into(collidables, options, &mut JobBuffers::default(), &mut pairs);
+    pairs
+}
+
+/// Below this, handing the pairs to rayon's threads takes longer than testing them on this one
+const MIN_COLLIDABLES_IN_PARALLEL: usize = 64;
+
+/// One buffer per rayon job of `detect_pairs_into`, each only written by its own job. Kept between calls so they stop allocating once they are big enough
+#[derive(Debug, Default)]
+pub struct JobBuffers(Vec<Vec<DetectedPair>>);
+
+/// `detect_pairs` into `pairs`, which is cleared first. Passing the same `buffers` and `pairs` every frame reuses their allocations.
+///
+/// Job `j` of `n` tests rows `j`, `j + n`, `j + 2n`... of the triangle of pairs, so every job has about as much work, and the buffers are copied into `pairs` once at the end, without any locking
+pub fn detect_pairs_into(
+    collidables: &[Collidable],
+    options: &DetectOptions,
+    buffers: &mut JobBuffers,
+    pairs: &mut Vec<DetectedPair>,
+) {
+    // a few jobs per thread, so threads that finish early can steal the rest
+    let jobs = match collidables.len() < MIN_COLLIDABLES_IN_PARALLEL {
+        true => 1,
+        false => rayon::current_num_threads() * 4,
+    };
+    buffers.0.resize_with(jobs, Vec::new);
+    let run_job = |(job, found): (usize, &mut Vec<DetectedPair>)| {
+        found.clear();
+        for i in (job..collidables.len()).step_by(jobs) {
+            // Only check against collidables with higher indices
+            found.extend(
+                (i + 1..collidables.len())
+                    .filter_map(|k| test_pair(i, &collidables[i], k, &collidables[k], options)),
+            );
+        }
+    };
+    match jobs {
+        1 => buffers.0.iter_mut().enumerate().for_each(run_job),
+        _ => buffers.0.par_iter_mut().enumerate().for_each(run_job),
+    }
+    pairs.clear();
+    pairs.reserve(buffers.0.iter().map(Vec::len).sum());
+    for found in &buffers.0 {
+        pairs.extend_from_slice(found);
+    }
+}
+
+/// The `Arc<Mutex<Vec>>` collection the CPU plugin used before `detect_pairs_into`, only built with the `bench` feature for `benches/cpu_detection.rs` to compare against
+#[cfg(feature = "bench")]
+pub fn detect_pairs_with_mutexes(
+    collidables: &[Collidable],
+    options: &DetectOptions,
+) -> Vec<DetectedPair> {
+    use rayon::slice::ParallelSlice;
+    use std::{
+        cmp::max,
+        sync::{Arc, Mutex},
+    };
+
+    let collisions_shared: Arc<Mutex<Vec<DetectedPair>>> = Arc::new(Mutex::new(Vec::new()));
+    let indexed: Vec<_> = collidables.iter().enumerate().collect();
+    let num_threads = rayon::current_num_threads();
+    let batch_size = max(indexed.len() / num_threads, 1);
+    indexed.par_chunks(batch_size).for_each(|chunk| {
+        chunk.iter().for_each(|(i, collidable)| {
+            let collisions_inner: Arc<Mutex<Vec<DetectedPair>>> = Arc::new(Mutex::new(Vec::new()));
+            indexed[i + 1..]
+                .par_chunks(batch_size)
+                .for_each(|other_chunk| {
+                    other_chunk.iter().for_each(|(k, other)| {
+                        if let Some(pair) = test_pair(*i, collidable, *k, other, options) {
+                            collisions_inner.lock().unwrap().push(pair);
+                        }
+                    })
+                });
+            let c =
+                Arc::try_unwrap(collisions_inner).unwrap_or_else(|_| panic!("Arc unwrap failed"));
+            collisions_shared
+                .lock()
+                .unwrap()
+                .append(&mut c.lock().unwrap());
+        });
+    });
+    Arc::try_unwrap(collisions_shared)
+        .unwrap_or_else(|_| panic!("Arc unwrap failed"))
+        .into_inner()
+        .unwrap()
+}
+
+/// The narrow phase of every CPU broadphase. `i` must be lower than `k`, so the contact is always from the lower index to the higher one
+pub(crate) fn test_pair(
+    i: usize,
+    collidable: &Collidable,
+    k: usize,
+    other: &Collidable,
+    options: &DetectOptions,
+) -> Option<DetectedPair> {
+    if !collidable.layers.interacts_with(&other.layers) {
+        return None;
+    }
+    // circles are only swept, the same as in the shader
+    let time_of_impact = match (&collidable.collider, &other.collider) {
+        (Collider::Circle(a), Collider::Circle(b)) if options.continuous => {
+            Some(swept_circles_time_of_impact(
+                collidable.previous_center(),
+                a.center,
+                a.radius(),
+                other.previous_center(),
+                b.center,
+                b.radius(),
+            ))
+        }
+        _ => None,
+    };
+    let hit = match time_of_impact {
+        Some(time_of_impact) => time_of_impact.is_some(),
+        None => collidable.collider.intersects(&other.collider),
+    };
+    hit.then(|| DetectedPair {
+        a: i as u32,
+        b: k as u32,
+        contact: options.contacts.then(|| match time_of_impact.flatten() {
+            Some(time_of_impact) => swept_contact(collidable, other, time_of_impact),
+            None => collidable.collider.contact(&other.collider),
+        }),
+        time_of_impact: time_of_impact.flatten(),
+    })
+}
+
+/// The contact of two swept circles where they first touch, instead of where they ended up, which can be apart. Float error can leave them a hair apart at the time of impact too, so the depth is at least 0. Must match the shader
+fn swept_contact(collidable: &Collidable, other: &Collidable, time_of_impact: f32) -> Contact {
+    let at_impact = |collidable: &Collidable| {
+        let center = collidable
+            .previous_center()
+            .lerp(collidable.collider.center(), time_of_impact);
+        Collider::Circle(BoundingCircle::new(
+            center,
+            collidable.collider.bounding_circle().radius(),
+        ))
+    };
+    let mut contact = at_impact(collidable).contact(&at_impact(other));
+    contact.depth = contact.depth.max(0.);
+    contact
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{Vec3, bounding::BoundingCircle};
+
+    use super::*;
+
+    #[test]
+    fn test_reused_buffers_only_hold_this_call() {
+        let row = |count: usize| -> Vec<Collidable> {
+            (0..count)
+                .map(|i| {
+                    let center = Vec2::new(i as f32 * 1.5, 0.);
+                    Collidable::new(Collider::Circle(BoundingCircle::new(center, 1.)))
+                })
+                .collect()
+        };
+        let (mut buffers, mut pairs) = (JobBuffers::default(), Vec::new());
+        for count in [200, 3, 0, 50] {
+            detect_pairs_into(
+                &row(count),
+                &DetectOptions::default(),
+                &mut buffers,
+                &mut pairs,
+            );
+            let mut found: Vec<_> = pairs.iter().map(|pair| (pair.a, pair.b)).collect();
+            found.sort();
+            let expected: Vec<_> = (1..count as u32).map(|b| (b - 1, b)).collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_swept_contacts_are_where_the_circles_first_touch() {
+        let circle = |center: Vec2| Collider::Circle(BoundingCircle::new(center, 1.));
+        let collidables = [
+            Collidable::new(circle(Vec2::ZERO)),
+            // passes straight through the first circle, and ends up 8 apart from it
+            Collidable::new(circle(Vec2::new(10., 0.))).with_previous_center(Vec2::new(-10., 0.)),
+        ];
+        let options = DetectOptions {
+            contacts: true,
+            continuous: true,
+        };
+        let pair = test_pair(0, &collidables[0], 1, &collidables[1], &options).unwrap();
+        assert!((pair.time_of_impact.unwrap() - 0.4).abs() < 1e-5);
+        let contact = pair.contact.unwrap();
+        assert!(contact.depth >= 0. && contact.depth < 1e-4);
+        assert!(contact.normal.abs_diff_eq(Vec3::NEG_X, 1e-5));
+        assert!(contact.point.abs_diff_eq(Vec3::new(-1., 0., 0.), 1e-4));
+    }
+}
@@ -0,0 +1,330 @@
+use bevy::math::bounding::{Aabb2d, BoundingVolume, IntersectsVolume};
+
+const NULL: u32 = u32::MAX;
+
+/// How much bigger than the collidable a leaf is, as a fraction of its half size. A leaf only has to be moved in the tree once the collidable leaves it
+const FAT_MARGIN: f32 = 0.25;
+
+/// Handle to a leaf of a `DynamicTree`, stable until it is removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Proxy(u32);
+
+#[derive(Debug, Clone)]
+struct Node<T> {
+    aabb: Aabb2d,
+    parent: u32,
+    children: [u32; 2],
+    /// 0 for leaves
+    height: u32,
+    /// Only on leaves
+    data: Option<T>,
+}
+
+impl<T> Node<T> {
+    fn is_leaf(&self) -> bool {
+        self.children[0] == NULL
+    }
+}
+
+/// Bounding volume hierarchy of fat AABBs, kept balanced with tree rotations as leaves are inserted, moved and removed, like Box2D's `b2DynamicTree`.
+///
+/// Leaves are only reinserted when their collidable leaves its fat AABB, so collidables that move a little every frame cost almost nothing to keep up to date.
+#[derive(Debug, Clone)]
+pub struct DynamicTree<T> {
+    nodes: Vec<Node<T>>,
+    root: u32,
+    free: Vec<u32>,
+}
+
+impl<T> Default for DynamicTree<T> {
+    fn default() -> Self {
+        DynamicTree {
+            nodes: Vec::new(),
+            root: NULL,
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<T: Copy> DynamicTree<T> {
+    pub fn insert(&mut self, aabb: Aabb2d, data: T) -> Proxy {
+        let leaf = self.allocate(Node {
+            aabb: fatten(aabb),
+            parent: NULL,
+            children: [NULL, NULL],
+            height: 0,
+            data: Some(data),
+        });
+        self.insert_leaf(leaf);
+        Proxy(leaf)
+    }
+
+    pub fn remove(&mut self, proxy: Proxy) -> T {
+        self.remove_leaf(proxy.0);
+        let data = self.nodes[proxy.0 as usize].data.take();
+        self.free.push(proxy.0);
+        data.expect("proxy is not a leaf")
+    }
+
+    /// Moves the leaf if `aabb` is no longer inside its fat AABB. Returns whether it moved
+    pub fn update(&mut self, proxy: Proxy, aabb: Aabb2d) -> bool {
+        if self.nodes[proxy.0 as usize].aabb.contains(&aabb) {
+            return false;
+        }
+        self.remove_leaf(proxy.0);
+        self.nodes[proxy.0 as usize].aabb = fatten(aabb);
+        self.insert_leaf(proxy.0);
+        true
+    }
+
+    /// The data of every leaf whose fat AABB overlaps `aabb`
+    pub fn query(&self, aabb: Aabb2d, callback: impl FnMut(T)) {
+        self.query_with_stack(aabb, &mut Vec::new(), callback);
+    }
+
+    /// `query` keeping the nodes left to visit in `stack`, so many queries can share one allocation
+    pub fn query_with_stack(
+        &self,
+        aabb: Aabb2d,
+        stack: &mut Vec<u32>,
+        mut callback: impl FnMut(T),
+    ) {
+        stack.clear();
+        stack.push(self.root);
+        while let Some(index) = stack.pop() {
+            if index == NULL {
+                continue;
+            }
+            let node = &self.nodes[index as usize];
+            if !node.aabb.intersects(&aabb) {
+                continue;
+            }
+            match node.data {
+                Some(data) => callback(data),
+                None => stack.extend(node.children),
+            }
+        }
+    }
+
+    /// 0 for an empty tree or a single leaf
+    pub fn height(&self) -> u32 {
+        match self.root {
+            NULL => 0,
+            root => self.nodes[root as usize].height,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = DynamicTree::default();
+    }
+
+    fn allocate(&mut self, node: Node<T>) -> u32 {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index as usize] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() as u32 - 1
+            }
+        }
+    }
+
+    fn insert_leaf(&mut self, leaf: u32) {
+        if self.root == NULL {
+            self.root = leaf;
+            self.nodes[leaf as usize].parent = NULL;
+            return;
+        }
+        // walk down to the sibling that grows the tree's total perimeter the least
+        let leaf_aabb = self.nodes[leaf as usize].aabb;
+        let mut index = self.root;
+        while !self.nodes[index as usize].is_leaf() {
+            let node = &self.nodes[index as usize];
+            let area = perimeter(&node.aabb);
+            let combined = perimeter(&node.aabb.merge(&leaf_aabb));
+            // pairing with this node makes a new parent of the combined size
+            let cost = 2. * combined;
+            // and going further down grows this node and every ancestor of it
+            let inheritance = 2. * (combined - area);
+            let child_cost = |child: u32| {
+                let child = &self.nodes[child as usize];
+                let merged = perimeter(&child.aabb.merge(&leaf_aabb));
+                match child.is_leaf() {
+                    true => merged + inheritance,
+                    false => merged - perimeter(&child.aabb) + inheritance,
+                }
+            };
+            let [child1, child2] = node.children;
+            let (cost1, cost2) = (child_cost(child1), child_cost(child2));
+            if cost < cost1 && cost < cost2 {
+                break;
+            }
+            index = if cost1 < cost2 { child1 } else { child2 };
+        }
+        let sibling = index;
+        let old_parent = self.nodes[sibling as usize].parent;
+        let new_parent = self.allocate(Node {
+            aabb: self.nodes[sibling as usize].aabb.merge(&leaf_aabb),
+            parent: old_parent,
+            children: [sibling, leaf],
+            height: self.nodes[sibling as usize].height + 1,
+            data: None,
+        });
+        if old_parent == NULL {
+            self.root = new_parent;
+        } else {
+            self.replace_child(old_parent, sibling, new_parent);
+        }
+        self.nodes[sibling as usize].parent = new_parent;
+        self.nodes[leaf as usize].parent = new_parent;
+        self.refit_from(new_parent);
+    }
+
+    fn remove_leaf(&mut self, leaf: u32) {
+        if leaf == self.root {
+            self.root = NULL;
+            return;
+        }
+        let parent = self.nodes[leaf as usize].parent;
+        let grandparent = self.nodes[parent as usize].parent;
+        let [child1, child2] = self.nodes[parent as usize].children;
+        let sibling = if child1 == leaf { child2 } else { child1 };
+        self.nodes[sibling as usize].parent = grandparent;
+        self.free.push(parent);
+        if grandparent == NULL {
+            self.root = sibling;
+        } else {
+            self.replace_child(grandparent, parent, sibling);
+            self.refit_from(grandparent);
+        }
+    }
+
+    fn replace_child(&mut self, parent: u32, old: u32, new: u32) {
+        let children = &mut self.nodes[parent as usize].children;
+        if children[0] == old {
+            children[0] = new;
+        } else {
+            children[1] = new;
+        }
+    }
+
+    /// Balances and refits every ancestor, starting at `index`
+    fn refit_from(&mut self, mut index: u32) {
+        while index != NULL {
+            index = self.balance(index);
+            let [child1, child2] = self.nodes[index as usize].children;
+            let (a, b) = (&self.nodes[child1 as usize], &self.nodes[child2 as usize]);
+            let (aabb, height) = (a.aabb.merge(&b.aabb), 1 + a.height.max(b.height));
+            let node = &mut self.nodes[index as usize];
+            node.aabb = aabb;
+            node.height = height;
+            index = node.parent;
+        }
+    }
+
+    /// If one child of `a` is more than one level taller than the other, rotates the taller one up in place of `a`. Returns the node now where `a` was
+    fn balance(&mut self, a: u32) -> u32 {
+        let node_a = &self.nodes[a as usize];
+        if node_a.is_leaf() || node_a.height < 2 {
+            return a;
+        }
+        let [b, c] = node_a.children;
+        let height_b = self.nodes[b as usize].height;
+        let height_c = self.nodes[c as usize].height;
+        if height_c > height_b + 1 {
+            self.rotate_up(a, c, b);
+            c
+        } else if height_b > height_c + 1 {
+            self.rotate_up(a, b, c);
+            b
+        } else {
+            a
+        }
+    }
+
+    /// Makes `up`, the taller child of `a`, the parent of `a`. `a` keeps `other` and takes the shorter child of `up`
+    fn rotate_up(&mut self, a: u32, up: u32, other: u32) {
+        let [f, g] = self.nodes[up as usize].children;
+        let parent = self.nodes[a as usize].parent;
+        self.nodes[up as usize].parent = parent;
+        self.nodes[a as usize].parent = up;
+        if parent == NULL {
+            self.root = up;
+        } else {
+            self.replace_child(parent, a, up);
+        }
+        let (keep, give) = if self.nodes[f as usize].height > self.nodes[g as usize].height {
+            (f, g)
+        } else {
+            (g, f)
+        };
+        self.nodes[up as usize].children = [a, keep];
+        self.nodes[a as usize].children = [other, give];
+        self.nodes[give as usize].parent = a;
+        for index in [a, up] {
+            let [child1, child2] = self.nodes[index as usize].children;
+            let (x, y) = (&self.nodes[child1 as usize], &self.nodes[child2 as usize]);
+            let (aabb, height) = (x.aabb.merge(&y.aabb), 1 + x.height.max(y.height));
+            let node = &mut self.nodes[index as usize];
+            node.aabb = aabb;
+            node.height = height;
+        }
+    }
+}
+
+fn fatten(aabb: Aabb2d) -> Aabb2d {
+    aabb.grow(aabb.half_size() * FAT_MARGIN)
+}
+
+fn perimeter(aabb: &Aabb2d) -> f32 {
+    let size = aabb.max - aabb.min;
+    2. * (size.x + size.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::Vec2;
+
+    use super::*;
+
+    fn square(x: f32, y: f32, half_size: f32) -> Aabb2d {
+        Aabb2d::new(Vec2::new(x, y), Vec2::splat(half_size))
+    }
+
+    fn overlapping(tree: &DynamicTree<u32>, aabb: Aabb2d) -> Vec<u32> {
+        let mut found = Vec::new();
+        tree.query(aabb, |data| found.push(data));
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn test_tree_stays_balanced_and_finds_overlaps() {
+        let mut tree = DynamicTree::default();
+        // a row of squares is the worst case for an unbalanced tree
+        let mut proxies: Vec<Proxy> = (0..1000)
+            .map(|i| tree.insert(square(i as f32 * 3., 0., 1.), i))
+            .collect();
+        assert!(tree.height() <= 20, "height {}", tree.height());
+        assert_eq!(overlapping(&tree, square(30., 0., 2.)), vec![9, 10, 11]);
+
+        // small moves stay inside the fat AABB
+        assert!(!tree.update(proxies[10], square(30.1, 0., 1.)));
+        assert!(tree.update(proxies[10], square(3000., 0., 1.)));
+        assert_eq!(overlapping(&tree, square(30., 0., 2.)), vec![9, 11]);
+        assert_eq!(overlapping(&tree, square(3000., 0., 0.5)), vec![10]);
+
+        for i in (0..1000).step_by(2) {
+            assert_eq!(tree.remove(proxies[i]), i as u32);
+        }
+        assert_eq!(overlapping(&tree, square(30., 0., 2.)), vec![9, 11]);
+        // freed nodes are reused
+        proxies[0] = tree.insert(square(30., 0., 1.), 5000);
+        assert_eq!(overlapping(&tree, square(30., 0., 2.)), vec![9, 11, 5000]);
+        assert!(tree.height() <= 20, "height {}", tree.height());
+        tree.clear();
+        assert!(overlapping(&tree, square(30., 0., 2.)).is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+use bevy::{
+    log,
+    math::{Vec2, bounding::BoundingCircle},
+};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use super::detect::{Collidable, DetectOptions, DetectedPair, test_pair};
+
+/// Same as the GPU `SpatialHashGrid`, the cell size is grown until there are at most this many cells per collidable
+const MAX_CELLS_PER_COLLIDABLE: u64 = 4;
+
+/// Uniform grid over the broadphase circles, with the cell size at least the largest diameter, so two colliding collidables are never more than one cell apart.
+///
+/// Unlike `SpatialHashGrid` the collidables are left where they are, and `sorted` holds their indices by cell.
+struct Grid {
+    min: Vec2,
+    cell_size: f32,
+    cells_x: u64,
+    cells_y: u64,
+    /// Indices of the collidables sorted by cell
+    sorted: Vec<u32>,
+    /// Prefix sum of the per-cell counts, has one more entry than there are cells
+    cell_starts: Vec<u32>,
+}
+
+impl Grid {
+    /// Circles whose center is not finite are left out of every cell
+    fn build(circles: &[BoundingCircle]) -> Self {
+        let mut min = Vec2::MAX;
+        let mut max = Vec2::MIN;
+        let mut max_radius: f32 = 0.;
+        let mut finite = 0;
+        for circle in circles.iter().filter(|circle| circle.center.is_finite()) {
+            min = min.min(circle.center);
+            max = max.max(circle.center);
+            max_radius = max_radius.max(circle.radius());
+            finite += 1;
+        }
+        if finite < circles.len() {
+            log::warn!(
+                "{} collidables with a non-finite center are skipped by the grid broadphase",
+                circles.len() - finite
+            );
+        }
+        if finite == 0 {
+            (min, max) = (Vec2::ZERO, Vec2::ZERO);
+        }
+        let max_cells = (finite as u64 * MAX_CELLS_PER_COLLIDABLE).max(1);
+        let mut cell_size = if max_radius > 0. { 2. * max_radius } else { 1. };
+        // start with at most `max_cells` cells along each axis, so far apart collidables don't need hundreds of doublings
+        cell_size = cell_size.max((max - min).max_element() / max_cells as f32);
+        let cells_per_axis = |cell_size: f32| {
+            let size = (max - min) / cell_size;
+            (
+                (size.x as u64).saturating_add(1),
+                (size.y as u64).saturating_add(1),
+            )
+        };
+        let (mut cells_x, mut cells_y) = cells_per_axis(cell_size);
+        while cells_x.saturating_mul(cells_y) > max_cells {
+            cell_size *= 2.;
+            (cells_x, cells_y) = cells_per_axis(cell_size);
+        }
+        let mut grid = Grid {
+            min,
+            cell_size,
+            cells_x,
+            cells_y,
+            sorted: vec![0; finite],
+            cell_starts: vec![0; (cells_x * cells_y) as usize + 1],
+        };
+        // counting sort by cell
+        let cells: Vec<Option<usize>> = circles
+            .iter()
+            .map(|circle| circle.center.is_finite().then(|| grid.cell_of(circle)))
+            .collect();
+        for cell in cells.iter().flatten() {
+            grid.cell_starts[cell + 1] += 1;
+        }
+        for i in 1..grid.cell_starts.len() {
+            grid.cell_starts[i] += grid.cell_starts[i - 1];
+        }
+        let mut next_slot = grid.cell_starts.clone();
+        for (i, cell) in cells.iter().enumerate() {
+            let Some(cell) = cell else {
+                continue;
+            };
+            grid.sorted[next_slot[*cell] as usize] = i as u32;
+            next_slot[*cell] += 1;
+        }
+        grid
+    }
+
+    fn cell_coordinates(&self, circle: &BoundingCircle) -> (u64, u64) {
+        let cell = (circle.center - self.min) / self.cell_size;
+        (
+            (cell.x as u64).min(self.cells_x - 1),
+            (cell.y as u64).min(self.cells_y - 1),
+        )
+    }
+
+    fn cell_of(&self, circle: &BoundingCircle) -> usize {
+        let (x, y) = self.cell_coordinates(circle);
+        (y * self.cells_x + x) as usize
+    }
+
+    /// Indices of the collidables in the (up to 9) cells around and including the circle's
+    fn neighbors(&self, circle: &BoundingCircle) -> impl Iterator<Item = u32> + '_ {
+        let (x, y) = self.cell_coordinates(circle);
+        let rows = y.saturating_sub(1)..=(y + 1).min(self.cells_y - 1);
+        rows.flat_map(move |row| {
+            // the 3 cells of a row are contiguous in `sorted`
+            let first = row * self.cells_x + x.saturating_sub(1);
+            let last = row * self.cells_x + (x + 1).min(self.cells_x - 1);
+            self.sorted[self.cell_starts[first as usize] as usize
+                ..self.cell_starts[last as usize + 1] as usize]
+                .iter()
+                .copied()
+        })
+    }
+}
+
+/// `detect_pairs` with a uniform grid broadphase, in parallel with rayon. Finds the same pairs, in a different order
+pub fn detect_pairs_grid(collidables: &[Collidable], options: &DetectOptions) -> Vec<DetectedPair> {
+    let circles: Vec<BoundingCircle> = collidables
+        .iter()
+        .map(|collidable| collidable.broadphase_circle(options.continuous))
+        .collect();
+    let grid = Grid::build(&circles);
+    (0..collidables.len())
+        .into_par_iter()
+        .flat_map_iter(|i| {
+            let grid = &grid;
+            let finite = circles[i].center.is_finite();
+            finite
+                .then(|| grid.neighbors(&circles[i]))
+                .into_iter()
+                .flatten()
+                .map(|k| k as usize)
+                // every pair is in both neighborhoods, and is only tested from its lower index
+                .filter(move |k| *k > i)
+                .filter_map(move |k| test_pair(i, &collidables[i], k, &collidables[k], options))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{bounding::Aabb2d, primitives::Capsule2d};
+
+    use super::*;
+    use crate::{
+        collision_core::detect::detect_pairs, cpu_collision_detection::collider::Collider,
+    };
+
+    #[test]
+    fn test_grid_finds_the_brute_force_pairs() {
+        let mut collidables: Vec<Collidable> = (0..400)
+            .map(|i| {
+                let center = Vec2::new((i * 37 % 150) as f32, (i * 53 % 100) as f32);
+                let collidable = Collidable::new(match i % 3 {
+                    0 => Collider::Circle(BoundingCircle::new(center, 1. + (i % 4) as f32)),
+                    1 => Collider::Box(Aabb2d::new(center, Vec2::new(2., 0.5))),
+                    _ => Collider::Capsule {
+                        center,
+                        capsule: Capsule2d::new(0.5, 2.),
+                    },
+                });
+                // fast circles that only hit anything with continuous detection
+                match i % 30 {
+                    0 => collidable.with_previous_center(center + Vec2::new(-150., 100.)),
+                    _ => collidable,
+                }
+            })
+            .collect();
+        // one much larger than the rest
+        collidables.push(Collidable::new(Collider::Circle(BoundingCircle::new(
+            Vec2::new(75., 50.),
+            12.,
+        ))));
+        for continuous in [false, true] {
+            let options = DetectOptions {
+                contacts: true,
+                continuous,
+            };
+            let sorted = |mut pairs: Vec<DetectedPair>| {
+                pairs.sort_by_key(|pair| (pair.a, pair.b));
+                pairs
+            };
+            let expected = sorted(detect_pairs(&collidables, &options));
+            assert!(expected.len() > 20, "{}", expected.len());
+            assert_eq!(sorted(detect_pairs_grid(&collidables, &options)), expected);
+        }
+        assert!(detect_pairs_grid(&[], &DetectOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_far_away_and_non_finite_collidables() {
+        let circle = |x: f32, y: f32| {
+            Collidable::new(Collider::Circle(BoundingCircle::new(Vec2::new(x, y), 1.)))
+        };
+        let collidables = vec![
+            circle(0., 0.),
+            circle(1., 0.),
+            circle(1e30, 1e30),
+            circle(f32::NAN, 0.),
+            circle(f32::INFINITY, 0.),
+        ];
+        let pairs = detect_pairs_grid(&collidables, &DetectOptions::default());
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].a, pairs[0].b), (0, 1));
+        let grid = Grid::build(&[BoundingCircle::new(Vec2::splat(1e30), 1.)]);
+        assert_eq!((grid.cells_x, grid.cells_y), (1, 1));
+    }
+}
@@ -0,0 +1,495 @@
+use std::{collections::HashMap, fmt, sync::mpsc};
+
+use bevy::{
+    log,
+    math::{Vec2, bounding::BoundingVolume},
+    prelude::Entity,
+};
+use bevy_gpu_compute_core::{
+    IterSpaceDimmension, wgsl::shader_module::complete_shader_module::WgslShaderModule,
+};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    cpu_collision_detection::collider::Collider,
+    gpu_collision_detection::{
+        shader::collision_detection_module,
+        single_batch::{
+            convert_collidables_to_wgsl_types::{
+                CollidableShape, PerCollidableDataRequiredByGpu, convert_collidables_to_wgsl_types,
+            },
+            resources::{ShaderCollisionResult, SingleBatchDataForWgsl, WgslIdToMetadataMap},
+        },
+    },
+};
+
+use super::detect::{Collidable, DetectOptions, DetectedPair};
+
+/// Must match the `@workgroup_size` of a 2D iteration space
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Why a slice can't be detected in one batch on this device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadlessGpuError {
+    /// A thread per collidable would need more workgroups than one dimension of a dispatch allows
+    TooManyCollidables { collidables: usize, max: usize },
+    /// A buffer, in bytes, is bigger than the device's `max_storage_buffer_binding_size`
+    BufferTooLarge {
+        name: &'static str,
+        size: u64,
+        max: u64,
+    },
+}
+
+impl fmt::Display for HeadlessGpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeadlessGpuError::TooManyCollidables { collidables, max } => write!(
+                f,
+                "{} collidables is more than the {} that can be dispatched at once",
+                collidables, max
+            ),
+            HeadlessGpuError::BufferTooLarge { name, size, max } => write!(
+                f,
+                "the {} buffer would be {} bytes, more than the device's limit of {}",
+                name, size, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HeadlessGpuError {}
+
+/// The 2D collision shader on a wgpu device of its own, for detecting collisions on the GPU without a Bevy `App`.
+///
+/// Every call uploads the whole slice as one brute force batch and sizes the results exactly with a counting pass first. It isn't split into batches like the plugin, so a slice that doesn't fit in one dispatch or one storage buffer is an error.
+pub struct HeadlessGpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    shader: wgpu::ShaderModule,
+    bindings: HashMap<String, u32>,
+}
+
+impl HeadlessGpu {
+    /// `None` when there is no adapter, or it can't run the shader
+    pub fn new() -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter =
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+        log::info!("Headless collision detection on {:?}", adapter.get_info());
+        // the shader needs 8 storage buffers, more than the default limits
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("headless_collision_detection"),
+                required_features: wgpu::Features::empty(),
+                required_limits: adapter.limits(),
+                memory_hints: wgpu::MemoryHints::default(),
+            },
+            None,
+        ))
+        .map_err(|error| log::warn!("Could not get a device: {}", error))
+        .ok()?;
+        let parsed = collision_detection_module::parsed();
+        let bindings = parsed.binding_numbers_by_variable_name.clone().unwrap();
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("collision_detection"),
+            source: wgpu::ShaderSource::Wgsl(
+                WgslShaderModule::new(parsed)
+                    .wgsl_code(IterSpaceDimmension::TwoD)
+                    .into(),
+            ),
+        });
+        Some(HeadlessGpu {
+            device,
+            queue,
+            shader,
+            bindings,
+        })
+    }
+
+    /// The GPU version of `detect`
+    pub fn detect(
+        &self,
+        collidables: &[Collidable],
+        options: &DetectOptions,
+    ) -> Result<Vec<(u32, u32)>, HeadlessGpuError> {
+        Ok(self
+            .detect_pairs(collidables, options)?
+            .iter()
+            .map(|pair| (pair.a, pair.b))
+            .collect())
+    }
+
+    /// The GPU version of `detect_pairs`
+    pub fn detect_pairs(
+        &self,
+        collidables: &[Collidable],
+        options: &DetectOptions,
+    ) -> Result<Vec<DetectedPair>, HeadlessGpuError> {
+        if collidables.len() < 2 {
+            return Ok(Vec::new());
+        }
+        let max_collidables =
+            (self.device.limits().max_compute_workgroups_per_dimension * WORKGROUP_SIZE) as usize;
+        if collidables.len() > max_collidables {
+            return Err(HeadlessGpuError::TooManyCollidables {
+                collidables: collidables.len(),
+                max: max_collidables,
+            });
+        }
+        let n = collidables.len() as u32;
+        let data = convert_collidables_to_wgsl_types(
+            collidables
+                .iter()
+                .enumerate()
+                .map(|(i, collidable)| gpu_data(i, collidable))
+                .collect(),
+            &mut WgslIdToMetadataMap(Vec::new()),
+        );
+        let config = |count_only: bool| collision_detection_module::BroadphaseConfig {
+            mode: 0,
+            query_start: 0,
+            query_end: n,
+            cells_x: 1,
+            cells_y: 1,
+            second_range_start: 0,
+            count_only: count_only as u32,
+            contacts: options.contacts as u32,
+            continuous: options.continuous as u32,
+            whole_rows: 0,
+        };
+        // one thread per collidable counts, then one thread per pair detects. Every thread that found collisions pushes its count as entity2
+        let counts = self.run_pass(&data, config(true), (n, 1), n as usize)?;
+        let count = counts
+            .iter()
+            .map(|count| count.entity2 as usize)
+            .sum::<usize>();
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .run_pass(&data, config(false), (n, n), count)?
+            .into_iter()
+            .map(|result| DetectedPair {
+                a: result.entity1,
+                b: result.entity2,
+                contact: options.contacts.then_some(result.contact),
+                time_of_impact: result.time_of_impact,
+            })
+            .collect())
+    }
+
+    /// Returns what the pass pushed, to `CollisionContact` or `CollisionResult`
+    fn run_pass(
+        &self,
+        data: &SingleBatchDataForWgsl,
+        config: collision_detection_module::BroadphaseConfig,
+        (x, y): (u32, u32),
+        max_pushed: usize,
+    ) -> Result<Vec<ShaderCollisionResult>, HeadlessGpuError> {
+        let pushes_contacts = config.pushes_contacts();
+        let (max_results, max_contacts) = if pushes_contacts {
+            (1, max_pushed)
+        } else {
+            (max_pushed, 1)
+        };
+        let result_size = std::mem::size_of::<collision_detection_module::CollisionResult>();
+        let contact_size = std::mem::size_of::<collision_detection_module::CollisionContact>();
+        let max_size = self.device.limits().max_storage_buffer_binding_size as u64;
+        for (name, size) in [
+            ("position", std::mem::size_of_val(data.positions.as_slice())),
+            ("shape", std::mem::size_of_val(data.shapes.as_slice())),
+            ("vertex", std::mem::size_of_val(data.vertices.as_slice())),
+            ("collisionresult", max_results * result_size),
+            ("collisioncontact", max_contacts * contact_size),
+        ] {
+            if size as u64 > max_size {
+                return Err(HeadlessGpuError::BufferTooLarge {
+                    name,
+                    size: size as u64,
+                    max: max_size,
+                });
+            }
+        }
+        let storage = |name: &str, contents: &[u8]| {
+            self.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(name),
+                    contents,
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                })
+        };
+        let config_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("broadphaseconfig"),
+                contents: bytemuck::bytes_of(&config),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        // the brute force broadphase doesn't read the grid, but inputs can't be empty
+        let buffers = [
+            ("broadphaseconfig", config_buffer),
+            (
+                "position_input_array",
+                storage("position", bytemuck::cast_slice(&data.positions)),
+            ),
+            (
+                "shape_input_array",
+                storage("shape", bytemuck::cast_slice(&data.shapes)),
+            ),
+            (
+                "vertex_input_array",
+                storage("vertex", bytemuck::cast_slice(&data.vertices)),
+            ),
+            (
+                "gridcell_input_array",
+                storage("gridcell", bytemuck::cast_slice(&[0u32])),
+            ),
+            (
+                "collisionresult_output_array",
+                storage("collisionresult", &vec![0; max_results * result_size]),
+            ),
+            (
+                "collisionresult_counter",
+                storage("collisionresult_counter", bytemuck::cast_slice(&[0u32])),
+            ),
+            (
+                "collisioncontact_output_array",
+                storage("collisioncontact", &vec![0; max_contacts * contact_size]),
+            ),
+            (
+                "collisioncontact_counter",
+                storage("collisioncontact_counter", bytemuck::cast_slice(&[0u32])),
+            ),
+        ];
+        let lengths = HashMap::from([
+            ("POSITION_INPUT_ARRAY_LENGTH", data.positions.len()),
+            ("SHAPE_INPUT_ARRAY_LENGTH", data.shapes.len()),
+            ("VERTEX_INPUT_ARRAY_LENGTH", data.vertices.len()),
+            ("GRIDCELL_INPUT_ARRAY_LENGTH", 1),
+            ("COLLISIONRESULT_OUTPUT_ARRAY_LENGTH", max_results),
+            ("COLLISIONCONTACT_OUTPUT_ARRAY_LENGTH", max_contacts),
+        ])
+        .into_iter()
+        .map(|(name, length)| (name.to_string(), length as f64))
+        .collect();
+        let pipeline = self
+            .device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("collision_detection"),
+                layout: None,
+                module: &self.shader,
+                entry_point: Some("main"),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &lengths,
+                    zero_initialize_workgroup_memory: true,
+                },
+                cache: None,
+            });
+        let entries: Vec<_> = buffers
+            .iter()
+            .map(|(name, buffer)| wgpu::BindGroupEntry {
+                binding: self.bindings[*name],
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("collision_detection"),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(x.div_ceil(WORKGROUP_SIZE), y.div_ceil(WORKGROUP_SIZE), 1);
+        }
+        self.queue.submit([encoder.finish()]);
+
+        let buffer = |name: &str| {
+            &buffers
+                .iter()
+                .find(|(buffer_name, _)| *buffer_name == name)
+                .unwrap()
+                .1
+        };
+        // pushes past the end of the buffer are dropped
+        let (output, max_pushed, size) = if pushes_contacts {
+            ("collisioncontact", max_contacts, contact_size)
+        } else {
+            ("collisionresult", max_results, result_size)
+        };
+        let counter: u32 =
+            bytemuck::pod_read_unaligned(&self.read(buffer(&format!("{}_counter", output)), 4));
+        let bytes = self.read(
+            buffer(&format!("{}_output_array", output)),
+            ((counter as usize).min(max_pushed) * size) as u64,
+        );
+        Ok(if pushes_contacts {
+            bytemuck::pod_collect_to_vec::<_, collision_detection_module::CollisionContact>(&bytes)
+                .iter()
+                .map(ShaderCollisionResult::from)
+                .collect()
+        } else {
+            bytemuck::pod_collect_to_vec::<_, collision_detection_module::CollisionResult>(&bytes)
+                .iter()
+                .map(ShaderCollisionResult::from)
+                .collect()
+        })
+    }
+
+    fn read(&self, buffer: &wgpu::Buffer, size: u64) -> Vec<u8> {
+        if size == 0 {
+            return Vec::new();
+        }
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, size);
+        self.queue.submit([encoder.finish()]);
+        let slice = staging.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).unwrap();
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .unwrap()
+            .expect("Failed to read from the GPU");
+        let bytes = slice.get_mapped_range().to_vec();
+        staging.unmap();
+        bytes
+    }
+}
+
+/// What the GPU plugin's `get_collidables` would collect for the collidable, centered on its collider
+fn gpu_data(index: usize, collidable: &Collidable) -> PerCollidableDataRequiredByGpu {
+    let center = collidable.collider.center();
+    let shape = match &collidable.collider {
+        Collider::Circle(_) => CollidableShape::Circle,
+        Collider::Box(aabb) => CollidableShape::Box {
+            half_size: aabb.half_size(),
+        },
+        Collider::Capsule { capsule, .. } => CollidableShape::Capsule {
+            radius: capsule.radius,
+            half_length: capsule.half_length,
+        },
+        Collider::ConvexPolygon { vertices, .. } => CollidableShape::ConvexPolygon {
+            vertices: vertices.clone(),
+        },
+    };
+    let previous: Vec2 = collidable.previous_center();
+    PerCollidableDataRequiredByGpu {
+        center_x: center.x,
+        center_y: center.y,
+        center_z: 0.,
+        previous_x: previous.x,
+        previous_y: previous.y,
+        radius: collidable.collider.bounding_circle().radius(),
+        shape,
+        entity: Entity::from_raw(index as u32),
+        is_sensor: false,
+        layers: collidable.layers,
+        user_data: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{
+        Vec2,
+        bounding::{Aabb2d, BoundingCircle},
+        primitives::Capsule2d,
+    };
+
+    use super::*;
+    use crate::{collision_core::detect::detect_pairs, components_and_resources::CollisionLayers};
+
+    #[test]
+    fn test_headless_gpu_matches_the_cpu() {
+        let Some(gpu) = HeadlessGpu::new() else {
+            eprintln!("test_headless_gpu_matches_the_cpu needs a GPU adapter, skipping it");
+            return;
+        };
+        let collidables: Vec<Collidable> = (0..120)
+            .map(|i| {
+                let center = Vec2::new((i * 37 % 60) as f32, (i * 61 % 40) as f32);
+                let collider = match i % 4 {
+                    0 => Collider::Circle(BoundingCircle::new(center, 2.)),
+                    1 => Collider::Box(Aabb2d::new(center, Vec2::new(3., 1.))),
+                    2 => Collider::Capsule {
+                        center,
+                        capsule: Capsule2d::new(1., 4.),
+                    },
+                    _ => Collider::ConvexPolygon {
+                        center,
+                        vertices: vec![Vec2::new(-2., -1.), Vec2::new(2., -1.), Vec2::new(0., 3.)],
+                    },
+                };
+                let collidable = Collidable::new(collider).with_layers(match i % 5 {
+                    0 => CollisionLayers::new(1, 1),
+                    _ => CollisionLayers::ALL,
+                });
+                // every few circles moved a long way in one step
+                match i % 8 {
+                    0 => collidable.with_previous_center(Vec2::new(60. - center.x, center.y)),
+                    _ => collidable,
+                }
+            })
+            .collect();
+        for options in [
+            DetectOptions::default(),
+            DetectOptions {
+                contacts: true,
+                continuous: true,
+            },
+        ] {
+            let mut expected = detect_pairs(&collidables, &options);
+            let mut detected = gpu.detect_pairs(&collidables, &options).unwrap();
+            assert!(!expected.is_empty());
+            for pairs in [&mut expected, &mut detected] {
+                pairs.sort_by_key(|pair| (pair.a, pair.b));
+            }
+            assert_eq!(detected.len(), expected.len());
+            for (detected, expected) in detected.iter().zip(&expected) {
+                assert_eq!((detected.a, detected.b), (expected.a, expected.b));
+                assert_eq!(
+                    detected.time_of_impact.is_some(),
+                    expected.time_of_impact.is_some()
+                );
+                if let (Some(detected), Some(expected)) = (detected.contact, expected.contact) {
+                    assert!((detected.depth - expected.depth).abs() < 1e-3);
+                    assert!(detected.normal.distance(expected.normal) < 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_headless_gpu_rejects_more_collidables_than_one_dispatch() {
+        let Some(gpu) = HeadlessGpu::new() else {
+            eprintln!(
+                "test_headless_gpu_rejects_more_collidables_than_one_dispatch needs a GPU adapter, skipping it"
+            );
+            return;
+        };
+        let max =
+            (gpu.device.limits().max_compute_workgroups_per_dimension * WORKGROUP_SIZE) as usize;
+        let collidables =
+            vec![Collidable::new(Collider::Circle(BoundingCircle::new(Vec2::ZERO, 1.))); max + 1];
+        assert_eq!(
+            gpu.detect_pairs(&collidables, &DetectOptions::default())
+                .map(|pairs| pairs.len()),
+            Err(HeadlessGpuError::TooManyCollidables {
+                collidables: max + 1,
+                max
+            })
+        );
+    }
+}
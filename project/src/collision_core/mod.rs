@@ -0,0 +1,5 @@
+pub mod detect;
+pub mod dynamic_tree;
+pub mod grid;
+pub mod headless_gpu;
+pub mod sweep_and_prune;
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use bevy::{math::bounding::BoundingCircle, prelude::Entity};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use super::detect::{Collidable, DetectOptions, DetectedPair, test_pair};
+
+/// Sort and sweep along x, keeping the sorted order between calls.
+///
+/// The order is kept by entity, so it carries over whatever order the collidables are queried in. Last call's order is fixed up with an insertion sort, which is close to O(n) when the collidables only moved a little, and new collidables are sorted on their own and merged in. Like the grid, collidables that aren't finite are left out.
+#[derive(Debug, Clone, Default)]
+pub struct SweepAndPrune {
+    /// The collidables' entities by the left edge of their broadphase circle, as of the last call
+    order: Vec<Entity>,
+    /// Where each entity is in the current slice, kept to reuse its allocation
+    indices: HashMap<Entity, u32>,
+    /// `order` as indices into the current slice
+    sorted: Vec<u32>,
+}
+
+impl SweepAndPrune {
+    /// `detect_pairs`, finding the same pairs in a different order. `entities` are those of the `collidables`
+    pub fn detect_pairs(
+        &mut self,
+        collidables: &[Collidable],
+        entities: &[Entity],
+        options: &DetectOptions,
+    ) -> Vec<DetectedPair> {
+        let circles: Vec<BoundingCircle> = collidables
+            .iter()
+            .map(|collidable| collidable.broadphase_circle(options.continuous))
+            .collect();
+        let min_x: Vec<f32> = circles
+            .iter()
+            .map(|circle| circle.center.x - circle.radius())
+            .collect();
+        self.indices.clear();
+        self.indices.extend(
+            entities
+                .iter()
+                .enumerate()
+                .map(|(i, entity)| (*entity, i as u32)),
+        );
+        // a NaN key can't be sorted past, and would stop every sweep that reaches it
+        let finite = |i: &u32| {
+            let circle = &circles[*i as usize];
+            circle.center.is_finite() && circle.radius().is_finite()
+        };
+        // removing the entities that were there last time leaves the new ones
+        self.sorted.clear();
+        self.sorted.extend(
+            self.order
+                .iter()
+                .filter_map(|entity| self.indices.remove(entity))
+                .filter(finite),
+        );
+        let kept = self.sorted.len();
+        insertion_sort_by_key(&mut self.sorted, &min_x);
+        self.sorted
+            .extend(self.indices.values().copied().filter(finite));
+        let by_key = |a: &u32, b: &u32| {
+            min_x[*a as usize]
+                .total_cmp(&min_x[*b as usize])
+                .then(a.cmp(b))
+        };
+        self.sorted[kept..].sort_unstable_by(by_key);
+        if kept > 0 && kept < self.sorted.len() {
+            // a stable sort merges two sorted runs in O(n)
+            self.sorted.sort_by(by_key);
+        }
+        self.order.clear();
+        self.order
+            .extend(self.sorted.iter().map(|i| entities[*i as usize]));
+        let order = &self.sorted;
+        (0..order.len())
+            .into_par_iter()
+            .flat_map_iter(|position| {
+                let i = order[position] as usize;
+                let circle = &circles[i];
+                let max_x = circle.center.x + circle.radius();
+                let (circles, min_x) = (&circles, &min_x);
+                order[position + 1..]
+                    .iter()
+                    .map(|k| *k as usize)
+                    // everything after the first one starting past the right edge starts past it too
+                    .take_while(move |k| min_x[*k] <= max_x)
+                    .filter(move |k| {
+                        (circle.center.y - circles[*k].center.y).abs()
+                            <= circle.radius() + circles[*k].radius()
+                    })
+                    .filter_map(move |k| {
+                        let (a, b) = (i.min(k), i.max(k));
+                        test_pair(a, &collidables[a], b, &collidables[b], options)
+                    })
+            })
+            .collect()
+    }
+}
+
+fn insertion_sort_by_key(order: &mut [u32], keys: &[f32]) {
+    for i in 1..order.len() {
+        let index = order[i];
+        let key = keys[index as usize];
+        let mut j = i;
+        while j > 0 && keys[order[j - 1] as usize].total_cmp(&key).is_gt() {
+            order[j] = order[j - 1];
+            j -= 1;
+        }
+        order[j] = index;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{Vec2, bounding::Aabb2d};
+
+    use super::*;
+    use crate::{
+        collision_core::detect::detect_pairs, cpu_collision_detection::collider::Collider,
+    };
+
+    fn collidables(frame: u32, count: u32) -> Vec<Collidable> {
+        (0..count)
+            .map(|i| {
+                let center = Vec2::new((i * 37 % 100) as f32, (i * 53 % 60) as f32)
+                    + Vec2::new(frame as f32 * 0.7, 0.).rotate(Vec2::from_angle(i as f32));
+                let collidable = Collidable::new(match i % 2 {
+                    0 => Collider::Circle(BoundingCircle::new(center, 1. + (i % 4) as f32)),
+                    _ => Collider::Box(Aabb2d::new(center, Vec2::new(2., 0.5))),
+                });
+                match i % 25 {
+                    0 => collidable.with_previous_center(center + Vec2::new(-60., 40.)),
+                    _ => collidable,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sweep_and_prune_finds_the_brute_force_pairs_every_frame() {
+        let sorted = |mut pairs: Vec<DetectedPair>| {
+            pairs.sort_by_key(|pair| (pair.a, pair.b));
+            pairs
+        };
+        for continuous in [false, true] {
+            let options = DetectOptions {
+                contacts: true,
+                continuous,
+            };
+            let mut sweep_and_prune = SweepAndPrune::default();
+            // moving a little, then fewer of them, then reordered, then with new ones
+            for (frame, count) in [(0, 300), (1, 300), (2, 300), (3, 250), (4, 250), (5, 320)] {
+                let mut collidables = collidables(frame, count);
+                let mut entities: Vec<Entity> = (0..count).map(Entity::from_raw).collect();
+                if frame == 4 {
+                    collidables.reverse();
+                    entities.reverse();
+                }
+                let expected = sorted(detect_pairs(&collidables, &options));
+                assert!(expected.len() > 20, "{}", expected.len());
+                assert_eq!(
+                    sorted(sweep_and_prune.detect_pairs(&collidables, &entities, &options)),
+                    expected
+                );
+                assert_eq!(sweep_and_prune.order.len(), count as usize);
+            }
+        }
+    }
+
+    #[test]
+    fn test_a_collidable_turning_nan_doesnt_hide_other_pairs() {
+        let options = DetectOptions::default();
+        let entities: Vec<Entity> = (0..300).map(Entity::from_raw).collect();
+        let mut sweep_and_prune = SweepAndPrune::default();
+        for frame in 0..4 {
+            let mut collidables = collidables(frame, 300);
+            let nan = frame > 0 && frame < 3;
+            if nan {
+                collidables[150] =
+                    Collidable::new(Collider::Circle(BoundingCircle::new(Vec2::NAN, 3.)));
+            }
+            let mut expected: Vec<_> = detect_pairs(&collidables, &options)
+                .into_iter()
+                .filter(|pair| !nan || (pair.a != 150 && pair.b != 150))
+                .collect();
+            let mut detected = sweep_and_prune.detect_pairs(&collidables, &entities, &options);
+            for pairs in [&mut expected, &mut detected] {
+                pairs.sort_by_key(|pair| (pair.a, pair.b));
+            }
+            assert_eq!(detected, expected, "frame {}", frame);
+            assert_eq!(sweep_and_prune.order.len(), if nan { 299 } else { 300 });
+        }
+    }
+}
@@ -2,14 +2,19 @@ use bevy::{
     DefaultPlugins,
     app::{App, PreUpdate, Startup, Update},
     diagnostic::FrameTimeDiagnosticsPlugin,
-    prelude::{Commands, IntoSystemConfigs},
+    prelude::IntoSystemConfigs,
+};
+
+use gpu_accelerated_collision_detection::{
+    collision_detection_plugin::{
+        CollisionDetectionMethod, CollisionDetectionPlugin, CollisionDetectionSet,
+    },
+    components_and_resources::SysInfo,
+    gpu_collision_detection::plugin::estimate_minimum_scale_factor_to_catch_all_collisions,
 };
 
 use crate::{
-    colliding_pair::CollidingPairs,
-    collision_detection_plugin::{CollisionDetectionMethod, CollisionDetectionPlugin},
     collision_processing::process_collisions,
-    components_and_resources::SysInfo,
     config::RunConfig,
     entity_movement::{move_entities_deterministic, setup_position_cache},
     entity_spawning::spawn_entities,
@@ -29,21 +34,25 @@ pub fn collision_detection_performance_test(
         .init_resource::<SysInfo>()
         .insert_resource(run_config.clone())
         .add_plugins(GraphicsPlugin)
-        .add_systems(
-            Startup,
-            (setup, spawn_entities, setup_position_cache).chain(),
+        .add_systems(Startup, (spawn_entities, setup_position_cache).chain())
+        .add_plugins(
+            CollisionDetectionPlugin::builder()
+                .method(collision_detection_type)
+                .max_detectable_collisions_scale(
+                    estimate_minimum_scale_factor_to_catch_all_collisions(
+                        (run_config.top_right_x - run_config.bottom_left_x) as f32,
+                        (run_config.top_right_y - run_config.bottom_left_y) as f32,
+                        (run_config.sensor_radius + run_config.body_radius) / 2.,
+                    ),
+                )
+                .build(),
         )
-        .add_plugins(CollisionDetectionPlugin {
-            method: collision_detection_type,
-            run_config,
-        })
         .add_systems(PreUpdate, (move_entities_deterministic,).chain())
         .add_systems(
             Update,
-            (process_collisions, track_performance_and_exit).chain(),
+            (process_collisions, track_performance_and_exit)
+                .chain()
+                .after(CollisionDetectionSet),
         )
         .run();
 }
-fn setup(mut commands: Commands) {
-    commands.insert_resource(CollidingPairs(Vec::new()));
-}
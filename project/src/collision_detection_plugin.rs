@@ -1,34 +1,429 @@
 use bevy::{
-    app::{App, Plugin},
+    app::{App, Plugin, Update},
     log,
-    prelude::Resource,
+    prelude::{
+        IntoSystemConfigs, IntoSystemSetConfigs, Res, ResMut, Resource, SystemSet, resource_changed,
+    },
+    render::RenderPlugin,
 };
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::RunConfig,
-    cpu_collision_detection::cpu_collision_detection::CpuCollisionDetectionPlugin,
-    gpu_collision_detection::plugin::GpuCollisionDetectionPlugin,
+    auto_method_selection::{AutoMethodSelection, AutoMethodSelectionPlugin},
+    colliding_entities::TrackCollidingEntities,
+    components_and_resources::CollisionLayers,
+    cpu_collision_detection::cpu_collision_detection::{
+        CpuBroadphase, CpuCollisionDetectionPlugin,
+    },
+    gpu_collision_detection::{plugin::GpuCollisionDetectionPlugin, resources::GpuBroadphase},
 };
 
-#[derive(Clone, Debug, Copy, Resource)]
+/// Held in a resource by `CollisionDetectionPlugin`, so it can be changed at runtime, for example from a debug UI
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Resource, Serialize, Deserialize)]
 pub enum CollisionDetectionMethod {
     Gpu,
     Cpu,
+    /// The CPU plugin with a uniform grid broadphase, see `CpuBroadphase::Grid`
+    CpuGrid,
+    /// The CPU plugin with a sweep and prune broadphase, see `CpuBroadphase::SweepAndPrune`
+    CpuSweepAndPrune,
+    /// The CPU plugin with a dynamic AABB tree broadphase, see `CpuBroadphase::DynamicTree`
+    CpuDynamicTree,
+    /// The GPU pipeline with the shader run on the CPU, for testing without a GPU adapter
+    GpuEmulated,
+    /// Both the CPU plugin and the GPU plugin, with one of them running each frame depending on the population and how long each took, see `AutoMethodSelection`
+    Auto,
 }
 
+impl CollisionDetectionMethod {
+    /// The broadphase of the CPU methods, `None` for the others
+    pub fn cpu_broadphase(&self) -> Option<CpuBroadphase> {
+        match self {
+            CollisionDetectionMethod::Cpu => Some(CpuBroadphase::BruteForce),
+            CollisionDetectionMethod::CpuGrid => Some(CpuBroadphase::Grid),
+            CollisionDetectionMethod::CpuSweepAndPrune => Some(CpuBroadphase::SweepAndPrune),
+            CollisionDetectionMethod::CpuDynamicTree => Some(CpuBroadphase::DynamicTree),
+            _ => None,
+        }
+    }
+
+    /// Whether the method runs the CPU plugin, always for `Auto`
+    pub fn uses_cpu(&self) -> bool {
+        self.cpu_broadphase().is_some() || *self == CollisionDetectionMethod::Auto
+    }
+
+    /// Whether the method runs the GPU plugin, always for `Auto`
+    pub fn uses_gpu(&self) -> bool {
+        self.cpu_broadphase().is_none()
+    }
+}
+
+/// Every system that detects collisions or updates what is derived from them, like the collision events and `CollidingEntities`. Systems reading `CollidingPairs` should run after it
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CollisionDetectionSet;
+
+/// The systems of the CPU plugin that `CollisionDetectionMethod::Auto` turns off while the GPU runs
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CpuDetectionSet;
+
+/// The systems of the GPU plugin that `CollisionDetectionMethod::Auto` turns off while the CPU runs
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GpuDetectionSet;
+
+/// Adds both detection plugins, with the options that both of them understand, and runs the one the `CollisionDetectionMethod` resource picks. Build it with `CollisionDetectionPlugin::builder()`.
+///
+/// Only the plugins the starting method uses are added, unless it is `hot_swappable`. The real GPU is only there when the `RenderPlugin` was added before this plugin, otherwise `Gpu` runs the shader emulated on the CPU. Its compute task is created the first time `Gpu` or `Auto` is picked, and released when another method is
 pub struct CollisionDetectionPlugin {
     pub method: CollisionDetectionMethod,
-    pub run_config: RunConfig,
+    /// Add both the CPU and the GPU plugin, so the method can be changed between them at runtime
+    pub hot_swappable: bool,
+    /// GPU only, see `GpuCollisionDetectionPlugin::max_detectable_collisions_scale`. `None` sizes the result buffers exactly
+    pub max_detectable_collisions_scale: Option<f32>,
+    /// GPU only, see `GpuCollisionDetectionPlugin::max_batch_size`
+    pub max_batch_size: Option<usize>,
+    /// GPU only
+    pub broadphase: GpuBroadphase,
+    pub default_layers: CollisionLayers,
+    pub compute_contacts: bool,
+    pub continuous: bool,
+    pub collision_observers: bool,
+    pub colliding_entities: TrackCollidingEntities,
+    pub deterministic: bool,
+}
+
+impl CollisionDetectionPlugin {
+    pub fn builder() -> CollisionDetectionPluginBuilder {
+        CollisionDetectionPluginBuilder(CollisionDetectionPlugin {
+            method: CollisionDetectionMethod::Cpu,
+            hot_swappable: false,
+            max_detectable_collisions_scale: None,
+            max_batch_size: None,
+            broadphase: GpuBroadphase::default(),
+            default_layers: CollisionLayers::ALL,
+            compute_contacts: false,
+            continuous: false,
+            collision_observers: false,
+            colliding_entities: TrackCollidingEntities::Off,
+            deterministic: false,
+        })
+    }
+
+    fn gpu_plugin(&self, has_gpu: bool) -> GpuCollisionDetectionPlugin {
+        let mut plugin = GpuCollisionDetectionPlugin {
+            max_detectable_collisions_scale: self.max_detectable_collisions_scale,
+            max_batch_size: self.max_batch_size,
+            broadphase: self.broadphase,
+            default_layers: self.default_layers,
+            compute_contacts: self.compute_contacts,
+            continuous: self.continuous,
+            collision_observers: self.collision_observers,
+            colliding_entities: self.colliding_entities,
+            deterministic: self.deterministic,
+            ..Default::default()
+        };
+        if !has_gpu {
+            plugin = plugin.with_cpu_emulation();
+        }
+        plugin
+    }
+
+    fn cpu_plugin(&self) -> CpuCollisionDetectionPlugin {
+        CpuCollisionDetectionPlugin {
+            broadphase: self.method.cpu_broadphase().unwrap_or_default(),
+            compute_contacts: self.compute_contacts,
+            continuous: self.continuous,
+            collision_observers: self.collision_observers,
+            colliding_entities: self.colliding_entities,
+            default_layers: self.default_layers,
+            deterministic: self.deterministic,
+        }
+    }
 }
 
 impl Plugin for CollisionDetectionPlugin {
     fn build(&self, app: &mut App) {
         log::info!("Using collision detection method: {:?}", self.method);
-        app.insert_resource(self.method.clone());
-        if let CollisionDetectionMethod::Gpu = self.method {
-            app.add_plugins(GpuCollisionDetectionPlugin::new(&self.run_config));
-        } else {
-            app.add_plugins(CpuCollisionDetectionPlugin);
+        let has_gpu = app.is_plugin_added::<RenderPlugin>();
+        if !has_gpu && self.method == CollisionDetectionMethod::Gpu {
+            log::warn!(
+                "No RenderPlugin was added before the collision detection, so the GPU pipeline runs emulated on the CPU"
+            );
         }
+        let has_cpu_plugin = self.hot_swappable || self.method.uses_cpu();
+        let has_gpu_plugin = self.hot_swappable || self.method.uses_gpu();
+        // inserted first, so the GPU plugin knows whether it starts out running
+        app.insert_resource(self.method);
+        if has_cpu_plugin {
+            app.add_plugins(self.cpu_plugin()).add_systems(
+                Update,
+                sync_cpu_broadphase
+                    .run_if(resource_changed::<CollisionDetectionMethod>)
+                    .before(CpuDetectionSet),
+            );
+        }
+        if has_gpu_plugin {
+            app.add_plugins(self.gpu_plugin(has_gpu));
+        }
+        app.add_plugins(AutoMethodSelectionPlugin)
+            .configure_sets(
+                Update,
+                (
+                    CpuDetectionSet.run_if(cpu_selected),
+                    GpuDetectionSet.run_if(gpu_selected),
+                ),
+            )
+            .add_systems(
+                Update,
+                warn_missing_plugin(has_cpu_plugin, has_gpu_plugin)
+                    .run_if(resource_changed::<CollisionDetectionMethod>),
+            );
+    }
+}
+
+/// A method whose plugin wasn't added leaves `CollidingPairs` as it is
+fn warn_missing_plugin(
+    has_cpu_plugin: bool,
+    has_gpu_plugin: bool,
+) -> impl FnMut(Res<CollisionDetectionMethod>) {
+    move |method: Res<CollisionDetectionMethod>| {
+        if (method.uses_cpu() && !has_cpu_plugin) || (method.uses_gpu() && !has_gpu_plugin) {
+            log::warn!(
+                "{:?} needs a detection plugin that wasn't added, build the CollisionDetectionPlugin with hot_swappable() to switch between CPU and GPU methods",
+                *method
+            );
+        }
+    }
+}
+
+/// Whether the CPU plugin detects collisions this frame
+pub fn cpu_selected(
+    method: Res<CollisionDetectionMethod>,
+    selection: Res<AutoMethodSelection>,
+) -> bool {
+    match *method {
+        CollisionDetectionMethod::Auto => selection.current == CollisionDetectionMethod::Cpu,
+        method => method.cpu_broadphase().is_some(),
+    }
+}
+
+/// Whether the GPU plugin detects collisions this frame
+pub fn gpu_selected(
+    method: Res<CollisionDetectionMethod>,
+    selection: Res<AutoMethodSelection>,
+) -> bool {
+    match *method {
+        CollisionDetectionMethod::Auto => selection.current == CollisionDetectionMethod::Gpu,
+        method => method.cpu_broadphase().is_none(),
+    }
+}
+
+/// Whether the GPU plugin runs the shader emulated on the CPU even though it has the real GPU
+pub fn gpu_emulated(method: Option<Res<CollisionDetectionMethod>>) -> bool {
+    method.is_some_and(|method| *method == CollisionDetectionMethod::GpuEmulated)
+}
+
+/// Also marks the broadphase changed when it stays the same, so the `CpuDynamicTree` is rebuilt with what the CPU plugin missed while another method ran
+fn sync_cpu_broadphase(
+    method: Res<CollisionDetectionMethod>,
+    mut broadphase: ResMut<CpuBroadphase>,
+) {
+    *broadphase = method.cpu_broadphase().unwrap_or_default();
+}
+
+pub struct CollisionDetectionPluginBuilder(CollisionDetectionPlugin);
+
+impl CollisionDetectionPluginBuilder {
+    pub fn method(mut self, method: CollisionDetectionMethod) -> Self {
+        self.0.method = method;
+        self
+    }
+
+    /// Add both the CPU and the GPU plugin whatever the method, so it can be changed between them at runtime
+    pub fn hot_swappable(mut self) -> Self {
+        self.0.hot_swappable = true;
+        self
+    }
+
+    pub fn max_detectable_collisions_scale(mut self, scale: f32) -> Self {
+        self.0.max_detectable_collisions_scale = Some(scale);
+        self
+    }
+
+    pub fn max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.0.max_batch_size = Some(max_batch_size);
+        self
+    }
+
+    pub fn broadphase(mut self, broadphase: GpuBroadphase) -> Self {
+        self.0.broadphase = broadphase;
+        self
+    }
+
+    /// Layers of the collidables without a `CollisionLayers` component
+    pub fn default_layers(mut self, layers: CollisionLayers) -> Self {
+        self.0.default_layers = layers;
+        self
+    }
+
+    pub fn contacts(mut self) -> Self {
+        self.0.compute_contacts = true;
+        self
+    }
+
+    pub fn continuous_collision_detection(mut self) -> Self {
+        self.0.continuous = true;
+        self
+    }
+
+    pub fn collision_observers(mut self) -> Self {
+        self.0.collision_observers = true;
+        self
+    }
+
+    pub fn colliding_entities(mut self, tracking: TrackCollidingEntities) -> Self {
+        self.0.colliding_entities = tracking;
+        self
+    }
+
+    /// Sort `CollidingPairs` into the same order on every run and with every method, and hash them into `CollisionSetHash`
+    pub fn deterministic(mut self) -> Self {
+        self.0.deterministic = true;
+        self
+    }
+
+    pub fn build(self) -> CollisionDetectionPlugin {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        app::PluginsState,
+        log::LogPlugin,
+        math::{Vec2, bounding::BoundingCircle},
+        prelude::*,
+        window::ExitCondition,
+        winit::WinitPlugin,
+    };
+
+    use super::*;
+    use crate::{
+        colliding_pair::CollidingPairs,
+        components_and_resources::BoundingCircleComponent,
+        cpu_collision_detection::cpu_dynamic_tree::CpuDynamicTree,
+        gpu_collision_detection::{
+            create_gpu_task::GpuTaskCreated, resources::AllCollidablesThisFrame,
+        },
+    };
+
+    #[test]
+    fn test_method_can_be_switched_at_runtime() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            CollisionDetectionPlugin::builder().hot_swappable().build(),
+        ));
+        let entities: Vec<Entity> = (0..30)
+            .map(|i| {
+                let center = Vec2::new(i as f32 * 1.5, 0.);
+                app.world_mut()
+                    .spawn((
+                        Transform::from_translation(center.extend(0.)),
+                        BoundingCircleComponent(BoundingCircle::new(center, 1.)),
+                    ))
+                    .id()
+            })
+            .collect();
+        let gpu_population = |app: &App| app.world().resource::<AllCollidablesThisFrame>().0.len();
+        let pair_count = |app: &App| app.world().resource::<CollidingPairs>().0.len();
+        app.update();
+        assert_eq!(pair_count(&app), 29);
+        assert_eq!(gpu_population(&app), 0);
+
+        *app.world_mut().resource_mut::<CollisionDetectionMethod>() =
+            CollisionDetectionMethod::GpuEmulated;
+        app.world_mut().despawn(entities[0]);
+        app.update();
+        assert_eq!(gpu_population(&app), 29);
+        assert_eq!(pair_count(&app), 28);
+
+        // the tree missed the despawns while the GPU ran
+        app.world_mut().despawn(entities[1]);
+        for _ in 0..3 {
+            app.update();
+        }
+        *app.world_mut().resource_mut::<CollisionDetectionMethod>() =
+            CollisionDetectionMethod::CpuDynamicTree;
+        app.world_mut().despawn(entities[2]);
+        app.update();
+        assert_eq!(
+            *app.world().resource::<CpuBroadphase>(),
+            CpuBroadphase::DynamicTree
+        );
+        assert_eq!(app.world().resource::<CpuDynamicTree>().len(), 27);
+        assert_eq!(pair_count(&app), 26);
+        // the GPU plugin didn't run
+        assert_eq!(gpu_population(&app), 28);
+    }
+
+    /// Needs a GPU adapter, which can be a software one like llvmpipe
+    #[test]
+    fn test_method_can_be_switched_on_a_real_gpu() {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        if pollster::block_on(instance.request_adapter(&Default::default())).is_none() {
+            eprintln!("test_method_can_be_switched_on_a_real_gpu needs a GPU adapter, skipping it");
+            return;
+        }
+        let mut app = App::new();
+        app.add_plugins((
+            DefaultPlugins
+                .build()
+                .disable::<WinitPlugin>()
+                .disable::<LogPlugin>()
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    close_when_requested: false,
+                }),
+            CollisionDetectionPlugin::builder().hot_swappable().build(),
+        ));
+        while app.plugins_state() == PluginsState::Adding {
+            bevy::tasks::tick_global_task_pools_on_main_thread();
+        }
+        app.finish();
+        app.cleanup();
+        for i in 0..30 {
+            let center = Vec2::new(i as f32 * 1.5, 0.);
+            app.world_mut().spawn((
+                Transform::from_translation(center.extend(0.)),
+                BoundingCircleComponent(BoundingCircle::new(center, 1.)),
+            ));
+        }
+        let task_created = |app: &App| app.world().resource::<GpuTaskCreated>().0;
+        let gpu_population = |app: &App| app.world().resource::<AllCollidablesThisFrame>().0.len();
+        let pair_count = |app: &App| app.world().resource::<CollidingPairs>().0.len();
+        app.update();
+        assert_eq!(pair_count(&app), 29);
+        assert!(!task_created(&app));
+        assert_eq!(gpu_population(&app), 0);
+
+        *app.world_mut().resource_mut::<CollisionDetectionMethod>() = CollisionDetectionMethod::Gpu;
+        app.world_mut().resource_mut::<CollidingPairs>().0.clear();
+        app.update();
+        assert!(task_created(&app));
+        assert_eq!(gpu_population(&app), 30);
+        assert_eq!(pair_count(&app), 29);
+
+        *app.world_mut().resource_mut::<CollisionDetectionMethod>() = CollisionDetectionMethod::Cpu;
+        app.world_mut()
+            .resource_mut::<AllCollidablesThisFrame>()
+            .0
+            .clear();
+        app.world_mut().resource_mut::<CollidingPairs>().0.clear();
+        app.update();
+        assert!(!task_created(&app));
+        assert_eq!(gpu_population(&app), 0);
+        assert_eq!(pair_count(&app), 29);
     }
 }
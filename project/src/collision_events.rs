@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::{
+    colliding_entities::{TrackCollidingEntities, update_colliding_entities},
+    colliding_pair::{CollidingPairs, EntityPair},
+    collision_detection_plugin::CollisionDetectionSet,
+    contact_graph::{ContactGraph, update_contact_graph},
+    deterministic_collisions::{
+        CollisionSetHash, DeterministicCollisions, canonicalize_colliding_pairs,
+    },
+};
+
+/// Sent the first frame a pair is in `CollidingPairs`. When `CollisionObservers` is on it is also triggered on both entities
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollisionStarted(pub EntityPair);
+
+/// Sent the first frame a pair that was in `CollidingPairs` is not anymore, including when one of its entities was despawned. When `CollisionObservers` is on it is also triggered on both entities
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollisionEnded(pub EntityPair);
+
+/// Whether `CollisionStarted` and `CollisionEnded` are also triggered for observers, on top of being sent as events. Set by the detection plugins, and can be changed at runtime
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Resource)]
+pub struct CollisionObservers(pub bool);
+
+/// The pairs that were colliding the last time `send_collision_events` ran
+#[derive(Debug, Default, Resource)]
+pub struct PreviousCollidingPairs(pub HashSet<EntityPair>);
+
+/// Added by both detection plugins, which run their detection before `canonicalize_colliding_pairs`, so the order, the events, the `ContactGraph` and `CollidingEntities` are the same whichever method found the pairs
+pub struct CollisionEventsPlugin {
+    pub observers: bool,
+    pub colliding_entities: TrackCollidingEntities,
+    pub deterministic: bool,
+}
+
+impl Plugin for CollisionEventsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CollisionStarted>()
+            .add_event::<CollisionEnded>()
+            .insert_resource(CollisionObservers(self.observers))
+            .insert_resource(self.colliding_entities)
+            .insert_resource(DeterministicCollisions(self.deterministic))
+            .init_resource::<CollisionSetHash>()
+            .init_resource::<CollidingPairs>()
+            .init_resource::<PreviousCollidingPairs>()
+            .init_resource::<ContactGraph>()
+            .add_systems(
+                Update,
+                (
+                    canonicalize_colliding_pairs
+                        .before(send_collision_events)
+                        .run_if(resource_equals(DeterministicCollisions(true))),
+                    send_collision_events,
+                    update_contact_graph.after(send_collision_events),
+                    update_colliding_entities
+                        .after(send_collision_events)
+                        .run_if(not(resource_equals(TrackCollidingEntities::Off))),
+                )
+                    .in_set(CollisionDetectionSet),
+            );
+    }
+}
+
+/// Diffs this frame's `CollidingPairs` against last frame's
+pub fn send_collision_events(
+    mut commands: Commands,
+    collisions: Res<CollidingPairs>,
+    observers: Res<CollisionObservers>,
+    mut previous: ResMut<PreviousCollidingPairs>,
+    mut started_events: EventWriter<CollisionStarted>,
+    mut ended_events: EventWriter<CollisionEnded>,
+) {
+    let mut current = HashSet::with_capacity(collisions.0.len());
+    for collision in collisions.0.iter() {
+        let pair = collision.entity_pair();
+        // a pair is only started once even if it was found twice
+        if current.insert(pair) && !previous.0.contains(&pair) {
+            started_events.send(CollisionStarted(pair));
+            if observers.0 {
+                commands.trigger_targets(CollisionStarted(pair), pair.entities());
+            }
+        }
+    }
+    for pair in previous.0.difference(&current) {
+        ended_events.send(CollisionEnded(*pair));
+        if observers.0 {
+            commands.trigger_targets(CollisionEnded(*pair), pair.entities());
+        }
+    }
+    previous.0 = current;
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::event::EventCursor, math::bounding::BoundingCircle};
+
+    use super::*;
+    use crate::{
+        components_and_resources::{BoundingCircleComponent, CollisionLayers, SysInfo},
+        cpu_collision_detection::cpu_collision_detection::CpuCollisionDetectionPlugin,
+        gpu_collision_detection::{
+            plugin::GpuCollisionDetectionPlugin,
+            resources::{CollisionDimensions, CollisionOverflowPolicy, GpuBroadphase},
+        },
+    };
+
+    #[derive(Resource, Default)]
+    struct Observed(Vec<(Entity, bool)>);
+
+    fn circle(x: f32) -> (Transform, BoundingCircleComponent) {
+        (
+            Transform::from_xyz(x, 0., 0.),
+            BoundingCircleComponent(BoundingCircle::new(Vec2::new(x, 0.), 1.)),
+        )
+    }
+
+    fn move_to(app: &mut App, entity: Entity, x: f32) {
+        let (transform, circle) = circle(x);
+        app.world_mut()
+            .entity_mut(entity)
+            .insert((transform, circle));
+    }
+
+    #[test]
+    fn test_started_and_ended_once_per_contact() {
+        assert_events(CpuCollisionDetectionPlugin::default().with_collision_observers());
+        assert_events(GpuCollisionDetectionPlugin {
+            max_detectable_collisions_scale: None,
+            broadphase: GpuBroadphase::BruteForce,
+            overflow_policy: CollisionOverflowPolicy::default(),
+            assert_batch_plan: false,
+            emulate_on_cpu: true,
+            dimensions: CollisionDimensions::Two,
+            compute_contacts: false,
+            continuous: false,
+            collision_observers: true,
+            colliding_entities: TrackCollidingEntities::Off,
+            default_layers: CollisionLayers::ALL,
+            max_batch_size: None,
+            deterministic: false,
+        });
+    }
+
+    fn assert_events(plugin: impl Plugin) {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, plugin))
+            .insert_resource(SysInfo {
+                total_mem: u64::MAX,
+            })
+            .insert_resource(CollidingPairs(Vec::new()))
+            .init_resource::<Observed>();
+        let a = app.world_mut().spawn(circle(0.)).id();
+        let b = app.world_mut().spawn(circle(10.)).id();
+        app.world_mut().entity_mut(b).observe(
+            |trigger: Trigger<CollisionStarted>, mut observed: ResMut<Observed>| {
+                observed.0.push((trigger.entity(), true));
+            },
+        );
+        app.world_mut().entity_mut(b).observe(
+            |trigger: Trigger<CollisionEnded>, mut observed: ResMut<Observed>| {
+                observed.0.push((trigger.entity(), false));
+            },
+        );
+        // events are kept for more than one frame, so each frame only reads the ones it hasn't read yet
+        let mut started_cursor = EventCursor::<CollisionStarted>::default();
+        let mut ended_cursor = EventCursor::<CollisionEnded>::default();
+        let mut frame = |app: &mut App| {
+            app.update();
+            let started: Vec<_> = started_cursor
+                .read(app.world().resource::<Events<CollisionStarted>>())
+                .map(|event| event.0)
+                .collect();
+            let ended: Vec<_> = ended_cursor
+                .read(app.world().resource::<Events<CollisionEnded>>())
+                .map(|event| event.0)
+                .collect();
+            (started, ended)
+        };
+        assert_eq!(frame(&mut app), (vec![], vec![]));
+        move_to(&mut app, b, 1.);
+        assert_eq!(frame(&mut app), (vec![EntityPair::new(b, a)], vec![]));
+        assert_eq!(frame(&mut app), (vec![], vec![]));
+        move_to(&mut app, b, 10.);
+        assert_eq!(frame(&mut app), (vec![], vec![EntityPair::new(a, b)]));
+        move_to(&mut app, b, 1.);
+        frame(&mut app);
+        app.world_mut().despawn(a);
+        assert_eq!(frame(&mut app), (vec![], vec![EntityPair::new(a, b)]));
+        assert_eq!(
+            app.world().resource::<Observed>().0,
+            vec![(b, true), (b, false), (b, true), (b, false)]
+        );
+    }
+}
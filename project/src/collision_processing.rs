@@ -5,11 +5,13 @@ use bevy::{
     prelude::{Entity, Mut, Query, Res, ResMut, Transform, With, Without},
 };
 
-use crate::{
+use gpu_accelerated_collision_detection::{
     colliding_pair::CollidingPairs, components_and_resources::Sensor,
-    helpers::math::my_rads::MyRads, performance::PerformanceMetrics,
+    helpers::math::my_rads::MyRads,
 };
 
+use crate::performance::PerformanceMetrics;
+
 /**
  * Only processes collisions between sensors and bodies
  * This code does not affect the core goal of comparing the performance of CPU vs GPU collision detection.
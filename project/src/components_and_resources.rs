@@ -1,12 +1,52 @@
+use std::{any::Any, sync::Arc};
+
 use bevy::{
-    math::bounding::BoundingCircle,
+    math::{
+        Vec2,
+        bounding::{Aabb2d, Aabb3d, BoundingCircle, BoundingSphere},
+        primitives::Capsule2d,
+    },
     prelude::{Component, Entity, Resource},
 };
 use sysinfo::System;
 
+use crate::cpu_collision_detection::collider::is_convex_polygon;
+
 #[derive(Debug, Component)]
 pub struct BoundingCircleComponent(pub BoundingCircle);
 
+/// Axis-aligned box collider, centered on the entity's `Transform` like the circles.
+/// A collidable with more than one collider component uses the first of circle, box, capsule and polygon.
+#[derive(Debug, Component)]
+pub struct BoundingBoxComponent(pub Aabb2d);
+
+/// Vertical capsule collider, centered on the entity's `Transform`.
+#[derive(Debug, Component)]
+pub struct CapsuleComponent(pub Capsule2d);
+
+/// Convex polygon collider, with vertices relative to the entity's `Transform` in either winding order. Rotation is ignored, like for the other colliders.
+#[derive(Debug, Component)]
+pub struct ConvexPolygonComponent(pub Vec<Vec2>);
+
+impl ConvexPolygonComponent {
+    /// None unless the vertices pass `is_convex_polygon`. Collidables with an invalid polygon are skipped by the detection, with a warning
+    pub fn new(vertices: Vec<Vec2>) -> Option<Self> {
+        is_convex_polygon(&vertices).then_some(ConvexPolygonComponent(vertices))
+    }
+
+    pub fn is_valid(&self) -> bool {
+        is_convex_polygon(&self.0)
+    }
+}
+
+/// Sphere collider for the 3D GPU plugin, centered on the entity's `Transform` like the 2D colliders.
+#[derive(Debug, Component)]
+pub struct BoundingSphereComponent(pub BoundingSphere);
+
+/// Axis-aligned box collider for the 3D GPU plugin, centered on the entity's `Transform`. A collidable with both 3D colliders uses the sphere.
+#[derive(Debug, Component)]
+pub struct BoundingBox3dComponent(pub Aabb3d);
+
 #[derive(Resource)]
 pub struct SysInfo {
     pub total_mem: u64,
@@ -31,5 +71,89 @@ pub struct CollisionTask {
 #[derive(Component)]
 pub struct Sensor {}
 
+/// Layer that sensors are members of
+pub const SENSOR_LAYER: u32 = 1 << 0;
+/// Layer that bodies are members of
+pub const BODY_LAYER: u32 = 1 << 1;
+
+/// Collidables only form a pair when each one's memberships overlap the other's filters, so unwanted pairs are skipped during detection instead of after it.
+/// Collidables without this component are on every layer and collide with every layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub struct CollisionLayers {
+    /// Bitmask of the layers this collidable is on
+    pub memberships: u32,
+    /// Bitmask of the layers this collidable can collide with
+    pub filters: u32,
+}
+
+impl CollisionLayers {
+    pub const ALL: CollisionLayers = CollisionLayers {
+        memberships: u32::MAX,
+        filters: u32::MAX,
+    };
+
+    pub fn new(memberships: u32, filters: u32) -> Self {
+        CollisionLayers {
+            memberships,
+            filters,
+        }
+    }
+
+    /// Must match `layers_interact` in the shader
+    pub fn interacts_with(&self, other: &CollisionLayers) -> bool {
+        (self.memberships & other.filters) != 0 && (other.memberships & self.filters) != 0
+    }
+}
+
+impl Default for CollisionLayers {
+    fn default() -> Self {
+        CollisionLayers::ALL
+    }
+}
+
+/// The `CollisionLayers` of collidables without the component. Set by the detection plugins
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Resource)]
+pub struct DefaultCollisionLayers(pub CollisionLayers);
+
 #[derive(Resource)]
 pub struct NumEntitiesSpawned(pub usize);
+
+/// Whether the collision detection also finds the `Contact` of every colliding pair. Set by the detection plugins, and can be changed at runtime
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Resource)]
+pub struct ComputeContacts(pub bool);
+
+/// Where the collidable's `Transform` was the last time collisions were detected. Only kept while continuous collision detection is on, which inserts and updates it
+#[derive(Debug, Clone, Copy, Component)]
+pub struct PreviousPosition(pub Vec2);
+
+/// Whether circles are swept from their `PreviousPosition` to their current one, so fast ones can't pass through each other between frames. Set by the detection plugins, and can be changed at runtime
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Resource)]
+pub struct ContinuousCollisionDetection(pub bool);
+
+/// Any value to carry through to the `CollidableMetadata` of the collidable's pairs, so collision responses don't have to look it up again. Cloning only clones the `Arc`
+#[derive(Clone, Component)]
+pub struct CollisionUserData(Arc<dyn Any + Send + Sync>);
+
+impl CollisionUserData {
+    pub fn new<T: Send + Sync + 'static>(data: T) -> Self {
+        CollisionUserData(Arc::new(data))
+    }
+
+    /// `None` if the data is not a `T`
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.0.downcast_ref()
+    }
+}
+
+impl std::fmt::Debug for CollisionUserData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CollisionUserData(..)")
+    }
+}
+
+/// Only equal to clones of itself
+impl PartialEq for CollisionUserData {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
@@ -8,6 +8,7 @@
 // pub const NUM_FRAMES_TO_TEST: u32 = 3;
 
 use bevy::prelude::Resource;
+use gpu_accelerated_collision_detection::collision_detection_plugin::CollisionDetectionMethod;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Clone, Deserialize, Resource)]
@@ -21,5 +22,8 @@ pub struct RunConfig {
     pub rng_seed: u32,
     pub num_frames_to_test: u32,
     pub use_gpu: bool,
+    /// Overrides `use_gpu`, for the methods other than `Cpu` and `Gpu`
+    #[serde(default)]
+    pub method: Option<CollisionDetectionMethod>,
     pub path_to_output_json: String,
 }
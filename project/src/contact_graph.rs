@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use bevy::{core::FrameCount, ecs::entity::Entities, prelude::*};
+
+use crate::colliding_pair::{CollidingPairs, EntityPair};
+
+/// How long a pair has been colliding without a break
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveContact {
+    /// `FrameCount` of the first frame of this contact
+    pub first_seen_frame: u32,
+    /// Frames in a row the pair has been colliding, including the current one
+    pub frames: u32,
+    /// Sum of the frame times of those frames
+    pub seconds: f32,
+    /// Deepest `Contact::depth` seen during this contact. Only kept while `ComputeContacts` is on
+    pub peak_depth: Option<f32>,
+}
+
+/// Every pair colliding this frame, with how long it has been colliding. Updated from `CollidingPairs` every frame, so a pair that stops colliding for a single frame starts over
+#[derive(Debug, Default, Resource)]
+pub struct ContactGraph(pub HashMap<EntityPair, ActiveContact>);
+
+impl ContactGraph {
+    pub fn get(&self, a: Entity, b: Entity) -> Option<&ActiveContact> {
+        self.0.get(&EntityPair::new(a, b))
+    }
+
+    /// Every entity colliding with `entity`, with their contact
+    pub fn contacts_of(&self, entity: Entity) -> impl Iterator<Item = (Entity, &ActiveContact)> {
+        self.0
+            .iter()
+            .filter_map(move |(pair, contact)| Some((pair.other(entity)?, contact)))
+    }
+}
+
+pub fn update_contact_graph(
+    collisions: Res<CollidingPairs>,
+    frame: Res<FrameCount>,
+    time: Res<Time>,
+    entities: &Entities,
+    mut graph: ResMut<ContactGraph>,
+) {
+    let mut current = HashMap::with_capacity(collisions.0.len());
+    for collision in collisions.0.iter() {
+        let pair = collision.entity_pair();
+        let depth = collision.contact.map(|contact| contact.depth);
+        // the same pair can be found twice, which must not count as two frames
+        let contact = current
+            .entry(pair)
+            .or_insert_with(|| match graph.0.get(&pair) {
+                Some(previous) => ActiveContact {
+                    frames: previous.frames + 1,
+                    seconds: previous.seconds + time.delta_secs(),
+                    ..*previous
+                },
+                None => ActiveContact {
+                    first_seen_frame: frame.0,
+                    frames: 1,
+                    seconds: time.delta_secs(),
+                    peak_depth: None,
+                },
+            });
+        contact.peak_depth = match (contact.peak_depth, depth) {
+            (Some(peak), Some(depth)) => Some(peak.max(depth)),
+            (peak, depth) => peak.or(depth),
+        };
+    }
+    // entities despawned since detection ran
+    current.retain(|pair, _| {
+        pair.entities()
+            .iter()
+            .all(|entity| entities.contains(*entity))
+    });
+    graph.0 = current;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{math::bounding::BoundingCircle, time::TimeUpdateStrategy};
+
+    use super::*;
+    use crate::{
+        components_and_resources::BoundingCircleComponent,
+        cpu_collision_detection::cpu_collision_detection::CpuCollisionDetectionPlugin,
+    };
+
+    fn circle(x: f32) -> (Transform, BoundingCircleComponent) {
+        (
+            Transform::from_xyz(x, 0., 0.),
+            BoundingCircleComponent(BoundingCircle::new(Vec2::new(x, 0.), 1.)),
+        )
+    }
+
+    #[test]
+    fn test_contact_age_and_peak_depth() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            CpuCollisionDetectionPlugin::default().with_contacts(),
+        ))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+            250,
+        )))
+        .insert_resource(CollidingPairs(Vec::new()));
+        let a = app.world_mut().spawn(circle(0.)).id();
+        let b = app.world_mut().spawn(circle(10.)).id();
+        let c = app.world_mut().spawn(circle(1.5)).id();
+        app.update();
+        app.update();
+        app.world_mut().entity_mut(b).insert(circle(1.));
+        app.update();
+        app.world_mut().entity_mut(b).insert(circle(1.8));
+        app.update();
+        app.update();
+        let graph = app.world().resource::<ContactGraph>();
+        let contact = graph.get(b, a).unwrap();
+        assert_eq!(contact.frames, 3);
+        assert_eq!(
+            contact.first_seen_frame,
+            graph.get(a, c).unwrap().first_seen_frame + 2
+        );
+        assert!((contact.seconds - 0.75).abs() < 1e-6);
+        assert!((contact.peak_depth.unwrap() - 1.).abs() < 1e-5);
+        assert_eq!(graph.get(a, c).unwrap().frames, 5);
+        let mut others: Vec<_> = graph.contacts_of(a).map(|(other, _)| other).collect();
+        others.sort();
+        assert_eq!(others, vec![b, c]);
+
+        app.world_mut().entity_mut(b).insert(circle(10.));
+        app.update();
+        app.world_mut().entity_mut(b).insert(circle(1.));
+        app.update();
+        assert_eq!(
+            app.world()
+                .resource::<ContactGraph>()
+                .get(a, b)
+                .unwrap()
+                .frames,
+            1
+        );
+        app.world_mut().despawn(c);
+        app.update();
+        let graph = app.world().resource::<ContactGraph>();
+        assert!(graph.get(a, c).is_none());
+        assert_eq!(graph.contacts_of(a).count(), 1);
+    }
+}
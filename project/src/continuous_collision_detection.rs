@@ -0,0 +1,23 @@
+use bevy::prelude::{Commands, Entity, Query, Transform, With};
+
+use crate::components_and_resources::{BoundingCircleComponent, PreviousPosition};
+
+/// Runs after detection, so the next frame's sweep starts where this frame's detection saw each circle, however it was moved in between.
+/// Circles without a `PreviousPosition` get one, and are only swept from the next frame on
+pub fn store_previous_positions(
+    mut commands: Commands,
+    mut query: Query<
+        (Entity, &Transform, Option<&mut PreviousPosition>),
+        With<BoundingCircleComponent>,
+    >,
+) {
+    for (entity, transform, previous) in query.iter_mut() {
+        let position = transform.translation.truncate();
+        match previous {
+            Some(mut previous) => previous.0 = position,
+            None => {
+                commands.entity(entity).insert(PreviousPosition(position));
+            }
+        }
+    }
+}
@@ -0,0 +1,531 @@
+use bevy::math::{
+    Vec2,
+    bounding::{Aabb2d, BoundingCircle, BoundingVolume, IntersectsVolume},
+    primitives::Capsule2d,
+};
+
+use crate::colliding_pair::Contact;
+
+/// A collidable's shape on the CPU, in world space. Must give the same results as the shader's narrow phase.
+#[derive(Debug, Clone)]
+pub enum Collider {
+    Circle(BoundingCircle),
+    Box(Aabb2d),
+    Capsule {
+        center: Vec2,
+        capsule: Capsule2d,
+    },
+    /// Vertices are relative to `center`, and have to pass `is_convex_polygon`
+    ConvexPolygon {
+        center: Vec2,
+        vertices: Vec<Vec2>,
+    },
+}
+
+impl Collider {
+    pub fn center(&self) -> Vec2 {
+        match self {
+            Collider::Circle(circle) => circle.center(),
+            Collider::Box(aabb) => aabb.center(),
+            Collider::Capsule { center, .. } | Collider::ConvexPolygon { center, .. } => *center,
+        }
+    }
+
+    pub fn bounding_circle(&self) -> BoundingCircle {
+        match self {
+            Collider::Circle(circle) => *circle,
+            Collider::Box(aabb) => BoundingCircle::new(aabb.center(), aabb.half_size().length()),
+            Collider::Capsule { center, capsule } => {
+                BoundingCircle::new(*center, capsule.radius + capsule.half_length)
+            }
+            Collider::ConvexPolygon { center, vertices } => BoundingCircle::new(
+                *center,
+                vertices.iter().map(|v| v.length()).fold(0., f32::max),
+            ),
+        }
+    }
+
+    /// Bounding circles are compared first, circles and boxes then use bevy's tests, every other pair compares the distance between their cores with the sum of their radii
+    pub fn intersects(&self, other: &Collider) -> bool {
+        if !self.bounding_circle().intersects(&other.bounding_circle()) {
+            return false;
+        }
+        match (self, other) {
+            (Collider::Circle(a), Collider::Circle(b)) => a.intersects(b),
+            (Collider::Box(a), Collider::Box(b)) => a.intersects(b),
+            (Collider::Circle(circle), Collider::Box(aabb))
+            | (Collider::Box(aabb), Collider::Circle(circle)) => aabb.intersects(circle),
+            _ => {
+                let radius_sum = self.core_radius() + other.core_radius();
+                core_distance_squared(self, other) <= radius_sum * radius_sum
+            }
+        }
+    }
+
+    /// The contact of a pair that intersects, with the normal pointing towards `other`. Must give the same results as the shader.
+    ///
+    /// Apart cores give the contact along their closest points. Overlapping cores use the axis they overlap the least on, out of the edge normals of both cores and the y axis
+    pub fn contact(&self, other: &Collider) -> Contact {
+        let (a_vertices, b_vertices) = (self.core_vertices(), other.core_vertices());
+        let a_edges = edges(&a_vertices, self.is_closed());
+        let b_edges = edges(&b_vertices, other.is_closed());
+        let (a_radius, b_radius) = (self.core_radius(), other.core_radius());
+        let mut cores_overlap = (self.is_closed()
+            && convex_polygon_contains(&a_vertices, b_vertices[0]))
+            || (other.is_closed() && convex_polygon_contains(&b_vertices, a_vertices[0]));
+        // the closest points of two segments that don't cross include an endpoint of one of them
+        let mut closest = (f32::INFINITY, a_vertices[0], b_vertices[0]);
+        for (p, q) in &a_edges {
+            for (r, s) in &b_edges {
+                cores_overlap |= segments_cross(*p, *q, *r, *s);
+                for (on_a, on_b) in [
+                    (*p, closest_point_on_segment(*p, *r, *s)),
+                    (*q, closest_point_on_segment(*q, *r, *s)),
+                    (closest_point_on_segment(*r, *p, *q), *r),
+                    (closest_point_on_segment(*s, *p, *q), *s),
+                ] {
+                    let distance_squared = on_a.distance_squared(on_b);
+                    if distance_squared < closest.0 {
+                        closest = (distance_squared, on_a, on_b);
+                    }
+                }
+            }
+        }
+        let (normal, depth, point) = if !cores_overlap && closest.0 > 0. {
+            let distance = closest.0.sqrt();
+            let normal = (closest.2 - closest.1) / distance;
+            let depth = a_radius + b_radius - distance;
+            (normal, depth, closest.1 + normal * (a_radius - depth * 0.5))
+        } else {
+            let project = |vertices: &[Vec2], axis: Vec2| {
+                vertices
+                    .iter()
+                    .map(|v| v.dot(axis))
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), d| {
+                        (min.min(d), max.max(d))
+                    })
+            };
+            let (mut normal, mut depth) = (Vec2::Y, f32::INFINITY);
+            for axis in a_edges
+                .iter()
+                .chain(&b_edges)
+                .map(|(p, q)| (*q - *p).perp())
+                .chain([Vec2::Y])
+            {
+                if axis.length_squared() <= 0. {
+                    continue;
+                }
+                let axis = axis / axis.length();
+                let (a_min, a_max) = project(&a_vertices, axis);
+                let (b_min, b_max) = project(&b_vertices, axis);
+                // how far the other core has to move forwards or backwards along the axis to stop overlapping
+                let (forward, backward) = (a_max - b_min, b_max - a_min);
+                let (axis_depth, axis_normal) = if backward < forward {
+                    (backward, -axis)
+                } else {
+                    (forward, axis)
+                };
+                if axis_depth + a_radius + b_radius < depth {
+                    depth = axis_depth + a_radius + b_radius;
+                    normal = axis_normal;
+                }
+            }
+            let deepest = b_vertices
+                .iter()
+                .copied()
+                .reduce(|deepest, v| {
+                    if v.dot(normal) < deepest.dot(normal) {
+                        v
+                    } else {
+                        deepest
+                    }
+                })
+                .unwrap();
+            (
+                normal,
+                depth,
+                deepest - normal * b_radius + normal * depth * 0.5,
+            )
+        };
+        Contact {
+            normal: normal.extend(0.),
+            depth,
+            point: point.extend(0.),
+        }
+    }
+
+    /// The shape is every point within this distance of its core
+    fn core_radius(&self) -> f32 {
+        match self {
+            Collider::Circle(circle) => circle.radius(),
+            Collider::Capsule { capsule, .. } => capsule.radius,
+            Collider::Box(_) | Collider::ConvexPolygon { .. } => 0.,
+        }
+    }
+
+    /// A point for circles, a segment for capsules, and a closed polygon for boxes and polygons
+    fn core_vertices(&self) -> Vec<Vec2> {
+        match self {
+            Collider::Circle(circle) => vec![circle.center()],
+            Collider::Box(aabb) => vec![
+                aabb.min,
+                Vec2::new(aabb.max.x, aabb.min.y),
+                aabb.max,
+                Vec2::new(aabb.min.x, aabb.max.y),
+            ],
+            Collider::Capsule { center, capsule } => vec![
+                *center - Vec2::Y * capsule.half_length,
+                *center + Vec2::Y * capsule.half_length,
+            ],
+            Collider::ConvexPolygon { center, vertices } => {
+                vertices.iter().map(|v| *center + *v).collect()
+            }
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        matches!(self, Collider::Box(_) | Collider::ConvexPolygon { .. })
+    }
+}
+
+/// At least 3 finite vertices going around a convex shape with some area once, in either winding order. Consecutive collinear vertices are allowed
+pub fn is_convex_polygon(vertices: &[Vec2]) -> bool {
+    if vertices.len() < 3 || !vertices.iter().all(|v| v.is_finite()) {
+        return false;
+    }
+    let mut turns = 0.;
+    let mut sign = 0.;
+    for i in 0..vertices.len() {
+        let a = vertices[(i + 1) % vertices.len()] - vertices[i];
+        let b = vertices[(i + 2) % vertices.len()] - vertices[(i + 1) % vertices.len()];
+        let cross = a.perp_dot(b);
+        if cross != 0. {
+            if sign * cross < 0. {
+                return false;
+            }
+            sign = cross.signum();
+        }
+        turns += a.angle_to(b);
+    }
+    // a star turns the same way at every vertex too, but goes around more than once
+    (turns.abs() - std::f32::consts::TAU).abs() < 1e-3
+}
+
+fn core_distance_squared(a: &Collider, b: &Collider) -> f32 {
+    let (a_vertices, b_vertices) = (a.core_vertices(), b.core_vertices());
+    if (a.is_closed() && convex_polygon_contains(&a_vertices, b_vertices[0]))
+        || (b.is_closed() && convex_polygon_contains(&b_vertices, a_vertices[0]))
+    {
+        return 0.;
+    }
+    let b_edges = edges(&b_vertices, b.is_closed());
+    edges(&a_vertices, a.is_closed())
+        .iter()
+        .flat_map(|(p, q)| {
+            b_edges
+                .iter()
+                .map(|(r, s)| segments_distance_squared(*p, *q, *r, *s))
+        })
+        .fold(f32::INFINITY, f32::min)
+}
+
+fn edges(vertices: &[Vec2], closed: bool) -> Vec<(Vec2, Vec2)> {
+    if !closed {
+        return vec![(vertices[0], vertices[vertices.len() - 1])];
+    }
+    (0..vertices.len())
+        .map(|i| (vertices[i], vertices[(i + 1) % vertices.len()]))
+        .collect()
+}
+
+/// Points on an edge are inside
+fn convex_polygon_contains(vertices: &[Vec2], point: Vec2) -> bool {
+    let sides: Vec<f32> = edges(vertices, true)
+        .iter()
+        .map(|(a, b)| (*b - *a).perp_dot(point - *a))
+        .collect();
+    sides.iter().all(|side| *side >= 0.) || sides.iter().all(|side| *side <= 0.)
+}
+
+/// How far through their movement two circles moving in straight lines first overlap, from 0 to 1, or None if they don't. Must give the same results as the shader, where touching doesn't count
+pub fn swept_circles_time_of_impact(
+    a_start: Vec2,
+    a_end: Vec2,
+    a_radius: f32,
+    b_start: Vec2,
+    b_end: Vec2,
+    b_radius: f32,
+) -> Option<f32> {
+    if a_radius <= 0. || b_radius <= 0. {
+        return None;
+    }
+    // b's start and movement relative to a
+    let start = b_start - a_start;
+    let movement = (b_end - b_start) - (a_end - a_start);
+    let radius_sum = a_radius + b_radius;
+    // the distance squared minus the radius sum squared is a quadratic in the time, with these coefficients (b halved)
+    let c = start.length_squared() - radius_sum * radius_sum;
+    if c < 0. {
+        return Some(0.);
+    }
+    let a = movement.length_squared();
+    let b = start.dot(movement);
+    let discriminant = b * b - a * c;
+    if b >= 0. || discriminant <= 0. {
+        return None;
+    }
+    let t = (-b - discriminant.sqrt()) / a;
+    (t < 1.).then_some(t)
+}
+
+/// Segments that only touch don't cross
+fn segments_cross(a: Vec2, b: Vec2, c: Vec2, d: Vec2) -> bool {
+    let straddles = |p: Vec2, q: Vec2, r: Vec2, s: Vec2| {
+        let o1 = (q - p).perp_dot(r - p);
+        let o2 = (q - p).perp_dot(s - p);
+        (o1 > 0. && o2 < 0.) || (o1 < 0. && o2 > 0.)
+    };
+    straddles(c, d, a, b) && straddles(a, b, c, d)
+}
+
+fn segments_distance_squared(a: Vec2, b: Vec2, c: Vec2, d: Vec2) -> f32 {
+    if segments_cross(a, b, c, d) {
+        return 0.;
+    }
+    point_segment_distance_squared(a, c, d)
+        .min(point_segment_distance_squared(b, c, d))
+        .min(point_segment_distance_squared(c, a, b))
+        .min(point_segment_distance_squared(d, a, b))
+}
+
+fn point_segment_distance_squared(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    p.distance_squared(closest_point_on_segment(p, a, b))
+}
+
+fn closest_point_on_segment(p: Vec2, a: Vec2, b: Vec2) -> Vec2 {
+    let ab = b - a;
+    let length_squared = ab.length_squared();
+    let t = if length_squared > 0. {
+        ((p - a).dot(ab) / length_squared).clamp(0., 1.)
+    } else {
+        0.
+    };
+    a + ab * t
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::Vec3;
+
+    use super::*;
+
+    fn triangle(center: Vec2) -> Collider {
+        Collider::ConvexPolygon {
+            center,
+            vertices: vec![Vec2::new(-2., -1.), Vec2::new(2., -1.), Vec2::new(0., 2.)],
+        }
+    }
+
+    #[test]
+    fn test_capsule_against_other_shapes() {
+        let capsule = Collider::Capsule {
+            center: Vec2::ZERO,
+            capsule: Capsule2d {
+                radius: 1.,
+                half_length: 2.,
+            },
+        };
+        // the capsule reaches y = 3 at the top and x = 1 at the sides
+        let above = Collider::Circle(BoundingCircle::new(Vec2::new(0., 3.9), 1.));
+        let too_far_above = Collider::Circle(BoundingCircle::new(Vec2::new(0., 4.1), 1.));
+        let beside = Collider::Box(Aabb2d::new(Vec2::new(1.5, 0.), Vec2::splat(0.5)));
+        let too_far_beside = Collider::Box(Aabb2d::new(Vec2::new(1.6, 0.), Vec2::splat(0.5)));
+        // the bounding circles overlap, but the box's corner (0.8, 2.8) is farther than the capsule's radius from its top end
+        let off_the_corner = Collider::Box(Aabb2d::new(Vec2::new(1.3, 3.3), Vec2::splat(0.5)));
+        assert!(capsule.intersects(&above));
+        assert!(!capsule.intersects(&too_far_above));
+        assert!(capsule.intersects(&beside));
+        assert!(!capsule.intersects(&too_far_beside));
+        assert!(!capsule.intersects(&off_the_corner));
+        assert!(capsule.intersects(&triangle(Vec2::new(0., -3.9))));
+        assert!(!capsule.intersects(&triangle(Vec2::new(0., -5.1))));
+    }
+
+    #[test]
+    fn test_convex_polygons() {
+        let big = Collider::ConvexPolygon {
+            center: Vec2::ZERO,
+            vertices: vec![
+                Vec2::new(-5., -5.),
+                Vec2::new(5., -5.),
+                Vec2::new(5., 5.),
+                Vec2::new(-5., 5.),
+            ],
+        };
+        // fully inside, so none of the edges are close to each other
+        assert!(big.intersects(&triangle(Vec2::ZERO)));
+        assert!(triangle(Vec2::ZERO).intersects(&big));
+        let point_circle = Collider::Circle(BoundingCircle::new(Vec2::new(1., 1.), 0.));
+        assert!(big.intersects(&point_circle));
+        // the first triangle's tip pokes through the second one's bottom edge
+        assert!(triangle(Vec2::ZERO).intersects(&triangle(Vec2::new(0., 2.5))));
+        // side by side with a 0.1 gap
+        assert!(!triangle(Vec2::ZERO).intersects(&triangle(Vec2::new(4.1, 0.))));
+    }
+
+    #[test]
+    fn test_polygon_validation() {
+        let square = [
+            Vec2::new(-1., -1.),
+            Vec2::new(0., -1.),
+            Vec2::new(1., -1.),
+            Vec2::new(1., 1.),
+            Vec2::new(-1., 1.),
+        ];
+        assert!(is_convex_polygon(&square));
+        let mut clockwise = square;
+        clockwise.reverse();
+        assert!(is_convex_polygon(&clockwise));
+        assert!(!is_convex_polygon(&[]));
+        assert!(!is_convex_polygon(&square[..2]));
+        // degenerate, all on one line
+        assert!(!is_convex_polygon(&[
+            Vec2::ZERO,
+            Vec2::new(1., 0.),
+            Vec2::new(2., 0.)
+        ]));
+        assert!(!is_convex_polygon(&[
+            Vec2::ZERO,
+            Vec2::new(1., 0.),
+            Vec2::new(f32::NAN, 1.)
+        ]));
+        // concave
+        assert!(!is_convex_polygon(&[
+            Vec2::new(-1., -1.),
+            Vec2::new(1., -1.),
+            Vec2::new(0., -0.5),
+            Vec2::new(0., 1.),
+        ]));
+        let star: Vec<Vec2> = (0..5)
+            .map(|i| Vec2::from_angle(i as f32 * 2. * std::f32::consts::TAU / 5.))
+            .collect();
+        assert!(!is_convex_polygon(&star));
+    }
+
+    fn moved(collider: &Collider, offset: Vec2) -> Collider {
+        match collider {
+            Collider::Circle(circle) => Collider::Circle(BoundingCircle::new(
+                circle.center() + offset,
+                circle.radius(),
+            )),
+            Collider::Box(aabb) => {
+                Collider::Box(Aabb2d::new(aabb.center() + offset, aabb.half_size()))
+            }
+            Collider::Capsule { center, capsule } => Collider::Capsule {
+                center: *center + offset,
+                capsule: *capsule,
+            },
+            Collider::ConvexPolygon { center, vertices } => Collider::ConvexPolygon {
+                center: *center + offset,
+                vertices: vertices.clone(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_contacts() {
+        let circle = Collider::Circle(BoundingCircle::new(Vec2::ZERO, 1.));
+        let contact = circle.contact(&Collider::Circle(BoundingCircle::new(
+            Vec2::new(1.5, 0.),
+            1.,
+        )));
+        assert_eq!(contact.normal, Vec3::X);
+        assert_eq!(contact.depth, 0.5);
+        assert_eq!(contact.point, Vec3::new(0.75, 0., 0.));
+        // the boxes overlap by 0.5 in x and 1.5 in y
+        let unit_box = Collider::Box(Aabb2d::new(Vec2::ZERO, Vec2::ONE));
+        let contact = unit_box.contact(&Collider::Box(Aabb2d::new(Vec2::new(1.5, 0.5), Vec2::ONE)));
+        assert_eq!(contact.normal, Vec3::X);
+        assert_eq!(contact.depth, 0.5);
+        assert_eq!(contact.point, Vec3::new(0.75, -0.5, 0.));
+
+        // moving the second collider by the depth along the normal leaves the pair at most touching
+        let capsule = Collider::Capsule {
+            center: Vec2::ZERO,
+            capsule: Capsule2d {
+                radius: 1.,
+                half_length: 2.,
+            },
+        };
+        let colliders = [
+            circle,
+            unit_box,
+            capsule,
+            triangle(Vec2::ZERO),
+            Collider::Circle(BoundingCircle::new(Vec2::ZERO, 0.5)),
+        ];
+        for a in &colliders {
+            for b in &colliders {
+                for offset in [
+                    Vec2::ZERO,
+                    Vec2::new(0.3, -0.2),
+                    Vec2::new(-1.1, 1.7),
+                    Vec2::new(2.2, 0.4),
+                ] {
+                    let b = moved(b, offset);
+                    if !a.intersects(&b) {
+                        continue;
+                    }
+                    let contact = a.contact(&b);
+                    assert!((contact.normal.length() - 1.).abs() < 1e-5, "{a:?} {b:?}");
+                    assert!(contact.depth >= 0., "{a:?} {b:?}");
+                    let pushed_out = contact.normal.truncate() * (contact.depth + 0.01);
+                    assert!(!a.intersects(&moved(&b, pushed_out)), "{a:?} {b:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_swept_circles() {
+        let time_of_impact = |b_start: Vec2, b_end: Vec2| {
+            swept_circles_time_of_impact(Vec2::ZERO, Vec2::ZERO, 1., b_start, b_end, 1.)
+        };
+        // passes straight through, first touching at x = -2
+        assert_eq!(
+            time_of_impact(Vec2::new(-10., 0.), Vec2::new(10., 0.)),
+            Some(0.4)
+        );
+        assert_eq!(
+            time_of_impact(Vec2::new(1., 0.), Vec2::new(10., 0.)),
+            Some(0.)
+        );
+        assert_eq!(time_of_impact(Vec2::new(10., 0.), Vec2::new(20., 0.)), None);
+        // stops short, and passes by 2.5 away
+        assert_eq!(
+            time_of_impact(Vec2::new(-10., 0.), Vec2::new(-3., 0.)),
+            None
+        );
+        assert_eq!(
+            time_of_impact(Vec2::new(-10., 2.5), Vec2::new(10., 2.5)),
+            None
+        );
+        // only touches on the way past
+        assert_eq!(
+            time_of_impact(Vec2::new(-10., 2.), Vec2::new(10., 2.)),
+            None
+        );
+        // a moving into b gives the same time as b moving into a
+        assert_eq!(
+            swept_circles_time_of_impact(
+                Vec2::new(-10., 0.),
+                Vec2::new(10., 0.),
+                1.,
+                Vec2::ZERO,
+                Vec2::ZERO,
+                1.
+            ),
+            Some(0.4)
+        );
+    }
+}
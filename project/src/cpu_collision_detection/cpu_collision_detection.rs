@@ -1,78 +1,349 @@
+use super::{
+    collider::Collider,
+    cpu_dynamic_tree::{CpuDynamicTree, update_cpu_dynamic_tree},
+};
 use crate::{
+    colliding_entities::TrackCollidingEntities,
     colliding_pair::{CollidingPair, CollidingPairs},
-    collision_processing::process_collisions,
-    components_and_resources::{BoundingCircleComponent, Sensor},
+    collision_core::{
+        detect::{Collidable, DetectOptions, DetectedPair, JobBuffers, detect_pairs_into},
+        grid::detect_pairs_grid,
+        sweep_and_prune::SweepAndPrune,
+    },
+    collision_detection_plugin::{CollisionDetectionSet, CpuDetectionSet},
+    collision_events::CollisionEventsPlugin,
+    components_and_resources::{
+        BoundingBoxComponent, BoundingCircleComponent, CapsuleComponent, CollisionLayers,
+        CollisionUserData, ComputeContacts, ContinuousCollisionDetection, ConvexPolygonComponent,
+        DefaultCollisionLayers, PreviousPosition, Sensor,
+    },
+    continuous_collision_detection::store_previous_positions,
+    deterministic_collisions::canonicalize_colliding_pairs,
     gpu_collision_detection::entity_metadata::CollidableMetadata,
 };
-use bevy::{math::bounding::IntersectsVolume, prelude::*};
-use rayon::{iter::ParallelIterator, slice::ParallelSlice};
-use std::{
-    cmp::max,
-    sync::{Arc, Mutex},
+use bevy::{
+    ecs::query::QueryItem,
+    log,
+    math::bounding::{Aabb2d, BoundingCircle, BoundingVolume},
+    prelude::*,
 };
 
-pub struct CpuCollisionDetectionPlugin;
+/// How the CPU plugin finds candidate pairs before testing them, the CPU counterpart of `GpuBroadphase`. Every broadphase finds the same pairs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Resource)]
+pub enum CpuBroadphase {
+    /// Every pair is tested, in parallel with rayon
+    #[default]
+    BruteForce,
+    /// Collidables are binned into a uniform grid sized from the largest one, and only pairs in neighboring cells are tested
+    Grid,
+    /// Collidables are kept sorted along x from frame to frame, and only pairs overlapping along x are tested
+    SweepAndPrune,
+    /// Collidables are kept in a `CpuDynamicTree` updated as they move, and only pairs whose leaves overlap are tested. Unlike the grid it doesn't mind collidables of very different sizes
+    DynamicTree,
+}
+
+#[derive(Default)]
+pub struct CpuCollisionDetectionPlugin {
+    /// Held in the `CpuBroadphase` resource, so it can be changed at runtime
+    pub broadphase: CpuBroadphase,
+    /// Also find the `Contact` of every pair. Held in the `ComputeContacts` resource, so it can be changed at runtime
+    pub compute_contacts: bool,
+    /// Sweep circles from where they were last frame, see `ContinuousCollisionDetection`
+    pub continuous: bool,
+    /// Also trigger `CollisionStarted` and `CollisionEnded` for observers, see `CollisionObservers`
+    pub collision_observers: bool,
+    /// Which collidables get a `CollidingEntities` component
+    pub colliding_entities: TrackCollidingEntities,
+    /// Layers of the collidables without `CollisionLayers`, see `DefaultCollisionLayers`
+    pub default_layers: CollisionLayers,
+    /// Sort `CollidingPairs` and hash them every frame, see `DeterministicCollisions`
+    pub deterministic: bool,
+}
 
 impl Plugin for CpuCollisionDetectionPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, detect_collisions_cpu.before(process_collisions));
+        app.insert_resource(self.broadphase)
+            .insert_resource(ComputeContacts(self.compute_contacts))
+            .insert_resource(ContinuousCollisionDetection(self.continuous))
+            .insert_resource(DefaultCollisionLayers(self.default_layers))
+            .init_resource::<CpuDynamicTree>()
+            .add_systems(
+                Update,
+                (
+                    update_cpu_dynamic_tree
+                        .before(detect_collisions_cpu)
+                        .run_if(resource_equals(CpuBroadphase::DynamicTree)),
+                    detect_collisions_cpu.before(canonicalize_colliding_pairs),
+                    store_previous_positions
+                        .after(detect_collisions_cpu)
+                        .run_if(resource_equals(ContinuousCollisionDetection(true))),
+                )
+                    .in_set(CpuDetectionSet)
+                    .in_set(CollisionDetectionSet),
+            );
+        // already there when composed with the GPU plugin for `CollisionDetectionMethod::Auto`
+        if !app.is_plugin_added::<CollisionEventsPlugin>() {
+            app.add_plugins(CollisionEventsPlugin {
+                observers: self.collision_observers,
+                colliding_entities: self.colliding_entities,
+                deterministic: self.deterministic,
+            });
+        }
     }
 }
 
+impl CpuCollisionDetectionPlugin {
+    pub fn with_broadphase(mut self, broadphase: CpuBroadphase) -> Self {
+        self.broadphase = broadphase;
+        self
+    }
+
+    pub fn with_contacts(mut self) -> Self {
+        self.compute_contacts = true;
+        self
+    }
+
+    pub fn with_continuous_collision_detection(mut self) -> Self {
+        self.continuous = true;
+        self
+    }
+
+    pub fn with_collision_observers(mut self) -> Self {
+        self.collision_observers = true;
+        self
+    }
+
+    pub fn with_colliding_entities(mut self, tracking: TrackCollidingEntities) -> Self {
+        self.colliding_entities = tracking;
+        self
+    }
+
+    pub fn with_default_layers(mut self, layers: CollisionLayers) -> Self {
+        self.default_layers = layers;
+        self
+    }
+
+    pub fn with_deterministic_order(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+}
+
+/// The 2D colliders the CPU plugin reads
+pub type WithCpuCollider = Or<(
+    With<BoundingCircleComponent>,
+    With<BoundingBoxComponent>,
+    With<CapsuleComponent>,
+    With<ConvexPolygonComponent>,
+)>;
+
+/// What `collidable_of` needs from a collidable
+pub type CpuColliderData = (
+    &'static Transform,
+    Option<&'static BoundingCircleComponent>,
+    Option<&'static BoundingBoxComponent>,
+    Option<&'static CapsuleComponent>,
+    Option<&'static ConvexPolygonComponent>,
+    Option<&'static PreviousPosition>,
+);
+
+/// The collidable on every layer, with the same order of precedence between colliders as get_collidables. Like on the GPU, every collider is centered on the `Transform`
+pub fn collidable_of(
+    (transform, bounding_circle, bounding_box, capsule, polygon, previous): QueryItem<
+        CpuColliderData,
+    >,
+) -> Collidable {
+    let center = transform.translation.truncate();
+    let collider = match (bounding_circle, bounding_box, capsule, polygon) {
+        (Some(bounding_circle), ..) => {
+            Collider::Circle(BoundingCircle::new(center, bounding_circle.0.radius()))
+        }
+        (None, Some(bounding_box), ..) => {
+            Collider::Box(Aabb2d::new(center, bounding_box.0.half_size()))
+        }
+        (None, None, Some(capsule), _) => Collider::Capsule {
+            center,
+            capsule: capsule.0,
+        },
+        (None, None, None, Some(polygon)) => Collider::ConvexPolygon {
+            center,
+            vertices: polygon.0.clone(),
+        },
+        (None, None, None, None) => unreachable!("filtered out by the query"),
+    };
+    Collidable::new(collider).with_previous_center(previous.map_or(center, |previous| previous.0))
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 fn detect_collisions_cpu(
-    collidable_query: Query<(Entity, &BoundingCircleComponent, Option<&Sensor>)>,
+    collidable_query: Query<
+        (
+            Entity,
+            CpuColliderData,
+            Option<&Sensor>,
+            Option<&CollisionLayers>,
+            Option<&CollisionUserData>,
+        ),
+        WithCpuCollider,
+    >,
+    broadphase: Res<CpuBroadphase>,
+    mut sweep_and_prune: Local<SweepAndPrune>,
+    mut dynamic_tree: ResMut<CpuDynamicTree>,
+    compute_contacts: Res<ComputeContacts>,
+    default_layers: Res<DefaultCollisionLayers>,
+    continuous: Res<ContinuousCollisionDetection>,
     mut collisions: ResMut<CollidingPairs>,
+    mut collidables: Local<Vec<Collidable>>,
+    mut metadata: Local<Vec<CollidableMetadata>>,
+    mut entities: Local<Vec<Entity>>,
+    mut job_buffers: Local<JobBuffers>,
+    mut pairs: Local<Vec<DetectedPair>>,
 ) {
-    let collisions_shared: Arc<Mutex<Vec<CollidingPair>>> = Arc::new(Mutex::new(Vec::new()));
-    let entities: Vec<_> = collidable_query.iter().enumerate().collect();
-    let num_threads = rayon::current_num_threads();
-    let batch_size = max(entities.len() / num_threads, 1);
-    entities.par_chunks(batch_size).for_each(|chunk| {
-        chunk
-            .iter()
-            .for_each(|(i, (entity, bounding_circle, sensor))| {
-                let collisions_inner: Arc<Mutex<Vec<CollidingPair>>> =
-                    Arc::new(Mutex::new(Vec::new()));
-
-                // Only check against entities with higher indices
-                entities[i + 1..]
-                    .par_chunks(batch_size)
-                    .for_each(|other_chunk| {
-                        other_chunk.iter().for_each(
-                            |(_k, (other_entity, other_bounding_circle, other_sensor))| {
-                                if bounding_circle.0.intersects(&other_bounding_circle.0) {
-                                    let mut vec = collisions_inner.lock().unwrap();
-                                    vec.push(CollidingPair {
-                                        metadata1: CollidableMetadata {
-                                            entity: *entity,
-                                            is_sensor: sensor.is_some(),
-                                            x: bounding_circle.0.center.x,
-                                            y: bounding_circle.0.center.y,
-                                        },
-                                        metadata2: CollidableMetadata {
-                                            entity: *other_entity,
-                                            is_sensor: other_sensor.is_some(),
-                                            x: other_bounding_circle.0.center.x,
-                                            y: other_bounding_circle.0.center.y,
-                                        },
-                                    });
-                                }
-                            },
-                        )
-                    });
-
-                let c = Arc::try_unwrap(collisions_inner)
-                    .unwrap_or_else(|_| panic!("Arc unwrap failed"));
-                collisions_shared
-                    .lock()
-                    .unwrap()
-                    .append(&mut c.lock().unwrap());
-            });
-    });
+    // the buffers are kept from frame to frame, so they only allocate when the population grows
+    collidables.clear();
+    metadata.clear();
+    entities.clear();
+    for (entity, collider, sensor, layers, user_data) in collidable_query.iter() {
+        if let (_, None, None, None, Some(polygon), _) = collider
+            && !polygon.is_valid()
+        {
+            log::warn!(
+                "Skipping {} since its ConvexPolygonComponent is not a convex polygon",
+                entity
+            );
+            continue;
+        }
+        let collidable =
+            collidable_of(collider).with_layers(layers.copied().unwrap_or(default_layers.0));
+        metadata.push(CollidableMetadata {
+            entity,
+            is_sensor: sensor.is_some(),
+            x: collidable.collider.center().x,
+            y: collidable.collider.center().y,
+            z: 0.,
+            user_data: user_data.cloned(),
+        });
+        collidables.push(collidable);
+        entities.push(entity);
+    }
+    let options = DetectOptions {
+        contacts: compute_contacts.0,
+        continuous: continuous.0,
+    };
+    match *broadphase {
+        CpuBroadphase::BruteForce => {
+            detect_pairs_into(&collidables, &options, &mut job_buffers, &mut pairs)
+        }
+        CpuBroadphase::Grid => *pairs = detect_pairs_grid(&collidables, &options),
+        CpuBroadphase::SweepAndPrune => {
+            *pairs = sweep_and_prune.detect_pairs(&collidables, &entities, &options)
+        }
+        CpuBroadphase::DynamicTree => {
+            *pairs = dynamic_tree.detect_pairs(&collidables, &entities, &options)
+        }
+    };
+    collisions.0.clear();
+    collisions.0.extend(pairs.iter().map(|pair| CollidingPair {
+        metadata1: metadata[pair.a as usize].clone(),
+        metadata2: metadata[pair.b as usize].clone(),
+        contact: pair.contact,
+        time_of_impact: pair.time_of_impact,
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components_and_resources::{BODY_LAYER, SENSOR_LAYER};
+
+    #[test]
+    fn test_colliders_are_centered_on_the_transform() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, CpuCollisionDetectionPlugin::default()))
+            .insert_resource(CollidingPairs(Vec::new()));
+        // both components are left where they were spawned, only the transforms moved
+        app.world_mut().spawn((
+            Transform::from_xyz(10., 0., 0.),
+            BoundingBoxComponent(Aabb2d::new(Vec2::ZERO, Vec2::splat(1.))),
+        ));
+        app.world_mut().spawn((
+            Transform::from_xyz(12., 0., 0.),
+            BoundingCircleComponent(BoundingCircle::new(Vec2::new(-10., 0.), 1.)),
+        ));
+        app.world_mut().spawn((
+            Transform::from_xyz(0., 0., 0.),
+            BoundingCircleComponent(BoundingCircle::new(Vec2::new(10., 0.), 1.)),
+        ));
+        app.update();
+        // touching counts, like on the GPU
+        assert_eq!(app.world().resource::<CollidingPairs>().0.len(), 1);
+    }
 
-    let collisions_final = Arc::try_unwrap(collisions_shared)
-        .unwrap_or_else(|_| panic!("Arc unwrap failed"))
-        .into_inner()
-        .unwrap();
-    collisions.0 = collisions_final;
+    #[test]
+    fn test_invalid_polygons_are_skipped() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, CpuCollisionDetectionPlugin::default()))
+            .insert_resource(CollidingPairs(Vec::new()));
+        for polygon in [
+            ConvexPolygonComponent(Vec::new()),
+            ConvexPolygonComponent(vec![Vec2::ZERO, Vec2::X, Vec2::X * 2.]),
+        ] {
+            app.world_mut()
+                .spawn((Transform::from_xyz(0., 0., 0.), polygon));
+        }
+        app.world_mut().spawn((
+            Transform::from_xyz(0., 0., 0.),
+            BoundingCircleComponent(BoundingCircle::new(Vec2::ZERO, 1.)),
+        ));
+        app.update();
+        assert!(app.world().resource::<CollidingPairs>().0.is_empty());
+        assert!(ConvexPolygonComponent::new(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn test_layers_filter_pairs() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            CpuCollisionDetectionPlugin::default()
+                .with_default_layers(CollisionLayers::new(SENSOR_LAYER, BODY_LAYER)),
+        ))
+        .insert_resource(CollidingPairs(Vec::new()));
+        // all four overlap, the last one is a sensor through the default layers
+        let entities: Vec<Entity> = [
+            Some(CollisionLayers::new(BODY_LAYER, SENSOR_LAYER)),
+            Some(CollisionLayers::new(BODY_LAYER, SENSOR_LAYER)),
+            Some(CollisionLayers::new(SENSOR_LAYER, BODY_LAYER)),
+            None,
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, layers)| {
+            let center = Vec2::new(i as f32 * 0.2, 0.);
+            let mut entity = app.world_mut().spawn((
+                Transform::from_translation(center.extend(0.)),
+                BoundingCircleComponent(BoundingCircle::new(center, 1.)),
+            ));
+            if let Some(layers) = layers {
+                entity.insert(layers);
+            }
+            entity.id()
+        })
+        .collect();
+        app.update();
+        let mut pairs: Vec<(usize, usize)> = app
+            .world()
+            .resource::<CollidingPairs>()
+            .0
+            .iter()
+            .map(|pair| {
+                let a = entities.iter().position(|e| *e == pair.metadata1.entity);
+                let b = entities.iter().position(|e| *e == pair.metadata2.entity);
+                (a.unwrap().min(b.unwrap()), a.unwrap().max(b.unwrap()))
+            })
+            .collect();
+        pairs.sort();
+        // only bodies with sensors
+        assert_eq!(pairs, vec![(0, 2), (0, 3), (1, 2), (1, 3)]);
+    }
 }
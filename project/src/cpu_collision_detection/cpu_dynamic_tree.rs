@@ -0,0 +1,262 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{
+    math::bounding::{Aabb2d, BoundingCircle},
+    prelude::*,
+};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use super::cpu_collision_detection::{
+    CpuBroadphase, CpuColliderData, WithCpuCollider, collidable_of,
+};
+use crate::{
+    collision_core::{
+        detect::{Collidable, DetectOptions, DetectedPair, test_pair},
+        dynamic_tree::{DynamicTree, Proxy},
+    },
+    components_and_resources::{
+        BoundingBoxComponent, BoundingCircleComponent, CapsuleComponent,
+        ContinuousCollisionDetection, ConvexPolygonComponent, PreviousPosition,
+    },
+};
+
+/// A leaf for the broadphase circle of every collidable of the CPU plugin, kept up to date by `update_cpu_dynamic_tree` while `CpuBroadphase::DynamicTree` is used
+#[derive(Debug, Default, Resource)]
+pub struct CpuDynamicTree {
+    tree: DynamicTree<Entity>,
+    proxies: HashMap<Entity, Proxy>,
+    /// Where each entity is in the slice passed to `detect_pairs`, kept to reuse its allocation
+    indices: HashMap<Entity, u32>,
+}
+
+impl CpuDynamicTree {
+    pub fn tree(&self) -> &DynamicTree<Entity> {
+        &self.tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.proxies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.proxies.is_empty()
+    }
+
+    fn set(&mut self, entity: Entity, circle: BoundingCircle) {
+        let aabb = Aabb2d::new(circle.center, Vec2::splat(circle.radius()));
+        match self.proxies.get(&entity) {
+            Some(proxy) => {
+                self.tree.update(*proxy, aabb);
+            }
+            None => {
+                self.proxies.insert(entity, self.tree.insert(aabb, entity));
+            }
+        }
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if let Some(proxy) = self.proxies.remove(&entity) {
+            self.tree.remove(proxy);
+        }
+    }
+
+    /// `detect_pairs` using the tree, in parallel with rayon. `entities` are those of the `collidables`, which must all be in the tree
+    pub fn detect_pairs(
+        &mut self,
+        collidables: &[Collidable],
+        entities: &[Entity],
+        options: &DetectOptions,
+    ) -> Vec<DetectedPair> {
+        self.indices.clear();
+        self.indices.extend(
+            entities
+                .iter()
+                .enumerate()
+                .map(|(i, entity)| (*entity, i as u32)),
+        );
+        let (tree, indices) = (&self.tree, &self.indices);
+        // each rayon job keeps one query stack and one list of pairs
+        (0..collidables.len())
+            .into_par_iter()
+            .fold(
+                || (Vec::new(), Vec::new()),
+                |(mut pairs, mut stack), i| {
+                    let circle = collidables[i].broadphase_circle(options.continuous);
+                    tree.query_with_stack(
+                        Aabb2d::new(circle.center, Vec2::splat(circle.radius())),
+                        &mut stack,
+                        |entity| {
+                            // leaves are fat, so each pair is found from both sides
+                            if let Some(k) =
+                                indices.get(&entity).map(|k| *k as usize).filter(|k| *k > i)
+                            {
+                                pairs.extend(test_pair(
+                                    i,
+                                    &collidables[i],
+                                    k,
+                                    &collidables[k],
+                                    options,
+                                ));
+                            }
+                        },
+                    );
+                    (pairs, stack)
+                },
+            )
+            .map(|(pairs, _)| pairs)
+            .reduce(Vec::new, |mut pairs, more| {
+                pairs.extend(more);
+                pairs
+            })
+    }
+}
+
+/// Inserts collidables as they are added, moves their leaves as they change and removes them when they lose their collider or are despawned. Rebuilds the whole tree when it was just picked, or continuous detection was switched, since the tree missed changes meanwhile
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn update_cpu_dynamic_tree(
+    mut dynamic_tree: ResMut<CpuDynamicTree>,
+    broadphase: Res<CpuBroadphase>,
+    continuous: Res<ContinuousCollisionDetection>,
+    all: Query<(Entity, CpuColliderData), WithCpuCollider>,
+    changed: Query<
+        (Entity, CpuColliderData),
+        (
+            WithCpuCollider,
+            Or<(
+                Changed<Transform>,
+                Changed<BoundingCircleComponent>,
+                Changed<BoundingBoxComponent>,
+                Changed<CapsuleComponent>,
+                Changed<ConvexPolygonComponent>,
+                Changed<PreviousPosition>,
+            )>,
+        ),
+    >,
+    mut removed_circles: RemovedComponents<BoundingCircleComponent>,
+    mut removed_boxes: RemovedComponents<BoundingBoxComponent>,
+    mut removed_capsules: RemovedComponents<CapsuleComponent>,
+    mut removed_polygons: RemovedComponents<ConvexPolygonComponent>,
+) {
+    let removed: HashSet<Entity> = removed_circles
+        .read()
+        .chain(removed_boxes.read())
+        .chain(removed_capsules.read())
+        .chain(removed_polygons.read())
+        .collect();
+    let broadphase_circle = |collider| collidable_of(collider).broadphase_circle(continuous.0);
+    if broadphase.is_changed() || continuous.is_changed() {
+        let dynamic_tree = dynamic_tree.as_mut();
+        dynamic_tree.tree.clear();
+        dynamic_tree.proxies.clear();
+        for (entity, collider) in all.iter() {
+            dynamic_tree.set(entity, broadphase_circle(collider));
+        }
+        return;
+    }
+    for entity in removed {
+        match all.get(entity) {
+            // it still has another collider
+            Ok((entity, collider)) => dynamic_tree.set(entity, broadphase_circle(collider)),
+            Err(_) => dynamic_tree.remove(entity),
+        }
+    }
+    for (entity, collider) in changed.iter() {
+        dynamic_tree.set(entity, broadphase_circle(collider));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        colliding_pair::{CollidingPairs, EntityPair},
+        cpu_collision_detection::cpu_collision_detection::CpuCollisionDetectionPlugin,
+    };
+
+    fn circle(x: f32, y: f32, radius: f32) -> (Transform, BoundingCircleComponent) {
+        (
+            Transform::from_xyz(x, y, 0.),
+            BoundingCircleComponent(BoundingCircle::new(Vec2::new(x, y), radius)),
+        )
+    }
+
+    fn pairs(app: &App) -> Vec<EntityPair> {
+        let mut pairs: Vec<_> = app
+            .world()
+            .resource::<CollidingPairs>()
+            .0
+            .iter()
+            .map(|pair| pair.entity_pair())
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    #[test]
+    fn test_tree_follows_the_world() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            CpuCollisionDetectionPlugin::default().with_broadphase(CpuBroadphase::DynamicTree),
+        ));
+        // a sensor much larger than the bodies, like in the performance test
+        let sensor = app.world_mut().spawn(circle(0., 0., 21.)).id();
+        let bodies: Vec<Entity> = (0..40)
+            .map(|i| {
+                app.world_mut()
+                    .spawn(circle(i as f32 * 5. - 100., 0., 3.))
+                    .id()
+            })
+            .collect();
+        app.update();
+        assert_eq!(app.world().resource::<CpuDynamicTree>().len(), 41);
+        let expected = |app: &mut App| {
+            let mut expected = Vec::new();
+            let mut query = app
+                .world_mut()
+                .query::<(Entity, &BoundingCircleComponent)>();
+            let circles: Vec<_> = query.iter(app.world()).collect();
+            for (i, (a, circle_a)) in circles.iter().enumerate() {
+                for (b, circle_b) in &circles[i + 1..] {
+                    if circle_a.0.center.distance(circle_b.0.center)
+                        <= circle_a.0.radius() + circle_b.0.radius()
+                    {
+                        expected.push(EntityPair::new(*a, *b));
+                    }
+                }
+            }
+            expected.sort();
+            expected
+        };
+        assert_eq!(pairs(&app), expected(&mut app));
+        assert!(pairs(&app).iter().any(|pair| pair.contains(sensor)));
+
+        // moving far, despawning and losing the collider all have to reach the tree
+        app.world_mut()
+            .entity_mut(sensor)
+            .insert(circle(-100., 0., 21.));
+        app.world_mut().despawn(bodies[2]);
+        app.world_mut()
+            .entity_mut(bodies[3])
+            .remove::<BoundingCircleComponent>();
+        app.update();
+        assert_eq!(app.world().resource::<CpuDynamicTree>().len(), 39);
+        assert_eq!(pairs(&app), expected(&mut app));
+
+        // switching away and back rebuilds it with what changed meanwhile
+        *app.world_mut().resource_mut::<CpuBroadphase>() = CpuBroadphase::BruteForce;
+        app.update();
+        app.world_mut()
+            .entity_mut(sensor)
+            .insert(circle(50., 0., 21.));
+        app.world_mut().despawn(bodies[30]);
+        // long enough for the removal to be gone from `RemovedComponents`
+        for _ in 0..3 {
+            app.update();
+        }
+        *app.world_mut().resource_mut::<CpuBroadphase>() = CpuBroadphase::DynamicTree;
+        app.update();
+        assert_eq!(app.world().resource::<CpuDynamicTree>().len(), 38);
+        assert_eq!(pairs(&app), expected(&mut app));
+    }
+}
@@ -1 +1,3 @@
+pub mod collider;
 pub mod cpu_collision_detection;
+pub mod cpu_dynamic_tree;
@@ -0,0 +1,103 @@
+use bevy::prelude::*;
+
+use crate::colliding_pair::CollidingPairs;
+
+/// Whether `CollidingPairs` is put in a canonical order every frame, so it is the same from run to run and between the CPU and the GPU, and `CollisionSetHash` is updated. Set by the detection plugins, and can be changed at runtime
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Resource)]
+pub struct DeterministicCollisions(pub bool);
+
+/// Hash of which pairs are colliding this frame, for comparing frames between runs or machines. Contacts are left out, since the CPU and the GPU can disagree on their last bits. Only updated while `DeterministicCollisions` is on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Resource)]
+pub struct CollisionSetHash(pub u64);
+
+/// FNV-1a, which unlike `DefaultHasher` is the same on every platform and Rust version
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Puts the lower entity of every pair first, sorts the pairs, and drops pairs that were found twice
+pub fn canonicalize_colliding_pairs(
+    mut collisions: ResMut<CollidingPairs>,
+    mut hash: ResMut<CollisionSetHash>,
+) {
+    collisions.0.iter_mut().for_each(|pair| pair.canonicalize());
+    collisions.0.sort_unstable_by_key(|pair| pair.entity_pair());
+    collisions.0.dedup_by_key(|pair| pair.entity_pair());
+    hash.0 = collisions
+        .0
+        .iter()
+        .flat_map(|pair| pair.entity_pair().entities())
+        .flat_map(|entity| entity.to_bits().to_le_bytes())
+        .fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::bounding::BoundingCircle;
+
+    use super::*;
+    use crate::{
+        colliding_pair::EntityPair,
+        collision_detection_plugin::{CollisionDetectionMethod, CollisionDetectionPlugin},
+        components_and_resources::BoundingCircleComponent,
+    };
+
+    fn run(method: CollisionDetectionMethod) -> (Vec<EntityPair>, Vec<Vec3>, u64, u64) {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            CollisionDetectionPlugin::builder()
+                .method(method)
+                .contacts()
+                .deterministic()
+                .build(),
+        ));
+        for i in 0..200 {
+            let center = Vec2::new((i * 37 % 50) as f32, (i * 11 % 30) as f32);
+            app.world_mut().spawn((
+                Transform::from_translation(center.extend(0.)),
+                BoundingCircleComponent(BoundingCircle::new(center, 1.5)),
+            ));
+        }
+        app.update();
+        let collisions = &app.world().resource::<CollidingPairs>().0;
+        let pairs: Vec<_> = collisions.iter().map(|pair| pair.entity_pair()).collect();
+        assert!(collisions.iter().all(|pair| {
+            pair.metadata1.entity == pair.entity_pair().first()
+                && pair.contact.unwrap().normal.x * (pair.metadata2.x - pair.metadata1.x) >= 0.
+        }));
+        let normals = collisions
+            .iter()
+            .map(|pair| pair.contact.unwrap().normal)
+            .collect();
+        let hash = app.world().resource::<CollisionSetHash>().0;
+        app.world_mut().despawn(pairs[0].first());
+        app.update();
+        (
+            pairs,
+            normals,
+            hash,
+            app.world().resource::<CollisionSetHash>().0,
+        )
+    }
+
+    #[test]
+    fn test_same_order_and_hash_with_every_method() {
+        let (pairs, normals, hash, next_hash) = run(CollisionDetectionMethod::Cpu);
+        assert!(pairs.len() > 10);
+        assert!(pairs.is_sorted());
+        assert_ne!(hash, next_hash);
+        assert_eq!(run(CollisionDetectionMethod::Cpu).2, hash);
+        assert_eq!(run(CollisionDetectionMethod::CpuGrid).0, pairs);
+        assert_eq!(run(CollisionDetectionMethod::CpuSweepAndPrune).0, pairs);
+        assert_eq!(run(CollisionDetectionMethod::CpuDynamicTree).0, pairs);
+        let (gpu_pairs, gpu_normals, gpu_hash, gpu_next_hash) =
+            run(CollisionDetectionMethod::GpuEmulated);
+        assert_eq!(gpu_pairs, pairs);
+        assert_eq!((gpu_hash, gpu_next_hash), (hash, next_hash));
+        for (gpu_normal, normal) in gpu_normals.iter().zip(&normals) {
+            assert!(gpu_normal.distance(*normal) < 1e-3);
+        }
+    }
+}
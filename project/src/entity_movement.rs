@@ -5,7 +5,9 @@ use bevy::{
 };
 use rand::{Rng, SeedableRng, rngs::StdRng};
 
-use crate::{components_and_resources::BoundingCircleComponent, config::RunConfig};
+use gpu_accelerated_collision_detection::components_and_resources::BoundingCircleComponent;
+
+use crate::config::RunConfig;
 
 // Pre-generated random movements for deterministic behavior
 #[derive(Resource)]
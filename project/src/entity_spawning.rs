@@ -7,8 +7,11 @@ use bevy::{
     utils::default,
 };
 
+use gpu_accelerated_collision_detection::components_and_resources::{
+    BoundingCircleComponent, NumEntitiesSpawned, Sensor,
+};
+
 use crate::{
-    components_and_resources::{BoundingCircleComponent, NumEntitiesSpawned, Sensor},
     config::RunConfig,
     graphics::colors_and_handles::{AvailableColor, ColorHandles},
 };
@@ -1,11 +1,23 @@
-use bevy_gpu_compute::prelude::{BevyGpuComputeTaskCreator, IterationSpace};
+use bevy::prelude::{Res, ResMut, Resource};
+use bevy_gpu_compute::prelude::{
+    BevyGpuComputeTaskCreator, BevyGpuComputeTaskDeleter, IterationSpace,
+};
 
 use super::shader::collision_detection_module;
+use crate::collision_detection_plugin::CollisionDetectionMethod;
 
-pub fn create_gpu_task(mut gpu_task_creator: BevyGpuComputeTaskCreator) {
+/// Whether the compute task of the GPU plugin exists. It is only created while the real GPU can be used, see `gpu_task_needed`
+#[derive(Debug, Default, Resource)]
+pub struct GpuTaskCreated(pub bool);
+
+pub fn create_gpu_task(
+    mut gpu_task_creator: BevyGpuComputeTaskCreator,
+    mut created: ResMut<GpuTaskCreated>,
+) {
     let initial_iteration_space = IterationSpace::new(100, 100, 1);
     let initial_max_output_lengths = collision_detection_module::MaxOutputLengthsBuilder::new()
         .set_collision_result(100)
+        .set_collision_contact(100)
         .finish();
     gpu_task_creator.create_task_from_rust_shader::<collision_detection_module::Types>(
         "collision_detection", // ensure name is unique
@@ -13,4 +25,29 @@ pub fn create_gpu_task(mut gpu_task_creator: BevyGpuComputeTaskCreator) {
         initial_iteration_space,
         initial_max_output_lengths,
     );
+    created.0 = true;
+}
+
+/// Despawns the task called `name`, which frees its buffers on the GPU
+pub fn release_gpu_task(
+    name: &'static str,
+) -> impl FnMut(BevyGpuComputeTaskDeleter, ResMut<GpuTaskCreated>) {
+    move |mut gpu_task_deleter: BevyGpuComputeTaskDeleter, mut created: ResMut<GpuTaskCreated>| {
+        gpu_task_deleter.delete(name);
+        created.0 = false;
+    }
+}
+
+/// True when the GPU plugin is used on its own, without a `CollisionDetectionMethod`
+pub fn gpu_task_needed(method: Option<Res<CollisionDetectionMethod>>) -> bool {
+    method.is_none_or(|method| {
+        matches!(
+            *method,
+            CollisionDetectionMethod::Gpu | CollisionDetectionMethod::Auto
+        )
+    })
+}
+
+pub fn gpu_task_created(created: Res<GpuTaskCreated>) -> bool {
+    created.0
 }
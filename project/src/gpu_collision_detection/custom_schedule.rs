@@ -1,12 +1,19 @@
 use bevy::{ecs::schedule::ScheduleLabel, prelude::World};
 
-use super::multi_batch_manager::resources::{GpuCollisionBatchJobs, GpuCollisionBatchManager};
+use super::multi_batch_manager::resources::{
+    GpuCollisionBatchJobs, GpuCollisionBatchManager, GpuCollisionBatchResults,
+};
 
 #[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct BatchedCollisionDetectionSchedule;
 
 // An exclusive system to run this schedule
 pub fn run_batched_collision_detection_schedule(world: &mut World) {
+    // start over with this frame's jobs
+    world
+        .resource_mut::<GpuCollisionBatchManager>()
+        .current_batch_job = 0;
+    world.resource_mut::<GpuCollisionBatchResults>().0.clear();
     let mut current_job = world
         .resource::<GpuCollisionBatchManager>()
         .current_batch_job;
@@ -1,5 +1,7 @@
 use bevy::prelude::Entity;
 
+use crate::components_and_resources::CollisionUserData;
+
 use super::single_batch::convert_collidables_to_wgsl_types::PerCollidableDataRequiredByGpu;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -8,6 +10,10 @@ pub struct CollidableMetadata {
     pub is_sensor: bool,
     pub x: f32,
     pub y: f32,
+    /// 0 for 2D collidables
+    pub z: f32,
+    /// The collidable's `CollisionUserData`, if it has one
+    pub user_data: Option<CollisionUserData>,
 }
 
 impl From<&PerCollidableDataRequiredByGpu> for CollidableMetadata {
@@ -17,10 +23,19 @@ impl From<&PerCollidableDataRequiredByGpu> for CollidableMetadata {
             is_sensor: collidable.is_sensor,
             x: collidable.center_x,
             y: collidable.center_y,
+            z: collidable.center_z,
+            user_data: collidable.user_data.clone(),
         }
     }
 }
 
+impl CollidableMetadata {
+    /// The collidable's `CollisionUserData`, if it has one holding a `T`
+    pub fn user_data<T: 'static>(&self) -> Option<&T> {
+        self.user_data.as_ref()?.get()
+    }
+}
+
 impl Eq for CollidableMetadata {}
 
 // impl std::hash::Hash for CollidableMetadata {
@@ -29,3 +44,69 @@ impl Eq for CollidableMetadata {}
 //         self.is_sensor.hash(state);
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        math::{Vec2, bounding::BoundingCircle},
+        prelude::*,
+    };
+
+    use super::*;
+    use crate::{
+        colliding_pair::CollidingPairs,
+        collision_detection_plugin::{CollisionDetectionMethod, CollisionDetectionPlugin},
+        components_and_resources::BoundingCircleComponent,
+    };
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Team(&'static str);
+
+    #[test]
+    fn test_user_data_is_on_the_pairs() {
+        for method in [
+            CollisionDetectionMethod::Cpu,
+            CollisionDetectionMethod::GpuEmulated,
+        ] {
+            let mut app = App::new();
+            app.add_plugins((
+                MinimalPlugins,
+                CollisionDetectionPlugin::builder().method(method).build(),
+            ));
+            let circle = |x: f32| {
+                (
+                    Transform::from_xyz(x, 0., 0.),
+                    BoundingCircleComponent(BoundingCircle::new(Vec2::new(x, 0.), 1.)),
+                )
+            };
+            let red = app
+                .world_mut()
+                .spawn((circle(0.), CollisionUserData::new(Team("red"))))
+                .id();
+            let seven = app
+                .world_mut()
+                .spawn((circle(1.5), CollisionUserData::new(7u32)))
+                .id();
+            let plain = app.world_mut().spawn(circle(-1.5)).id();
+            app.update();
+            let pairs = &app.world().resource::<CollidingPairs>().0;
+            assert_eq!(pairs.len(), 2, "{:?}", method);
+            for pair in pairs {
+                let (red_side, other) = if pair.metadata1.entity == red {
+                    (&pair.metadata1, &pair.metadata2)
+                } else {
+                    (&pair.metadata2, &pair.metadata1)
+                };
+                assert_eq!(red_side.user_data::<Team>(), Some(&Team("red")));
+                assert_eq!(red_side.user_data::<u32>(), None);
+                assert!(other.user_data::<Team>().is_none());
+                if other.entity == seven {
+                    assert_eq!(other.user_data::<u32>(), Some(&7), "{:?}", method);
+                } else {
+                    assert_eq!(other.entity, plain);
+                    assert!(other.user_data.is_none(), "{:?}", method);
+                }
+            }
+        }
+    }
+}
@@ -1,61 +1,141 @@
 use bevy::{
     log,
-    prelude::{Entity, Query, Res, ResMut, Transform},
+    math::bounding::BoundingVolume,
+    prelude::{Entity, Or, Query, Res, ResMut, Transform, With},
 };
 
 use crate::{
     colliding_pair::CollidingPair,
-    components_and_resources::{BoundingCircleComponent, Sensor, SysInfo},
+    components_and_resources::{
+        BoundingBoxComponent, BoundingCircleComponent, CapsuleComponent, CollisionLayers,
+        CollisionUserData, ConvexPolygonComponent, DefaultCollisionLayers, PreviousPosition,
+        Sensor, SysInfo,
+    },
     helpers::math::max_collisions::max_collisions,
 };
 
 use super::{
     multi_batch_manager::population::CollidablePopulation,
     resources::{AllCollidablesThisFrame, MaxDetectableCollisionsScale},
-    single_batch::convert_collidables_to_wgsl_types::PerCollidableDataRequiredByGpu,
+    single_batch::convert_collidables_to_wgsl_types::{
+        CollidableShape, PerCollidableDataRequiredByGpu,
+    },
 };
 
+#[allow(clippy::type_complexity)]
 pub fn get_collidables(
-    query: Query<(
-        Entity,
-        &Transform,
-        &BoundingCircleComponent,
-        Option<&Sensor>,
-    )>,
-    max_detectable_collisions_scale: Res<MaxDetectableCollisionsScale>,
+    query: Query<
+        (
+            Entity,
+            &Transform,
+            Option<&BoundingCircleComponent>,
+            Option<&BoundingBoxComponent>,
+            Option<&CapsuleComponent>,
+            Option<&ConvexPolygonComponent>,
+            Option<&Sensor>,
+            Option<&CollisionLayers>,
+            Option<&PreviousPosition>,
+            Option<&CollisionUserData>,
+        ),
+        Or<(
+            With<BoundingCircleComponent>,
+            With<BoundingBoxComponent>,
+            With<CapsuleComponent>,
+            With<ConvexPolygonComponent>,
+        )>,
+    >,
+    max_detectable_collisions_scale: Option<Res<MaxDetectableCollisionsScale>>,
     sys_info: Res<SysInfo>,
+    default_layers: Res<DefaultCollisionLayers>,
 
     mut population: ResMut<CollidablePopulation>,
     mut all_collidables: ResMut<AllCollidablesThisFrame>,
 ) {
     let mut collidables = Vec::new();
-    for (entity, transform, bounding_circle, sensor) in query.iter() {
+    for (
+        entity,
+        transform,
+        bounding_circle,
+        bounding_box,
+        capsule,
+        polygon,
+        sensor,
+        layers,
+        previous,
+        user_data,
+    ) in query.iter()
+    {
+        let (radius, shape) = match (bounding_circle, bounding_box, capsule, polygon) {
+            (Some(bounding_circle), ..) => (bounding_circle.0.radius(), CollidableShape::Circle),
+            (None, Some(bounding_box), ..) => {
+                let half_size = bounding_box.0.half_size();
+                (half_size.length(), CollidableShape::Box { half_size })
+            }
+            (None, None, Some(capsule), _) => (
+                capsule.0.radius + capsule.0.half_length,
+                CollidableShape::Capsule {
+                    radius: capsule.0.radius,
+                    half_length: capsule.0.half_length,
+                },
+            ),
+            (None, None, None, Some(polygon)) if !polygon.is_valid() => {
+                log::warn!(
+                    "Skipping {} since its ConvexPolygonComponent is not a convex polygon",
+                    entity
+                );
+                continue;
+            }
+            (None, None, None, Some(polygon)) => (
+                polygon.0.iter().map(|v| v.length()).fold(0., f32::max),
+                CollidableShape::ConvexPolygon {
+                    vertices: polygon.0.clone(),
+                },
+            ),
+            (None, None, None, None) => unreachable!("filtered out by the query"),
+        };
         let collidable = PerCollidableDataRequiredByGpu {
             entity,
             center_x: transform.translation.x,
             center_y: transform.translation.y,
-            radius: bounding_circle.0.radius(),
+            center_z: 0.,
+            previous_x: previous.map_or(transform.translation.x, |previous| previous.0.x),
+            previous_y: previous.map_or(transform.translation.y, |previous| previous.0.y),
+            radius,
+            shape,
             is_sensor: sensor.is_some(),
+            layers: layers.copied().unwrap_or(default_layers.0),
+            user_data: user_data.cloned(),
         };
         collidables.push(collidable);
     }
     population.0 = collidables.len();
-    // get theoretical max memory size of collisions
-    let max_num_results_to_receive_from_gpu =
-        (max_collisions(population.0 as u128) as f32 * max_detectable_collisions_scale.0) as usize;
-    let collision_size = std::mem::size_of::<CollidingPair>() * max_num_results_to_receive_from_gpu;
-    let in_gb = collision_size as f32 / 1024.0 / 1024.0 / 1024.0;
-    let available_memory = sys_info.total_mem;
-    if collision_size as f32 > available_memory as f32 * 0.9 {
-        log::error!(
-            "Not enough memory to store all collisions, either reduce the number of entities or allow more potential collision misses by lowering the max_detectable_collisions_scale"
-        );
-        log::info!(
-            "Available memory: {} GB",
-            available_memory as f32 / 1024.0 / 1024.0 / 1024.0
-        );
-        log::info!("Max Collision size: {} GB", in_gb);
-        panic!("Not enough memory to store all collisions");
-    }
+    check_memory_for_collisions(population.0, max_detectable_collisions_scale, &sys_info);
     all_collidables.0 = collidables;
 }
+
+/// Panics if the result buffers the scale allows could take up most of the memory. With exact output sizing nothing is over-allocated so there is nothing to check
+pub fn check_memory_for_collisions(
+    population: usize,
+    max_detectable_collisions_scale: Option<Res<MaxDetectableCollisionsScale>>,
+    sys_info: &SysInfo,
+) {
+    if let Some(scale) = max_detectable_collisions_scale {
+        let max_num_results_to_receive_from_gpu =
+            (max_collisions(population as u128) as f32 * scale.0) as usize;
+        let collision_size =
+            std::mem::size_of::<CollidingPair>() * max_num_results_to_receive_from_gpu;
+        let in_gb = collision_size as f32 / 1024.0 / 1024.0 / 1024.0;
+        let available_memory = sys_info.total_mem;
+        if collision_size as f32 > available_memory as f32 * 0.9 {
+            log::error!(
+                "Not enough memory to store all collisions, either reduce the number of entities or allow more potential collision misses by lowering the max_detectable_collisions_scale"
+            );
+            log::info!(
+                "Available memory: {} GB",
+                available_memory as f32 / 1024.0 / 1024.0 / 1024.0
+            );
+            log::info!("Max Collision size: {} GB", in_gb);
+            panic!("Not enough memory to store all collisions");
+        }
+    }
+}
@@ -7,3 +7,8 @@ pub mod plugin;
 pub mod resources;
 pub mod shader;
 pub mod single_batch;
+pub mod spatial_hash;
+#[cfg(test)]
+mod test_helpers;
+pub mod three_d;
+pub mod wgsl_helpers;
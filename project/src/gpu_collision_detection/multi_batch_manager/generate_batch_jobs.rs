@@ -11,40 +11,42 @@ pub fn generate_batch_jobs(
     max_batch_size: Res<MaxBatchSize>,
     mut batch_jobs: ResMut<GpuCollisionBatchJobs>,
 ) {
-    batch_jobs.0.clear();
+    batch_jobs.0 = plan_batch_jobs(population.0, max_batch_size.0);
+}
+
+/// The brute force batch jobs for a population, kept free of any ECS or GPU state so the plan can be checked on its own (see `validate_batch_plan`)
+pub fn plan_batch_jobs(population: usize, max_batch_size: usize) -> Vec<GpuCollisionBatchJob> {
+    let mut batch_jobs = Vec::new();
+    let max_batch_size = max_batch_size.max(1);
+    // A batch tests about half of its n*n pairs, but a cross-batch job tests all a*b of them, so the second range is half as wide to keep the output buffer the same size
+    let cross_batch_width = (max_batch_size / 2).max(1);
     // Process full batches
-    for i in (0..population.0).step_by(max_batch_size.0) {
-        let end_index = std::cmp::min(i + max_batch_size.0, population.0);
-        let batch_job_id_for_compare = batch_jobs.0.len();
-        let mut batch_job_len = batch_job_id_for_compare;
-        batch_jobs.0.push(GpuCollisionBatchJob {
-            name: format!("batch_{}", batch_job_id_for_compare),
+    for i in (0..population).step_by(max_batch_size) {
+        let end_index = std::cmp::min(i + max_batch_size, population);
+        let batch_job_id = batch_jobs.len();
+        batch_jobs.push(GpuCollisionBatchJob {
+            name: format!("batch_{}", batch_job_id),
             run_id: None,
             start_index_incl: i,
             end_index_excl: end_index,
-            dedup_against_other_batch_job: None,
             second_start_index_incl: None,
             second_end_index_excl: None,
+            candidate_pairs: None,
         });
-        // Then process collisions between this batch and remaining entities
-        let remaining_start = i + max_batch_size.0;
-        if remaining_start < population.0 {
-            let remaining_entities_len = population.0 - remaining_start;
-
-            // Process cross-batch collisions in smaller chunks to respect GPU memory
-            for j in (remaining_start..remaining_entities_len).step_by(max_batch_size.0) {
-                let j_end = std::cmp::min(j + max_batch_size.0, remaining_entities_len);
-                batch_job_len += 1;
-                batch_jobs.0.push(GpuCollisionBatchJob {
-                    name: format!("batch_{}", batch_job_len),
-                    run_id: None,
-                    start_index_incl: j,
-                    end_index_excl: j_end,
-                    dedup_against_other_batch_job: Some(batch_job_id_for_compare),
-                    second_start_index_incl: Some(i),
-                    second_end_index_excl: Some(end_index),
-                });
-            }
+        // Then process collisions between this batch and all entities after it, in chunks to respect GPU memory
+        for j in (end_index..population).step_by(cross_batch_width) {
+            let j_end = std::cmp::min(j + cross_batch_width, population);
+            let batch_job_id = batch_jobs.len();
+            batch_jobs.push(GpuCollisionBatchJob {
+                name: format!("batch_{}", batch_job_id),
+                run_id: None,
+                start_index_incl: i,
+                end_index_excl: end_index,
+                second_start_index_incl: Some(j),
+                second_end_index_excl: Some(j_end),
+                candidate_pairs: None,
+            });
         }
     }
+    batch_jobs
 }
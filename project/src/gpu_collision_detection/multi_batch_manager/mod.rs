@@ -2,3 +2,4 @@ pub mod combine_results;
 pub mod generate_batch_jobs;
 pub mod population;
 pub mod resources;
+pub mod validate_batch_plan;
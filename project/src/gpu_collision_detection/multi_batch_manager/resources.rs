@@ -34,8 +34,18 @@ pub struct GpuCollisionBatchJob {
     pub run_id: Option<u128>,
     pub start_index_incl: usize,
     pub end_index_excl: usize,
-    // used to allow combination of two sections
-    pub second_start_index_incl: Option<usize>, //todo not using this
-    pub second_end_index_excl: Option<usize>,   // todo not using this
-    pub dedup_against_other_batch_job: Option<usize>,
+    /// Cross-batch jobs upload this second range after the first one and test every pair with one collidable in each range, so no pair is tested twice
+    pub second_start_index_incl: Option<usize>,
+    pub second_end_index_excl: Option<usize>,
+    /// Upper bound on the number of pairs this job tests, when it is known exactly. Used instead of `MaxDetectableCollisionsScale` to size the output buffer.
+    pub candidate_pairs: Option<usize>,
+}
+
+impl GpuCollisionBatchJob {
+    pub fn second_range(&self) -> Option<std::ops::Range<usize>> {
+        match (self.second_start_index_incl, self.second_end_index_excl) {
+            (Some(start), Some(end)) => Some(start..end),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,139 @@
+use bevy::prelude::Res;
+
+use super::{
+    generate_batch_jobs::plan_batch_jobs,
+    population::CollidablePopulation,
+    resources::{GpuCollisionBatchJob, GpuCollisionBatchJobs},
+};
+
+/// How well a set of brute force batch jobs covers every unordered pair of a population
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchPlanCoverage {
+    pub job_count: usize,
+    /// Number of pairs each job tests, in job order
+    pub pairs_per_job: Vec<usize>,
+    /// Pairs no job tests, as `(lower index, higher index)`
+    pub missing_pairs: Vec<(usize, usize)>,
+    /// Pairs more than one job tests (or one job tests twice), as `(lower index, higher index)`
+    pub duplicated_pairs: Vec<(usize, usize)>,
+}
+
+impl BatchPlanCoverage {
+    /// Every pair is tested exactly once
+    pub fn is_exact(&self) -> bool {
+        self.missing_pairs.is_empty() && self.duplicated_pairs.is_empty()
+    }
+}
+
+/// Plans the batch jobs the same way `generate_batch_jobs` does and reports their coverage, no GPU needed
+pub fn validate_batch_plan(population: usize, max_batch_size: usize) -> BatchPlanCoverage {
+    batch_plan_coverage(population, &plan_batch_jobs(population, max_batch_size))
+}
+
+/// Walks every pair each job tests, with the same rules as the shader: a single range job tests each pair inside the range once, a cross-batch job tests every pair with one collidable in each range.
+/// This is O(population²), it is meant for tests and debugging.
+pub fn batch_plan_coverage(population: usize, jobs: &[GpuCollisionBatchJob]) -> BatchPlanCoverage {
+    let mut times_tested = vec![0u32; population * population.saturating_sub(1) / 2];
+    let pair_index = |a: usize, b: usize| {
+        let (low, high) = if a < b { (a, b) } else { (b, a) };
+        // pairs are laid out row by row, row `low` holds the pairs (low, low + 1..population)
+        low * population - low * (low + 1) / 2 + (high - low - 1)
+    };
+    let mut pairs_per_job = Vec::with_capacity(jobs.len());
+    for job in jobs.iter() {
+        let first_range = job.start_index_incl..job.end_index_excl.min(population);
+        let mut pairs = 0;
+        match job.second_range() {
+            Some(second_range) => {
+                for a in first_range {
+                    for b in second_range.start..second_range.end.min(population) {
+                        if a != b {
+                            times_tested[pair_index(a, b)] += 1;
+                            pairs += 1;
+                        }
+                    }
+                }
+            }
+            None => {
+                for a in first_range.clone() {
+                    for b in a + 1..first_range.end {
+                        times_tested[pair_index(a, b)] += 1;
+                        pairs += 1;
+                    }
+                }
+            }
+        }
+        pairs_per_job.push(pairs);
+    }
+    let mut missing_pairs = Vec::new();
+    let mut duplicated_pairs = Vec::new();
+    for low in 0..population {
+        for high in low + 1..population {
+            match times_tested[pair_index(low, high)] {
+                0 => missing_pairs.push((low, high)),
+                1 => {}
+                _ => duplicated_pairs.push((low, high)),
+            }
+        }
+    }
+    BatchPlanCoverage {
+        job_count: jobs.len(),
+        pairs_per_job,
+        missing_pairs,
+        duplicated_pairs,
+    }
+}
+
+/// Debug check, enabled with `GpuCollisionDetectionPlugin::with_batch_plan_assertions`
+pub fn assert_batch_plan(population: Res<CollidablePopulation>, jobs: Res<GpuCollisionBatchJobs>) {
+    let coverage = batch_plan_coverage(population.0, &jobs.0);
+    assert!(
+        coverage.is_exact(),
+        "batch plan for {} collidables in {} jobs misses {} pairs and duplicates {} pairs, first missing: {:?}, first duplicated: {:?}",
+        population.0,
+        coverage.job_count,
+        coverage.missing_pairs.len(),
+        coverage.duplicated_pairs.len(),
+        coverage.missing_pairs.first(),
+        coverage.duplicated_pairs.first()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_pair_covered_once() {
+        for population in [0, 1, 2, 7, 10, 33, 64] {
+            for max_batch_size in [1, 2, 3, 5, 10, 64, 100] {
+                let coverage = validate_batch_plan(population, max_batch_size);
+                assert!(
+                    coverage.is_exact(),
+                    "population {} max_batch_size {}: {:?}",
+                    population,
+                    max_batch_size,
+                    coverage
+                );
+                assert_eq!(
+                    coverage.pairs_per_job.iter().sum::<usize>(),
+                    population * population.saturating_sub(1) / 2
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_reports_missing_and_duplicated_pairs() {
+        let mut jobs = plan_batch_jobs(6, 2);
+        // drop the first cross-batch job (0..2 against 2..3) and run the first batch twice
+        let dropped = jobs.remove(1);
+        assert_eq!(dropped.second_range(), Some(2..3));
+        jobs.push(jobs[0].clone());
+        let coverage = batch_plan_coverage(6, &jobs);
+        assert_eq!(coverage.missing_pairs, vec![(0, 2), (1, 2)]);
+        assert_eq!(coverage.duplicated_pairs, vec![(0, 1)]);
+        assert_eq!(coverage.job_count, jobs.len());
+        assert_eq!(coverage.pairs_per_job[0], 1);
+    }
+}
@@ -1,12 +1,21 @@
-use std::vec;
-
-use super::create_gpu_task::create_gpu_task;
-use super::shader::collision_detection_module;
-use crate::collision_processing::process_collisions;
-use crate::config::RunConfig;
+use super::create_gpu_task::{
+    GpuTaskCreated, create_gpu_task, gpu_task_created, gpu_task_needed, release_gpu_task,
+};
+use crate::colliding_entities::TrackCollidingEntities;
+use crate::collision_detection_plugin::{
+    CollisionDetectionMethod, CollisionDetectionSet, GpuDetectionSet,
+};
+use crate::collision_events::CollisionEventsPlugin;
+use crate::components_and_resources::{
+    CollisionLayers, ComputeContacts, ContinuousCollisionDetection, DefaultCollisionLayers, SysInfo,
+};
+use crate::continuous_collision_detection::store_previous_positions;
+use crate::deterministic_collisions::canonicalize_colliding_pairs;
+use bevy::ecs::schedule::SystemConfigs;
+use bevy::log;
 use bevy::prelude::*;
-use bevy::render::render_resource::BufferUsages;
 use bevy::render::renderer::RenderDevice;
+use bevy::render::settings::WgpuLimits;
 use bevy_gpu_compute::prelude::BevyGpuComputePlugin;
 
 use super::custom_schedule::run_batched_collision_detection_schedule;
@@ -15,11 +24,18 @@ use super::multi_batch_manager::combine_results::combine_results;
 use super::multi_batch_manager::generate_batch_jobs::generate_batch_jobs;
 use super::multi_batch_manager::population::CollidablePopulation;
 use super::multi_batch_manager::resources::setup_multi_batch_manager_resources;
+use super::multi_batch_manager::validate_batch_plan::assert_batch_plan;
 use super::resources::{
-    AllCollidablesThisFrame, BindGroupLayoutsResource, CounterStagingBuffer, MaxBatchSize,
-    MaxDetectableCollisionsScale,
+    AllCollidablesThisFrame, CollisionDimensions, CollisionOverflowPolicy, GpuBroadphase,
+    MaxBatchSize, MaxBatchSizeLimit, MaxDetectableCollisionsScale, MaxStorageBufferBindingSize,
 };
 use super::single_batch::plugin::GpuCollisionSingleBatchRunnerPlugin;
+use super::single_batch::resources::result_size;
+use super::spatial_hash::generate_spatial_hash_batch_jobs::generate_spatial_hash_batch_jobs;
+use super::spatial_hash::grid::SpatialHashGrid;
+use super::spatial_hash::sort_collidables_by_cell::sort_collidables_by_cell;
+use super::three_d::create_gpu_task::create_gpu_task_3d;
+use super::three_d::get_collidables::get_collidables_3d;
 
 pub struct GpuCollisionDetectionPlugin {
     /**
@@ -30,29 +46,110 @@ pub struct GpuCollisionDetectionPlugin {
     The "max_detectable_collisions_scale" variable is multiplied by the maximum theoretical possible memory size of the results, and is used to reserve LESS than the maximum amount of memory in order to improve performance. The correct value for that variable is very hard to determine. I have used manual testing to come up with a very rough function describing what that variable should be, but it still most of the time overshoots signicantly, reducing performance.
 
     The variable is held in a Bevy resource so if you are using this code I encourage you to mutate that value yourself, since you will know a lot more about the number of expected collisions for your scenario and therefore guess much better how much memory will be needed for results.
+
+    If this is `None` no guess is made: every batch first runs a counting pass on the GPU, the exact number of collisions is read back, and the detection pass writes into an exactly sized buffer. This costs a second dispatch per batch but never over-allocates and never drops collisions.
+     */
+    pub max_detectable_collisions_scale: Option<f32>,
+    /**
+     * How candidate pairs are found. `BruteForce` dispatches every pair inside a batch (O(n²)), `SpatialHash` bins collidables into a uniform grid first and only tests neighboring cells, which is what makes very large populations feasible. Held in the `GpuBroadphase` resource, so it can be changed at runtime.
+     */
+    pub broadphase: GpuBroadphase,
+    /**
+     * What to do when a batch finds more collisions than its result buffer can hold. A `CollisionOverflow` event is sent either way. Only applies while a `max_detectable_collisions_scale` is used, exact output sizing never overflows.
+     */
+    pub overflow_policy: CollisionOverflowPolicy,
+    /**
+     * Debug mode: check every frame that the brute force batch jobs test every pair of collidables exactly once, and panic if not. This is O(n²) on the CPU, so only turn it on while working on the batching.
+     */
+    pub assert_batch_plan: bool,
+    /**
+     * Run the collision shader on the CPU, in parallel with rayon, instead of on the GPU. Without it the compute task is created on the first frame, or the first time `CollisionDetectionMethod` is `Gpu` or `Auto`, and released when it is changed to something else, while `GpuEmulated` runs the emulation anyway. Everything around the shader (batching, output sizing, overflow handling, combining results) runs exactly as it does on the GPU, so this is how the multi-batch path is tested on machines without a GPU adapter. Much slower than the GPU, and slower than the plain CPU plugin.
+     */
+    pub emulate_on_cpu: bool,
+    /**
+     * 2D colliders with the 2D shader, or spheres and 3D boxes with the 3D shader. The rest of the pipeline is the same for both, except that 3D only supports the brute force broadphase and falls back to it if `GpuBroadphase` is switched to `SpatialHash`.
+     */
+    pub dimensions: CollisionDimensions,
+    /**
+     * Also find the `Contact` of every pair in the shader. Held in the `ComputeContacts` resource, so it can be changed at runtime. Contacts are written to their own output, so with it off each result is only the 8 byte pair, which allows bigger batches.
+     */
+    pub compute_contacts: bool,
+    /**
+     * Sweep circles from where they were the last time collisions were detected, so fast ones can't pass through each other between frames. Held in the `ContinuousCollisionDetection` resource. Only in 2D with the brute force broadphase, since swept pairs can be further apart than neighboring spatial hash cells.
+     */
+    pub continuous: bool,
+    /**
+     * Also trigger `CollisionStarted` and `CollisionEnded` on both entities of the pair for observers. They are sent as events either way. Held in the `CollisionObservers` resource.
+     */
+    pub collision_observers: bool,
+    /**
+     * Which collidables get a `CollidingEntities` component listing what they collide with, updated after `combine_results`. Held in the `TrackCollidingEntities` resource.
      */
-    pub max_detectable_collisions_scale: f32,
+    pub colliding_entities: TrackCollidingEntities,
+    /**
+     * Layers of the collidables without `CollisionLayers`, held in the `DefaultCollisionLayers` resource.
+     */
+    pub default_layers: CollisionLayers,
+    /**
+     * Upper limit on the number of collidables in a batch, on top of the limit worked out from the storage buffer size and `max_detectable_collisions_scale`. Held in the `MaxBatchSizeLimit` resource.
+     */
+    pub max_batch_size: Option<usize>,
+    /**
+     * Sort `CollidingPairs` into a canonical order after `combine_results` and hash them into `CollisionSetHash`, so the results can be compared between runs and with the CPU plugin. Held in the `DeterministicCollisions` resource.
+     */
+    pub deterministic: bool,
 }
 
 impl Plugin for GpuCollisionDetectionPlugin {
     fn build(&self, app: &mut App) {
+        // added by a hot swappable `CollisionDetectionPlugin` that starts on the CPU, which only warns if the GPU is picked later
+        let starts_on_gpu = app
+            .world()
+            .get_resource::<CollisionDetectionMethod>()
+            .is_none_or(|method| method.uses_gpu());
+        if starts_on_gpu {
+            assert!(
+                self.dimensions == CollisionDimensions::Two
+                    || self.broadphase == GpuBroadphase::BruteForce,
+                "the spatial hash broadphase is only implemented in 2D"
+            );
+            assert!(
+                !self.continuous
+                    || (self.dimensions == CollisionDimensions::Two
+                        && self.broadphase == GpuBroadphase::BruteForce),
+                "continuous collision detection is only implemented in 2D with the brute force broadphase"
+            );
+        }
         let max_detectable_collisions_scale = self.max_detectable_collisions_scale;
-        app.add_plugins(BevyGpuComputePlugin::default())
-            .add_plugins(GpuCollisionSingleBatchRunnerPlugin)
+        let brute_force = GpuBroadphase::BruteForce.selected(self.dimensions);
+        let spatial_hash = GpuBroadphase::SpatialHash.selected(self.dimensions);
+        app.insert_resource(self.broadphase)
+            .insert_resource(self.overflow_policy)
+            .insert_resource(ComputeContacts(self.compute_contacts))
+            .insert_resource(ContinuousCollisionDetection(self.continuous))
+            .insert_resource(DefaultCollisionLayers(self.default_layers))
+            .insert_resource(MaxBatchSizeLimit(self.max_batch_size))
+            .init_resource::<SysInfo>()
+            .add_plugins(GpuCollisionSingleBatchRunnerPlugin {
+                emulate_on_cpu: self.emulate_on_cpu,
+                dimensions: self.dimensions,
+            })
             .add_systems(
                 Startup,
                 (
                     // create max_detectable_collisions_scale resource
                     move |mut commands: Commands| {
-                        commands.insert_resource(MaxDetectableCollisionsScale(
-                            max_detectable_collisions_scale,
-                        ));
-                        commands.insert_resource(MaxBatchSize(10));
+                        if let Some(scale) = max_detectable_collisions_scale {
+                            commands.insert_resource(MaxDetectableCollisionsScale(scale));
+                        }
+                        // worked out on the first frame
+                        commands.insert_resource(MaxBatchSize(0));
                         commands.insert_resource(AllCollidablesThisFrame(Vec::new()));
                         commands.insert_resource(CollidablePopulation(0));
+                        commands.insert_resource(SpatialHashGrid::default());
                     },
+                    setup_max_storage_buffer_binding_size,
                     setup_multi_batch_manager_resources,
-                    create_gpu_task,
                 )
                     .chain(),
             )
@@ -60,29 +157,187 @@ impl Plugin for GpuCollisionDetectionPlugin {
                 Update,
                 (
                     update_max_batch_size,
-                    get_collidables,
-                    generate_batch_jobs,
+                    self.get_collidables(),
+                    sort_collidables_by_cell.run_if(spatial_hash.clone()),
+                    generate_batch_jobs.run_if(brute_force.clone()),
+                    generate_spatial_hash_batch_jobs.run_if(spatial_hash),
                     run_batched_collision_detection_schedule,
                     combine_results,
                 )
                     .chain()
-                    .before(process_collisions),
+                    .before(canonicalize_colliding_pairs)
+                    .in_set(GpuDetectionSet)
+                    .in_set(CollisionDetectionSet),
+            )
+            .add_systems(
+                Update,
+                warn_unsupported_continuous(self.dimensions)
+                    .run_if(
+                        resource_changed::<ContinuousCollisionDetection>
+                            .or(resource_changed::<GpuBroadphase>)
+                            .or(resource_exists_and_changed::<CollisionDetectionMethod>),
+                    )
+                    .before(GpuDetectionSet),
+            )
+            .add_systems(
+                Update,
+                store_previous_positions
+                    .after(combine_results)
+                    .run_if(resource_equals(ContinuousCollisionDetection(true)))
+                    .in_set(GpuDetectionSet)
+                    .in_set(CollisionDetectionSet),
             );
+        // already there when composed with the CPU plugin for `CollisionDetectionMethod::Auto`
+        if !app.is_plugin_added::<CollisionEventsPlugin>() {
+            app.add_plugins(CollisionEventsPlugin {
+                observers: self.collision_observers,
+                colliding_entities: self.colliding_entities,
+                deterministic: self.deterministic,
+            });
+        }
+        if !self.emulate_on_cpu {
+            let (create_task, task_name) = match self.dimensions {
+                CollisionDimensions::Two => (create_gpu_task.into_configs(), "collision_detection"),
+                CollisionDimensions::Three => {
+                    (create_gpu_task_3d.into_configs(), "collision_detection_3d")
+                }
+            };
+            app.add_plugins(BevyGpuComputePlugin::default())
+                .init_resource::<GpuTaskCreated>()
+                .add_systems(
+                    Update,
+                    (
+                        create_task.run_if(gpu_task_needed.and(not(gpu_task_created))),
+                        release_gpu_task(task_name)
+                            .run_if(not(gpu_task_needed).and(gpu_task_created)),
+                    )
+                        .before(GpuDetectionSet),
+                );
+        }
+        if self.dimensions == CollisionDimensions::Three {
+            app.add_systems(
+                Update,
+                fall_back_to_brute_force
+                    .run_if(resource_changed::<GpuBroadphase>)
+                    .before(GpuDetectionSet),
+            );
+        }
+        if self.assert_batch_plan {
+            app.add_systems(
+                Update,
+                assert_batch_plan
+                    .after(generate_batch_jobs)
+                    .before(run_batched_collision_detection_schedule)
+                    .run_if(brute_force)
+                    .in_set(GpuDetectionSet),
+            );
+        }
     }
 }
 
-impl GpuCollisionDetectionPlugin {
-    pub fn new(run_config: &RunConfig) -> Self {
+/// Exact output sizing, since there is no way to guess a scale without knowing the scene
+impl Default for GpuCollisionDetectionPlugin {
+    fn default() -> Self {
         Self {
-            max_detectable_collisions_scale: estimate_minimum_scale_factor_to_catch_all_collisions(
-                (run_config.top_right_x - run_config.bottom_left_x) as f32,
-                (run_config.top_right_y - run_config.bottom_left_y) as f32,
-                (run_config.sensor_radius + run_config.body_radius) / 2.,
-            ),
+            max_detectable_collisions_scale: None,
+            broadphase: GpuBroadphase::default(),
+            overflow_policy: CollisionOverflowPolicy::default(),
+            assert_batch_plan: false,
+            emulate_on_cpu: false,
+            dimensions: CollisionDimensions::default(),
+            compute_contacts: false,
+            continuous: false,
+            collision_observers: false,
+            colliding_entities: TrackCollidingEntities::Off,
+            default_layers: CollisionLayers::ALL,
+            max_batch_size: None,
+            deterministic: false,
+        }
+    }
+}
+
+impl GpuCollisionDetectionPlugin {
+    fn get_collidables(&self) -> SystemConfigs {
+        match self.dimensions {
+            CollisionDimensions::Two => get_collidables.into_configs(),
+            CollisionDimensions::Three => get_collidables_3d.into_configs(),
         }
     }
+
+    pub fn with_broadphase(mut self, broadphase: GpuBroadphase) -> Self {
+        self.broadphase = broadphase;
+        self
+    }
+
+    pub fn with_overflow_policy(mut self, overflow_policy: CollisionOverflowPolicy) -> Self {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
+    pub fn with_batch_plan_assertions(mut self) -> Self {
+        self.assert_batch_plan = true;
+        self
+    }
+
+    pub fn with_dimensions(mut self, dimensions: CollisionDimensions) -> Self {
+        self.dimensions = dimensions;
+        self
+    }
+
+    pub fn with_contacts(mut self) -> Self {
+        self.compute_contacts = true;
+        self
+    }
+
+    pub fn with_continuous_collision_detection(mut self) -> Self {
+        self.continuous = true;
+        self
+    }
+
+    pub fn with_collision_observers(mut self) -> Self {
+        self.collision_observers = true;
+        self
+    }
+
+    pub fn with_colliding_entities(mut self, tracking: TrackCollidingEntities) -> Self {
+        self.colliding_entities = tracking;
+        self
+    }
+
+    pub fn with_cpu_emulation(mut self) -> Self {
+        self.emulate_on_cpu = true;
+        self
+    }
+
+    /// Size the result buffers with a counting pass instead of `max_detectable_collisions_scale`
+    pub fn with_exact_output_sizing(mut self) -> Self {
+        self.max_detectable_collisions_scale = None;
+        self
+    }
+
+    pub fn with_max_detectable_collisions_scale(mut self, scale: f32) -> Self {
+        self.max_detectable_collisions_scale = Some(scale);
+        self
+    }
+
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = Some(max_batch_size);
+        self
+    }
+
+    pub fn with_default_layers(mut self, layers: CollisionLayers) -> Self {
+        self.default_layers = layers;
+        self
+    }
+
+    pub fn with_deterministic_order(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
 }
-fn estimate_minimum_scale_factor_to_catch_all_collisions(
+
+/// A `max_detectable_collisions_scale` for collidables of `average_radius` spread over a `width` by `height` area
+pub fn estimate_minimum_scale_factor_to_catch_all_collisions(
     width: f32,
     height: f32,
     average_radius: f32,
@@ -91,19 +346,72 @@ fn estimate_minimum_scale_factor_to_catch_all_collisions(
     // equation based on very limited manual testing, lots of room for improvement
     0.07396755 + (1.054372 - 0.07396755) / (1.0 + (f / 401.5207).powf(1.816759))
 }
+fn setup_max_storage_buffer_binding_size(
+    mut commands: Commands,
+    render_device: Option<Res<RenderDevice>>,
+) {
+    let size = render_device.map_or(
+        WgpuLimits::default().max_storage_buffer_binding_size,
+        |render_device| render_device.limits().max_storage_buffer_binding_size,
+    );
+    commands.insert_resource(MaxStorageBufferBindingSize(size));
+}
+/// `ContinuousCollisionDetection`, `GpuBroadphase` and `CollisionDetectionMethod` can be changed at runtime, but only the 2D brute force broadphase sweeps, so the build time check doesn't cover them
+fn warn_unsupported_continuous(
+    dimensions: CollisionDimensions,
+) -> impl FnMut(
+    Res<ContinuousCollisionDetection>,
+    Res<GpuBroadphase>,
+    Option<Res<CollisionDetectionMethod>>,
+) {
+    move |continuous: Res<ContinuousCollisionDetection>,
+          broadphase: Res<GpuBroadphase>,
+          method: Option<Res<CollisionDetectionMethod>>| {
+        if continuous.0
+            && method.is_none_or(|method| method.uses_gpu())
+            && (dimensions == CollisionDimensions::Three
+                || *broadphase == GpuBroadphase::SpatialHash)
+        {
+            log::warn!(
+                "Continuous collision detection is only implemented in 2D with the brute force broadphase, collidables won't be swept with {:?} and {:?}",
+                dimensions,
+                *broadphase
+            );
+        }
+    }
+}
+
+/// `GpuBroadphase` can be changed at runtime, but 3D only has the brute force broadphase, so the build time check doesn't cover it
+fn fall_back_to_brute_force(mut broadphase: ResMut<GpuBroadphase>) {
+    if *broadphase == GpuBroadphase::SpatialHash {
+        log::warn!(
+            "The spatial hash broadphase is only implemented in 2D, falling back to brute force"
+        );
+        *broadphase = GpuBroadphase::BruteForce;
+    }
+}
+
+/// With exact output sizing there is no scale, so batches are sized for the worst case where every pair collides, which means the exact count always fits in a single buffer
 fn update_max_batch_size(
-    render_device: Res<RenderDevice>,                // static
-    scale_factor: Res<MaxDetectableCollisionsScale>, // dynamic
+    max_storage_buffer_binding_size: Res<MaxStorageBufferBindingSize>, // static
+    scale_factor: Option<Res<MaxDetectableCollisionsScale>>,           // dynamic
+    limit: Res<MaxBatchSizeLimit>,                                     // dynamic
+    compute_contacts: Res<ComputeContacts>,                            // dynamic
+    continuous: Res<ContinuousCollisionDetection>,                     // dynamic
     mut max_batch_size: ResMut<MaxBatchSize>,
 ) {
-    if scale_factor.is_changed() || max_batch_size.0 < 1 {
-        let max_storage_buffer_bytes = render_device.limits().max_storage_buffer_binding_size;
+    let scale_changed = scale_factor.as_ref().is_some_and(|s| s.is_changed());
+    let result_size_changed = compute_contacts.is_changed() || continuous.is_changed();
+    if scale_changed || result_size_changed || limit.is_changed() || max_batch_size.0 < 1 {
+        let max_storage_buffer_bytes = max_storage_buffer_binding_size.0;
         let safety_factor = 1.1;
-        let per_result_size = std::mem::size_of::<collision_detection_module::CollisionResult>();
+        let per_result_size = result_size(compute_contacts.0 || continuous.0);
         let p = per_result_size as f32;
         let t = max_storage_buffer_bytes as f32;
-        let s = scale_factor.0 * safety_factor;
+        let s = scale_factor.map_or(1., |s| s.0) * safety_factor;
         let b: f32 = (1. / 2.) * (((p * s + 8. * t).sqrt() / (p.sqrt() * s.sqrt())) + 1.);
-        max_batch_size.0 = b.floor() as usize;
+        max_batch_size.0 = limit
+            .0
+            .map_or(b.floor() as usize, |limit| limit.min(b.floor() as usize));
     }
 }
@@ -1,5 +1,5 @@
 use bevy::{
-    prelude::Resource,
+    prelude::{Res, Resource},
     render::render_resource::{BindGroupLayout, Buffer},
 };
 
@@ -26,3 +26,62 @@ pub struct AllCollidablesThisFrame(pub Vec<PerCollidableDataRequiredByGpu>);
 
 #[derive(Resource)]
 pub struct MaxBatchSize(pub usize);
+
+/// Caps `MaxBatchSize`, which is otherwise as large as the storage buffers allow
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct MaxBatchSizeLimit(pub Option<usize>);
+
+/// Largest storage buffer the device can bind, in bytes. Read from the `RenderDevice` at startup, or the wgpu default when emulating on the CPU without one
+#[derive(Resource)]
+pub struct MaxStorageBufferBindingSize(pub u32);
+
+/// How the GPU finds candidate pairs before running the narrow phase test on them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Resource)]
+pub enum GpuBroadphase {
+    /// Every pair inside a batch is tested, dispatched over an N×N iteration space.
+    #[default]
+    BruteForce,
+    /// Collidables are binned into a uniform grid and only pairs in neighboring cells are tested, dispatched over an N×9 iteration space.
+    SpatialHash,
+}
+
+impl GpuBroadphase {
+    /// The value of `BroadphaseConfig::mode` in the shader
+    pub fn shader_mode(&self) -> u32 {
+        match self {
+            GpuBroadphase::BruteForce => 0,
+            GpuBroadphase::SpatialHash => 1,
+        }
+    }
+
+    /// Run condition for the systems of this broadphase. 3D only has the brute force broadphase, so it runs those whatever the resource says
+    pub fn selected(
+        self,
+        dimensions: CollisionDimensions,
+    ) -> impl FnMut(Res<GpuBroadphase>) -> bool + Clone {
+        move |current: Res<GpuBroadphase>| match dimensions {
+            CollisionDimensions::Two => *current == self,
+            CollisionDimensions::Three => self == GpuBroadphase::BruteForce,
+        }
+    }
+}
+
+/// Which colliders the GPU plugin reads and which shader it dispatches. Batching, output sizing, overflow handling and combining results are shared by both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CollisionDimensions {
+    /// Circles, boxes, capsules and convex polygons, in the xy plane
+    #[default]
+    Two,
+    /// Spheres and boxes, using the `Transform`'s z as well. Only supports the brute force broadphase.
+    Three,
+}
+
+/// What to do when a batch finds more collisions than its result buffer can hold. Either way a `CollisionOverflow` event is sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Resource)]
+pub enum CollisionOverflowPolicy {
+    /// Count the batch's collisions and run it again with an exactly sized buffer, so nothing is lost this frame. Costs two extra dispatches for the overflowing batch.
+    #[default]
+    RerunBatch,
+    /// Keep the truncated results and grow `MaxDetectableCollisionsScale` so the next frame has room. Costs one extra (counting) dispatch.
+    GrowScale,
+}
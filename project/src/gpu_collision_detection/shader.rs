@@ -1,49 +1,821 @@
+// wgsl has no implicit returns, so the shader functions need explicit ones, and the generated cpu version of main takes every input and output as an argument
+#![allow(clippy::needless_return, clippy::too_many_arguments)]
 use bevy_gpu_compute::prelude::wgsl_shader_module;
 #[wgsl_shader_module]
 pub mod collision_detection_module {
     use bevy_gpu_compute::prelude::*;
 
+    use crate::gpu_collision_detection::wgsl_helpers::sqrt;
+
+    // must match GpuBroadphase::shader_mode
+    const BROADPHASE_SPATIAL_HASH: u32 = 1;
+    // must match CollidableShape::shader_kind
+    const SHAPE_CIRCLE: u32 = 0;
+    const SHAPE_BOX: u32 = 1;
+    const SHAPE_CAPSULE: u32 = 2;
+    const SHAPE_POLYGON: u32 = 3;
+    #[wgsl_config]
+    struct BroadphaseConfig {
+        pub mode: u32,
+        pub query_start: u32,
+        pub query_end: u32,
+        pub cells_x: u32,
+        pub cells_y: u32,
+        // for brute force cross-batch jobs, where the second range of collidables starts in the uploaded data, 0 for single range jobs
+        pub second_range_start: u32,
+        // 1 for the counting pass, which only pushes the number of collisions each thread found, see main
+        pub count_only: u32,
+        // 1 to push every collision with its contact to CollisionContact instead of CollisionResult
+        pub contacts: u32,
+        // 1 to sweep circles from their previous position, which also pushes to CollisionContact for the time of impact
+        pub continuous: u32,
+        // 1 for brute force detection passes dispatched like the counting pass, one thread per collidable of the first range. Only the CPU emulation does this, where every thread pays for a copy of the inputs
+        pub whole_rows: u32,
+    }
     #[wgsl_input_array]
     struct Position {
         pub v: Vec2F32,
+        // where the collidable was when collisions were last detected, only read for continuous collision detection
+        pub previous: Vec2F32,
     }
+    // every shape is the points within radius of its core: a point for circles, a vertical segment for capsules, and a convex polygon for boxes and polygons. Kept in one input with the layers since the shader is at the limit of 8 storage buffers
     #[wgsl_input_array]
-    type Radius = f32;
+    struct Shape {
+        // half the size of a box, or half the segment of a capsule
+        pub half_extents: Vec2F32,
+        pub bounding_radius: f32,
+        pub radius: f32,
+        pub kind: u32,
+        // a polygon's vertices are Vertex[vertex_start..vertex_start + vertex_count], relative to its Position
+        pub vertex_start: u32,
+        pub vertex_count: u32,
+        pub memberships: u32,
+        pub filters: u32,
+        // keeps the size a multiple of half_extents' 8 byte alignment, so the rust and wgsl layouts match
+        pub padding: u32,
+    }
+    #[wgsl_input_array]
+    struct Vertex {
+        pub v: Vec2F32,
+    }
+    // the cell of every collidable, followed by the prefix sum of the per-cell counts. One input for both, for the same storage buffer limit
+    #[wgsl_input_array]
+    type GridCell = u32;
+    // the counting pass pushes a result for every thread that found collisions, with the number it found as entity2
     #[wgsl_output_vec]
     struct CollisionResult {
         pub entity1: u32,
         pub entity2: u32,
     }
+    // a CollisionResult with room for the contact, only pushed to when contacts or continuous collision detection are on, so the results are 8 bytes otherwise. The normal points from entity1 towards entity2, see Contact
+    #[wgsl_output_vec]
+    struct CollisionContact {
+        pub entity1: u32,
+        pub entity2: u32,
+        pub normal: Vec2F32,
+        pub point: Vec2F32,
+        // 0 unless contacts are on
+        pub depth: f32,
+        // -1 unless the pair was swept, see CollidingPair::time_of_impact
+        pub time_of_impact: f32,
+    }
     fn calculate_distance_squared(p1: Vec2F32, p2: Vec2F32) -> f32 {
         let dx = p1.x - p2[0];
         let dy = p1.y - p2[1];
         return dx * dx + dy * dy;
     }
-    fn main(iter_pos: WgslIterationPosition) {
-        let current_entity = iter_pos.x;
-        let other_entity = iter_pos.y;
-        // Early exit conditions
-        let out_of_bounds = current_entity >= WgslVecInput::vec_len::<Position>()
-            || other_entity >= WgslVecInput::vec_len::<Position>();
-        if out_of_bounds || current_entity == other_entity || current_entity >= other_entity {
-            return;
-        }
-        let current_radius = WgslVecInput::vec_val::<Radius>(current_entity);
-        let other_radius = WgslVecInput::vec_val::<Radius>(other_entity);
-        if current_radius <= 0.0 || other_radius <= 0.0 {
-            return;
-        }
-        let current_pos = WgslVecInput::vec_val::<Position>(current_entity);
-        let other_pos = WgslVecInput::vec_val::<Position>(other_entity);
-        let dist_squared = calculate_distance_squared(current_pos.v, other_pos.v);
-        let radius_sum = (current_radius + other_radius);
+    fn dot(a: Vec2F32, b: Vec2F32) -> f32 {
+        return a.x * b.x + a.y * b.y;
+    }
+    // touching circles intersect, matching bevy's IntersectsVolume and the 3D spheres
+    fn circles_intersect(p1: Vec2F32, r1: f32, p2: Vec2F32, r2: f32) -> u32 {
+        if r1 <= 0.0 || r2 <= 0.0 {
+            return 0u32;
+        }
+        let dist_squared = calculate_distance_squared(p1, p2);
+        let radius_sum = r1 + r2;
         let rad_sum_sq = radius_sum * radius_sum;
-        let is_collision = dist_squared < rad_sum_sq;
-        if is_collision {
+        if dist_squared <= rad_sum_sq {
+            return 1u32;
+        }
+        return 0u32;
+    }
+    // touching boxes intersect, matching bevy's IntersectsVolume
+    fn boxes_intersect(p1: Vec2F32, e1: Vec2F32, p2: Vec2F32, e2: Vec2F32) -> u32 {
+        let mut dx = p1.x - p2.x;
+        if dx < 0.0 {
+            dx = -dx;
+        }
+        let mut dy = p1.y - p2.y;
+        if dy < 0.0 {
+            dy = -dy;
+        }
+        if dx <= e1.x + e2.x && dy <= e1.y + e2.y {
+            return 1u32;
+        }
+        return 0u32;
+    }
+    // tests the point of the box closest to the circle's center, matching bevy's IntersectsVolume
+    fn circle_box_intersect(center: Vec2F32, radius: f32, box_center: Vec2F32, e: Vec2F32) -> u32 {
+        let mut closest_x = center.x;
+        if closest_x < box_center.x - e.x {
+            closest_x = box_center.x - e.x;
+        } else if closest_x > box_center.x + e.x {
+            closest_x = box_center.x + e.x;
+        }
+        let mut closest_y = center.y;
+        if closest_y < box_center.y - e.y {
+            closest_y = box_center.y - e.y;
+        } else if closest_y > box_center.y + e.y {
+            closest_y = box_center.y + e.y;
+        }
+        let dx = center.x - closest_x;
+        let dy = center.y - closest_y;
+        if dx * dx + dy * dy <= radius * radius {
+            return 1u32;
+        }
+        return 0u32;
+    }
+    // only for circles and boxes
+    fn shapes_intersect(p1: Vec2F32, s1: Shape, p2: Vec2F32, s2: Shape) -> u32 {
+        if s1.kind == SHAPE_BOX && s2.kind == SHAPE_BOX {
+            return boxes_intersect(p1, s1.half_extents, p2, s2.half_extents);
+        }
+        if s1.kind == SHAPE_BOX {
+            return circle_box_intersect(p2, s2.radius, p1, s1.half_extents);
+        }
+        if s2.kind == SHAPE_BOX {
+            return circle_box_intersect(p1, s1.radius, p2, s2.half_extents);
+        }
+        return circles_intersect(p1, s1.radius, p2, s2.radius);
+    }
+    // must match swept_circles_time_of_impact, -1 if they don't overlap
+    fn swept_circles_time_of_impact(
+        start1: Vec2F32,
+        end1: Vec2F32,
+        r1: f32,
+        start2: Vec2F32,
+        end2: Vec2F32,
+        r2: f32,
+    ) -> f32 {
+        if r1 <= 0.0 || r2 <= 0.0 {
+            return -1.0;
+        }
+        // the second circle's start and movement relative to the first
+        let start_x = start2.x - start1.x;
+        let start_y = start2.y - start1.y;
+        let movement_x = (end2.x - start2.x) - (end1.x - start1.x);
+        let movement_y = (end2.y - start2.y) - (end1.y - start1.y);
+        let radius_sum = r1 + r2;
+        let c = start_x * start_x + start_y * start_y - radius_sum * radius_sum;
+        if c < 0.0 {
+            return 0.0;
+        }
+        let a = movement_x * movement_x + movement_y * movement_y;
+        let b = start_x * movement_x + start_y * movement_y;
+        let discriminant = b * b - a * c;
+        if b >= 0.0 || discriminant <= 0.0 {
+            return -1.0;
+        }
+        let t = (-b - sqrt(discriminant)) / a;
+        if t < 1.0 {
+            return t;
+        }
+        return -1.0;
+    }
+    // touching counts, so the pre-check never rejects a pair the narrow phase accepts
+    fn bounding_circles_intersect(p1: Vec2F32, r1: f32, p2: Vec2F32, r2: f32) -> u32 {
+        let radius_sum = r1 + r2;
+        if calculate_distance_squared(p1, p2) <= radius_sum * radius_sum {
+            return 1u32;
+        }
+        return 0u32;
+    }
+    fn orientation(o: Vec2F32, a: Vec2F32, b: Vec2F32) -> f32 {
+        return (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x);
+    }
+    // 1 if p is left of the line from a to b, 2 if it is right of it, 0 if it is on it
+    fn side_of_edge(a: Vec2F32, b: Vec2F32, p: Vec2F32) -> u32 {
+        let o = orientation(a, b, p);
+        if o > 0.0 {
+            return 1u32;
+        }
+        if o < 0.0 {
+            return 2u32;
+        }
+        return 0u32;
+    }
+    fn closest_point_on_segment(p: Vec2F32, a: Vec2F32, b: Vec2F32) -> Vec2F32 {
+        let abx = b.x - a.x;
+        let aby = b.y - a.y;
+        let length_squared = abx * abx + aby * aby;
+        // how far along the segment the closest point is, from 0 at a to 1 at b
+        let projection = (p.x - a.x) * abx + (p.y - a.y) * aby;
+        let mut t: f32 = 0.0;
+        if projection >= length_squared {
+            t = 1.0;
+        } else if projection > 0.0 {
+            t = projection / length_squared;
+        }
+        let mut closest = a;
+        closest.x += t * abx;
+        closest.y += t * aby;
+        return closest;
+    }
+    fn point_segment_distance_squared(p: Vec2F32, a: Vec2F32, b: Vec2F32) -> f32 {
+        return calculate_distance_squared(p, closest_point_on_segment(p, a, b));
+    }
+    // segments that only touch don't cross
+    fn segments_cross(a: Vec2F32, b: Vec2F32, c: Vec2F32, d: Vec2F32) -> u32 {
+        let o1 = orientation(c, d, a);
+        let o2 = orientation(c, d, b);
+        let o3 = orientation(a, b, c);
+        let o4 = orientation(a, b, d);
+        if ((o1 > 0.0 && o2 < 0.0) || (o1 < 0.0 && o2 > 0.0))
+            && ((o3 > 0.0 && o4 < 0.0) || (o3 < 0.0 && o4 > 0.0))
+        {
+            return 1u32;
+        }
+        return 0u32;
+    }
+    fn segments_distance_squared(a: Vec2F32, b: Vec2F32, c: Vec2F32, d: Vec2F32) -> f32 {
+        if segments_cross(a, b, c, d) == 1 {
+            return 0.0;
+        }
+        let mut best = point_segment_distance_squared(a, c, d);
+        let from_b = point_segment_distance_squared(b, c, d);
+        if from_b < best {
+            best = from_b;
+        }
+        let from_c = point_segment_distance_squared(c, a, b);
+        if from_c < best {
+            best = from_c;
+        }
+        let from_d = point_segment_distance_squared(d, a, b);
+        if from_d < best {
+            best = from_d;
+        }
+        return best;
+    }
+    // the core helpers take Shape's fields, since the cpu version of Shape can't be copied
+    fn core_segment_count(kind: u32, vertex_count: u32) -> u32 {
+        if kind == SHAPE_BOX {
+            return 4u32;
+        }
+        if kind == SHAPE_POLYGON {
+            return vertex_count;
+        }
+        return 1u32;
+    }
+    fn core_vertex_count(kind: u32, vertex_count: u32) -> u32 {
+        if kind == SHAPE_CAPSULE {
+            return 2u32;
+        }
+        return core_segment_count(kind, vertex_count);
+    }
+    fn is_closed(kind: u32) -> u32 {
+        if kind == SHAPE_BOX || kind == SHAPE_POLYGON {
+            return 1u32;
+        }
+        return 0u32;
+    }
+    // where a polygon's i-th vertex (wrapping around) is in the Vertex input, other shapes don't use it
+    fn vertex_index(kind: u32, vertex_start: u32, vertex_count: u32, i: u32) -> u32 {
+        if kind == SHAPE_POLYGON {
+            return vertex_start + i % vertex_count;
+        }
+        return 0u32;
+    }
+    // the i-th vertex of the shape's core, wrapping around. polygon_vertex is the Vertex at vertex_index
+    fn core_vertex(
+        position: Vec2F32,
+        kind: u32,
+        e: Vec2F32,
+        i: u32,
+        polygon_vertex: Vec2F32,
+    ) -> Vec2F32 {
+        // offset of the vertex from the position
+        let mut x: f32 = 0.0;
+        let mut y: f32 = 0.0;
+        if kind == SHAPE_POLYGON {
+            x = polygon_vertex.x;
+            y = polygon_vertex.y;
+        } else if kind == SHAPE_CAPSULE {
+            y = e.y;
+            if (i & 1u32) == 0 {
+                y = -e.y;
+            }
+        } else if kind == SHAPE_BOX {
+            let corner = i % 4;
+            x = e.x;
+            y = e.y;
+            if corner == 0 || corner == 3 {
+                x = -e.x;
+            }
+            if corner == 0 || corner == 1 {
+                y = -e.y;
+            }
+        }
+        let mut vertex = position;
+        vertex.x += x;
+        vertex.y += y;
+        return vertex;
+    }
+    // must match CollisionLayers::interacts_with
+    fn layers_interact(memberships1: u32, filters1: u32, memberships2: u32, filters2: u32) -> u32 {
+        if (memberships1 & filters2) != 0 && (memberships2 & filters1) != 0 {
+            return 1u32;
+        }
+        return 0u32;
+    }
+    pub fn main(iter_pos: WgslIterationPosition) {
+        let config = WgslConfigInput::get::<BroadphaseConfig>();
+        let num_collidables = WgslVecInput::vec_len::<Position>();
+        // every thread tests current_entity against the sorted index range other_entity..range_end, the range stays empty if there is nothing to test
+        let current_entity = config.query_start + iter_pos.x;
+        let mut other_entity: u32 = 0;
+        let mut range_end: u32 = 0;
+        if config.mode == BROADPHASE_SPATIAL_HASH {
+            // x is the collidable (in cell-sorted order), y is one of the 9 cells around it
+            if current_entity < config.query_end
+                && current_entity < num_collidables
+                && iter_pos.y < 9
+            {
+                let current_cell = WgslVecInput::vec_val::<GridCell>(current_entity);
+                let neighbor_x =
+                    (current_cell % config.cells_x) as i32 + (iter_pos.y % 3) as i32 - 1;
+                let neighbor_y =
+                    (current_cell / config.cells_x) as i32 + (iter_pos.y / 3) as i32 - 1;
+                if neighbor_x >= 0
+                    && neighbor_y >= 0
+                    && neighbor_x < config.cells_x as i32
+                    && neighbor_y < config.cells_y as i32
+                {
+                    let neighbor_cell = neighbor_y as u32 * config.cells_x + neighbor_x as u32;
+                    // the cell starts come after the cell of every collidable
+                    let neighbor_cell_start = num_collidables + neighbor_cell;
+                    let next_cell_start = neighbor_cell_start + 1;
+                    range_end = WgslVecInput::vec_val::<GridCell>(next_cell_start);
+                    other_entity = WgslVecInput::vec_val::<GridCell>(neighbor_cell_start);
+                    // only test pairs once, from the lower sorted index
+                    if other_entity <= current_entity {
+                        other_entity = current_entity + 1;
+                    }
+                }
+            }
+        } else {
+            // a single range job tests every collidable against the ones after it, a cross-batch job tests the first range (query_start..query_end) against all of the second range
+            let mut first_other = current_entity + 1;
+            if config.second_range_start > 0 {
+                first_other = config.second_range_start;
+            }
+            if config.count_only == 1 || config.whole_rows == 1 {
+                // one thread per collidable, against every collidable it pairs with. The dispatch is at least 2 long in y, so only the first row of threads does anything
+                if current_entity < config.query_end && iter_pos.y == 0 {
+                    other_entity = first_other;
+                    range_end = num_collidables;
+                }
+            } else {
+                // one thread per pair
+                other_entity = config.second_range_start + iter_pos.y;
+                if current_entity < config.query_end
+                    && other_entity >= first_other
+                    && other_entity < num_collidables
+                {
+                    range_end = other_entity + 1;
+                }
+            }
+        }
+        let mut count: u32 = 0;
+        while other_entity < range_end {
+            let current_pos = WgslVecInput::vec_val::<Position>(current_entity);
+            let current_shape = WgslVecInput::vec_val::<Shape>(current_entity);
+            let other_pos = WgslVecInput::vec_val::<Position>(other_entity);
+            let other_shape = WgslVecInput::vec_val::<Shape>(other_entity);
+            // the cores, used by the narrow phase for everything but circles and boxes, and by the contacts
+            let current_radius = current_shape.radius;
+            let other_radius = other_shape.radius;
+            let current_kind = current_shape.kind;
+            let current_start = current_shape.vertex_start;
+            let current_count = current_shape.vertex_count;
+            let current_extents = current_shape.half_extents;
+            let other_kind = other_shape.kind;
+            let other_start = other_shape.vertex_start;
+            let other_count = other_shape.vertex_count;
+            let other_extents = other_shape.half_extents;
+            let current_segments = core_segment_count(current_kind, current_count);
+            let other_segments = core_segment_count(other_kind, other_count);
+            // the first vertex of each core, which is the center of a circle. Only read for the pairs whose cores are compared, by the narrow phase or for the contact
+            let mut current_first = current_pos.v;
+            let mut other_first = other_pos.v;
+            // where the contact is worked out from, which is where swept circles first touch
+            let mut current_center = current_pos.v;
+            let mut other_center = other_pos.v;
+            let mut hit: u32 = 0;
+            let mut time_of_impact: f32 = -1.0;
+            let interacts = layers_interact(
+                current_shape.memberships,
+                current_shape.filters,
+                other_shape.memberships,
+                other_shape.filters,
+            );
+            if interacts == 1
+                && config.continuous == 1
+                && current_kind == SHAPE_CIRCLE
+                && other_kind == SHAPE_CIRCLE
+            {
+                // circles are only swept, from where they were when collisions were last detected
+                time_of_impact = swept_circles_time_of_impact(
+                    current_pos.previous,
+                    current_pos.v,
+                    current_radius,
+                    other_pos.previous,
+                    other_pos.v,
+                    other_radius,
+                );
+                if time_of_impact >= 0.0 {
+                    hit = 1u32;
+                    current_center.x = current_pos.previous.x
+                        + (current_pos.v.x - current_pos.previous.x) * time_of_impact;
+                    current_center.y = current_pos.previous.y
+                        + (current_pos.v.y - current_pos.previous.y) * time_of_impact;
+                    other_center.x = other_pos.previous.x
+                        + (other_pos.v.x - other_pos.previous.x) * time_of_impact;
+                    other_center.y = other_pos.previous.y
+                        + (other_pos.v.y - other_pos.previous.y) * time_of_impact;
+                    current_first = current_center;
+                    other_first = other_center;
+                }
+            } else if interacts == 1
+                && bounding_circles_intersect(
+                    current_pos.v,
+                    current_shape.bounding_radius,
+                    other_pos.v,
+                    other_shape.bounding_radius,
+                ) == 1
+            {
+                let mut compare_cores: u32 = 0;
+                if current_kind > SHAPE_BOX || other_kind > SHAPE_BOX {
+                    compare_cores = 1u32;
+                }
+                if compare_cores == 1 || (config.count_only == 0 && config.contacts == 1) {
+                    if current_kind != SHAPE_CIRCLE {
+                        let current_first_index =
+                            vertex_index(current_kind, current_start, current_count, 0u32);
+                        let current_first_vertex =
+                            WgslVecInput::vec_val::<Vertex>(current_first_index);
+                        current_first = core_vertex(
+                            current_pos.v,
+                            current_kind,
+                            current_extents,
+                            0u32,
+                            current_first_vertex.v,
+                        );
+                    }
+                    if other_kind != SHAPE_CIRCLE {
+                        let other_first_index =
+                            vertex_index(other_kind, other_start, other_count, 0u32);
+                        let other_first_vertex = WgslVecInput::vec_val::<Vertex>(other_first_index);
+                        other_first = core_vertex(
+                            other_pos.v,
+                            other_kind,
+                            other_extents,
+                            0u32,
+                            other_first_vertex.v,
+                        );
+                    }
+                }
+                if compare_cores == 0 {
+                    hit = shapes_intersect(current_pos.v, current_shape, other_pos.v, other_shape);
+                } else {
+                    // compare every pair of core segments, and the first vertex of each core against every edge of the other, to find the distance between the cores
+                    let mut current_sides: u32 = 0;
+                    let mut other_sides: u32 = 0;
+                    let mut distance_squared: f32 = -1.0;
+                    let mut i: u32 = 0;
+                    while i < current_segments {
+                        let a_index = vertex_index(current_kind, current_start, current_count, i);
+                        let a_vertex = WgslVecInput::vec_val::<Vertex>(a_index);
+                        let a = core_vertex(
+                            current_pos.v,
+                            current_kind,
+                            current_extents,
+                            i,
+                            a_vertex.v,
+                        );
+                        let b_index =
+                            vertex_index(current_kind, current_start, current_count, i + 1);
+                        let b_vertex = WgslVecInput::vec_val::<Vertex>(b_index);
+                        let b = core_vertex(
+                            current_pos.v,
+                            current_kind,
+                            current_extents,
+                            i + 1,
+                            b_vertex.v,
+                        );
+                        current_sides |= side_of_edge(a, b, other_first);
+                        let mut j: u32 = 0;
+                        while j < other_segments {
+                            let c_index = vertex_index(other_kind, other_start, other_count, j);
+                            let c_vertex = WgslVecInput::vec_val::<Vertex>(c_index);
+                            let c =
+                                core_vertex(other_pos.v, other_kind, other_extents, j, c_vertex.v);
+                            let d_index = vertex_index(other_kind, other_start, other_count, j + 1);
+                            let d_vertex = WgslVecInput::vec_val::<Vertex>(d_index);
+                            let d = core_vertex(
+                                other_pos.v,
+                                other_kind,
+                                other_extents,
+                                j + 1,
+                                d_vertex.v,
+                            );
+                            if i == 0 {
+                                other_sides |= side_of_edge(c, d, current_first);
+                            }
+                            let segment_distance = segments_distance_squared(a, b, c, d);
+                            if distance_squared < 0.0 || segment_distance < distance_squared {
+                                distance_squared = segment_distance;
+                            }
+                            j += 1u32;
+                        }
+                        i += 1u32;
+                    }
+                    // a closed core contains the other core's first vertex if it is never on the outside of one of its edges
+                    if (is_closed(current_kind) == 1 && current_sides != 3)
+                        || (is_closed(other_kind) == 1 && other_sides != 3)
+                    {
+                        distance_squared = 0.0;
+                    }
+                    let radius_sum = current_shape.radius + other_shape.radius;
+                    if distance_squared <= radius_sum * radius_sum {
+                        hit = 1u32;
+                    }
+                }
+            }
+            // must match Collider::contact: apart cores give the contact along their closest points, overlapping cores use the axis they overlap the least on, out of the edge normals of both cores and the y axis
+            let mut normal = current_pos.v;
+            normal.x = 0.0;
+            normal.y = 0.0;
+            let mut point = normal;
+            let mut depth: f32 = 0.0;
+            if hit == 1 && config.count_only == 0 && config.contacts == 1 {
+                let mut cores_overlap: u32 = 0;
+                let mut current_sides: u32 = 0;
+                let mut other_sides: u32 = 0;
+                let mut closest_squared: f32 = -1.0;
+                let mut closest_current = current_first;
+                let mut closest_other = other_first;
+                let mut i: u32 = 0;
+                while i < current_segments {
+                    let a_index = vertex_index(current_kind, current_start, current_count, i);
+                    let a_vertex = WgslVecInput::vec_val::<Vertex>(a_index);
+                    let a =
+                        core_vertex(current_center, current_kind, current_extents, i, a_vertex.v);
+                    let b_index = vertex_index(current_kind, current_start, current_count, i + 1);
+                    let b_vertex = WgslVecInput::vec_val::<Vertex>(b_index);
+                    let b = core_vertex(
+                        current_center,
+                        current_kind,
+                        current_extents,
+                        i + 1,
+                        b_vertex.v,
+                    );
+                    current_sides |= side_of_edge(a, b, other_first);
+                    let mut j: u32 = 0;
+                    while j < other_segments {
+                        let c_index = vertex_index(other_kind, other_start, other_count, j);
+                        let c_vertex = WgslVecInput::vec_val::<Vertex>(c_index);
+                        let c = core_vertex(other_center, other_kind, other_extents, j, c_vertex.v);
+                        let d_index = vertex_index(other_kind, other_start, other_count, j + 1);
+                        let d_vertex = WgslVecInput::vec_val::<Vertex>(d_index);
+                        let d =
+                            core_vertex(other_center, other_kind, other_extents, j + 1, d_vertex.v);
+                        if i == 0 {
+                            other_sides |= side_of_edge(c, d, current_first);
+                        }
+                        if segments_cross(a, b, c, d) == 1 {
+                            cores_overlap = 1u32;
+                        }
+                        // the closest points of two segments that don't cross include an endpoint of one of them
+                        let on_other_from_a = closest_point_on_segment(a, c, d);
+                        let from_a = calculate_distance_squared(a, on_other_from_a);
+                        if closest_squared < 0.0 || from_a < closest_squared {
+                            closest_squared = from_a;
+                            closest_current = a;
+                            closest_other = on_other_from_a;
+                        }
+                        let on_other_from_b = closest_point_on_segment(b, c, d);
+                        let from_b = calculate_distance_squared(b, on_other_from_b);
+                        if from_b < closest_squared {
+                            closest_squared = from_b;
+                            closest_current = b;
+                            closest_other = on_other_from_b;
+                        }
+                        let on_current_from_c = closest_point_on_segment(c, a, b);
+                        let from_c = calculate_distance_squared(on_current_from_c, c);
+                        if from_c < closest_squared {
+                            closest_squared = from_c;
+                            closest_current = on_current_from_c;
+                            closest_other = c;
+                        }
+                        let on_current_from_d = closest_point_on_segment(d, a, b);
+                        let from_d = calculate_distance_squared(on_current_from_d, d);
+                        if from_d < closest_squared {
+                            closest_squared = from_d;
+                            closest_current = on_current_from_d;
+                            closest_other = d;
+                        }
+                        j += 1u32;
+                    }
+                    i += 1u32;
+                }
+                if (is_closed(current_kind) == 1 && current_sides != 3)
+                    || (is_closed(other_kind) == 1 && other_sides != 3)
+                {
+                    cores_overlap = 1u32;
+                }
+                if cores_overlap == 0 && closest_squared > 0.0 {
+                    let distance = sqrt(closest_squared);
+                    normal.x = (closest_other.x - closest_current.x) / distance;
+                    normal.y = (closest_other.y - closest_current.y) / distance;
+                    depth = current_radius + other_radius - distance;
+                    point.x = closest_current.x + normal.x * (current_radius - depth * 0.5);
+                    point.y = closest_current.y + normal.y * (current_radius - depth * 0.5);
+                } else {
+                    let current_vertices = core_vertex_count(current_kind, current_count);
+                    let other_vertices = core_vertex_count(other_kind, other_count);
+                    let axis_count = current_segments + other_segments + 1;
+                    let mut has_axis: u32 = 0;
+                    let mut k: u32 = 0;
+                    while k < axis_count {
+                        // the y axis comes last, so there is one even when both cores are points
+                        let mut axis = normal;
+                        axis.x = 0.0;
+                        axis.y = 1.0;
+                        if k < axis_count - 1 {
+                            let mut edge_position = current_center;
+                            let mut edge_kind = current_kind;
+                            let mut edge_start = current_start;
+                            let mut edge_count = current_count;
+                            let mut edge_extents = current_extents;
+                            let mut e: u32 = k;
+                            if k >= current_segments {
+                                edge_position = other_center;
+                                edge_kind = other_kind;
+                                edge_start = other_start;
+                                edge_count = other_count;
+                                edge_extents = other_extents;
+                                e = k - current_segments;
+                            }
+                            let p_index = vertex_index(edge_kind, edge_start, edge_count, e);
+                            let p_vertex = WgslVecInput::vec_val::<Vertex>(p_index);
+                            let p =
+                                core_vertex(edge_position, edge_kind, edge_extents, e, p_vertex.v);
+                            let q_index = vertex_index(edge_kind, edge_start, edge_count, e + 1);
+                            let q_vertex = WgslVecInput::vec_val::<Vertex>(q_index);
+                            let q = core_vertex(
+                                edge_position,
+                                edge_kind,
+                                edge_extents,
+                                e + 1,
+                                q_vertex.v,
+                            );
+                            axis.x = -(q.y - p.y);
+                            axis.y = q.x - p.x;
+                        }
+                        let axis_length_squared = dot(axis, axis);
+                        if axis_length_squared > 0.0 {
+                            let axis_length = sqrt(axis_length_squared);
+                            axis.x /= axis_length;
+                            axis.y /= axis_length;
+                            let mut current_min: f32 = 0.0;
+                            let mut current_max: f32 = 0.0;
+                            let mut v: u32 = 0;
+                            while v < current_vertices {
+                                let v_index =
+                                    vertex_index(current_kind, current_start, current_count, v);
+                                let polygon_vertex = WgslVecInput::vec_val::<Vertex>(v_index);
+                                let vertex = core_vertex(
+                                    current_center,
+                                    current_kind,
+                                    current_extents,
+                                    v,
+                                    polygon_vertex.v,
+                                );
+                                let projection = dot(vertex, axis);
+                                if v == 0 || projection < current_min {
+                                    current_min = projection;
+                                }
+                                if v == 0 || projection > current_max {
+                                    current_max = projection;
+                                }
+                                v += 1u32;
+                            }
+                            let mut other_min: f32 = 0.0;
+                            let mut other_max: f32 = 0.0;
+                            v = 0u32;
+                            while v < other_vertices {
+                                let v_index = vertex_index(other_kind, other_start, other_count, v);
+                                let polygon_vertex = WgslVecInput::vec_val::<Vertex>(v_index);
+                                let vertex = core_vertex(
+                                    other_center,
+                                    other_kind,
+                                    other_extents,
+                                    v,
+                                    polygon_vertex.v,
+                                );
+                                let projection = dot(vertex, axis);
+                                if v == 0 || projection < other_min {
+                                    other_min = projection;
+                                }
+                                if v == 0 || projection > other_max {
+                                    other_max = projection;
+                                }
+                                v += 1u32;
+                            }
+                            // how far the other core has to move forwards or backwards along the axis to stop overlapping
+                            let forward = current_max - other_min;
+                            let backward = other_max - current_min;
+                            let mut axis_depth = forward;
+                            let mut direction: f32 = 1.0;
+                            if backward < forward {
+                                axis_depth = backward;
+                                direction = -1.0;
+                            }
+                            axis_depth += current_radius + other_radius;
+                            if has_axis == 0 || axis_depth < depth {
+                                has_axis = 1u32;
+                                depth = axis_depth;
+                                normal.x = axis.x * direction;
+                                normal.y = axis.y * direction;
+                            }
+                        }
+                        k += 1u32;
+                    }
+                    // the other core's vertex deepest inside the current one
+                    let mut deepest = other_first;
+                    let mut v: u32 = 1;
+                    while v < other_vertices {
+                        let v_index = vertex_index(other_kind, other_start, other_count, v);
+                        let polygon_vertex = WgslVecInput::vec_val::<Vertex>(v_index);
+                        let vertex = core_vertex(
+                            other_center,
+                            other_kind,
+                            other_extents,
+                            v,
+                            polygon_vertex.v,
+                        );
+                        if dot(vertex, normal) < dot(deepest, normal) {
+                            deepest = vertex;
+                        }
+                        v += 1u32;
+                    }
+                    point.x = deepest.x - normal.x * other_radius + normal.x * depth * 0.5;
+                    point.y = deepest.y - normal.y * other_radius + normal.y * depth * 0.5;
+                }
+            }
+            // float error can leave swept circles a hair apart at the time of impact, see swept_contact
+            if time_of_impact >= 0.0 && depth < 0.0 {
+                depth = 0.0;
+            }
+            if hit == 1 {
+                if config.count_only == 1 {
+                    count += 1u32;
+                } else if config.contacts == 1 || config.continuous == 1 {
+                    WgslOutput::push::<CollisionContact>(CollisionContact {
+                        entity1: current_entity,
+                        entity2: other_entity,
+                        normal,
+                        point,
+                        depth,
+                        time_of_impact,
+                    });
+                } else {
+                    WgslOutput::push::<CollisionResult>(CollisionResult {
+                        entity1: current_entity,
+                        entity2: other_entity,
+                    });
+                }
+            }
+            other_entity += 1u32;
+        }
+        if count > 0 {
             WgslOutput::push::<CollisionResult>(CollisionResult {
                 entity1: current_entity,
-                entity2: other_entity,
+                entity2: count,
             });
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::gpu_collision_detection::test_helpers::assert_valid_wgsl;
+
+    use super::collision_detection_module;
+
+    #[test]
+    fn test_generated_wgsl_is_valid() {
+        assert_valid_wgsl(collision_detection_module::parsed());
+    }
+}
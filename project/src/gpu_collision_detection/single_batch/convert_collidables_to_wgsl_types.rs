@@ -1,30 +1,82 @@
 use bevy::{
     log,
+    math::{Vec2, Vec3},
     prelude::{Entity, Res, ResMut},
 };
 use bevy_gpu_compute::prelude::Vec2F32;
 
-use crate::gpu_collision_detection::{
-    entity_metadata::CollidableMetadata, shader::collision_detection_module,
+use crate::{
+    components_and_resources::{CollisionLayers, CollisionUserData},
+    gpu_collision_detection::{
+        entity_metadata::CollidableMetadata,
+        multi_batch_manager::resources::{GpuCollisionBatchJobs, GpuCollisionBatchManager},
+        resources::AllCollidablesThisFrame,
+        shader::collision_detection_module,
+    },
 };
 
-use super::resources::{CollidablesBatch, SingleBatchDataForWgsl, WgslIdToMetadataMap};
+use super::resources::{SingleBatchDataForWgsl, WgslIdToMetadataMap};
+
+/// What the narrow phase tests, the broadphase only uses the bounding radius
+#[derive(Debug, Clone, PartialEq)]
+pub enum CollidableShape {
+    Circle,
+    Box {
+        half_size: Vec2,
+    },
+    /// Vertical, like bevy's `Capsule2d`
+    Capsule {
+        radius: f32,
+        half_length: f32,
+    },
+    /// Vertices are relative to the center
+    ConvexPolygon {
+        vertices: Vec<Vec2>,
+    },
+    /// Only in 3D, like `Cuboid`
+    Sphere,
+    Cuboid {
+        half_size: Vec3,
+    },
+}
+
+impl CollidableShape {
+    /// The value of `Shape::kind` in the 2D or 3D shader
+    pub fn shader_kind(&self) -> u32 {
+        match self {
+            CollidableShape::Circle | CollidableShape::Sphere => 0,
+            CollidableShape::Box { .. } | CollidableShape::Cuboid { .. } => 1,
+            CollidableShape::Capsule { .. } => 2,
+            CollidableShape::ConvexPolygon { .. } => 3,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct PerCollidableDataRequiredByGpu {
     pub center_x: f32,
     pub center_y: f32,
+    /// 0 for 2D collidables
+    pub center_z: f32,
+    /// Where the center was when collisions were last detected, the same as the center unless it has a `PreviousPosition`. Unused in 3D
+    pub previous_x: f32,
+    pub previous_y: f32,
+    /// Radius of the circle or sphere, or of the smallest circle or sphere around any other shape
     pub radius: f32,
+    pub shape: CollidableShape,
     pub entity: Entity,
     pub is_sensor: bool,
+    pub layers: CollisionLayers,
+    pub user_data: Option<CollisionUserData>,
 }
 
 pub fn convert_collidables_to_wgsl_types(
     collidables: std::vec::Vec<PerCollidableDataRequiredByGpu>,
-    mut wgsl_id_to_metadata: &mut WgslIdToMetadataMap,
+    wgsl_id_to_metadata: &mut WgslIdToMetadataMap,
 ) -> SingleBatchDataForWgsl {
     let mut positions = Vec::new();
-    let mut radii = Vec::new();
+    let mut shapes = Vec::new();
+    let mut vertices = Vec::new();
     wgsl_id_to_metadata.0 = Vec::new();
 
     let mut count = 0;
@@ -36,8 +88,41 @@ pub fn convert_collidables_to_wgsl_types(
             //  we need the x and y position, and the radius,and the entity and if it is a sensor or not
             .push(collision_detection_module::Position {
                 v: Vec2F32::new(collidable.center_x, collidable.center_y),
+                previous: Vec2F32::new(collidable.previous_x, collidable.previous_y),
             });
-        radii.push(collidable.radius);
+        let vertex_start = vertices.len() as u32;
+        let (half_extents, radius) = match &collidable.shape {
+            CollidableShape::Circle => (Vec2::ZERO, collidable.radius),
+            CollidableShape::Box { half_size } => (*half_size, 0.),
+            CollidableShape::Capsule {
+                radius,
+                half_length,
+            } => (Vec2::new(0., *half_length), *radius),
+            CollidableShape::ConvexPolygon {
+                vertices: polygon_vertices,
+            } => {
+                vertices.extend(polygon_vertices.iter().map(|v| {
+                    collision_detection_module::Vertex {
+                        v: Vec2F32::new(v.x, v.y),
+                    }
+                }));
+                (Vec2::ZERO, 0.)
+            }
+            CollidableShape::Sphere | CollidableShape::Cuboid { .. } => {
+                unreachable!("3D shapes are converted by convert_collidables_to_wgsl_types_3d")
+            }
+        };
+        shapes.push(collision_detection_module::Shape {
+            half_extents: Vec2F32::new(half_extents.x, half_extents.y),
+            bounding_radius: collidable.radius,
+            radius,
+            kind: collidable.shape.shader_kind(),
+            vertex_start,
+            vertex_count: vertices.len() as u32 - vertex_start,
+            memberships: collidable.layers.memberships,
+            filters: collidable.layers.filters,
+            padding: 0,
+        });
         wgsl_id_to_metadata
             .0
             .push(CollidableMetadata::from(collidable));
@@ -53,5 +138,33 @@ pub fn convert_collidables_to_wgsl_types(
     );
 
     log::info!("sensor count in convert coll to wgsl...count: {}", count);
-    SingleBatchDataForWgsl { positions, radii }
+    // inputs can't be empty
+    if vertices.is_empty() {
+        vertices.push(collision_detection_module::Vertex {
+            v: Vec2F32::new(0., 0.),
+        });
+    }
+    SingleBatchDataForWgsl {
+        positions,
+        shapes,
+        vertices,
+    }
+}
+
+/// Converts the collidables of the current brute force batch job, so the counting pass and the detection pass upload the same data.
+/// For cross-batch jobs the second range is appended after the first.
+pub fn convert_batch_to_wgsl_types(
+    batch_manager: Res<GpuCollisionBatchManager>,
+    jobs: Res<GpuCollisionBatchJobs>,
+    all_collidables: Res<AllCollidablesThisFrame>,
+    mut wgsl_id_to_metadata: ResMut<WgslIdToMetadataMap>,
+    mut wgsl_data: ResMut<SingleBatchDataForWgsl>,
+) {
+    let job = &jobs.0[batch_manager.current_batch_job];
+    let mut batch: Vec<PerCollidableDataRequiredByGpu> =
+        all_collidables.0[job.start_index_incl..job.end_index_excl].to_vec();
+    if let Some(second_range) = job.second_range() {
+        batch.extend_from_slice(&all_collidables.0[second_range]);
+    }
+    *wgsl_data = convert_collidables_to_wgsl_types(batch, &mut wgsl_id_to_metadata);
 }
@@ -0,0 +1,68 @@
+use bevy::{
+    log,
+    prelude::{Res, ResMut},
+};
+
+use crate::{
+    components_and_resources::ContinuousCollisionDetection,
+    gpu_collision_detection::{
+        multi_batch_manager::resources::{GpuCollisionBatchJobs, GpuCollisionBatchManager},
+        resources::GpuBroadphase,
+        spatial_hash::{
+            grid::SpatialHashGrid,
+            initialize_spatial_hash_batch::{NEIGHBORHOOD_SIZE, spatial_hash_config},
+        },
+    },
+};
+
+use super::{
+    initialize_batch::brute_force_config,
+    resources::{CollisionShaderOutput, CollisionShaderPass, ResultsCountFromGpu},
+};
+
+/// First pass of exact output sizing, used when there is no `MaxDetectableCollisionsScale`.
+///
+/// Runs the same narrow phase as the detection pass, but each thread only pushes how many collisions it found, so the output length is known before any results are allocated. Brute force batches dispatch one thread per collidable of the first range that loops over every collidable it pairs with, so there are at most N counts instead of N².
+pub fn count_collisions_in_batch(
+    batch_manager: Res<GpuCollisionBatchManager>,
+    jobs: Res<GpuCollisionBatchJobs>,
+    broadphase: Res<GpuBroadphase>,
+    grid: Res<SpatialHashGrid>,
+    continuous: Res<ContinuousCollisionDetection>,
+    mut pass: ResMut<CollisionShaderPass>,
+) {
+    let job = &jobs.0[batch_manager.current_batch_job];
+    let l = job.end_index_excl - job.start_index_incl;
+    // 3D falls back to brute force before detection runs, see `fall_back_to_brute_force`
+    match *broadphase {
+        GpuBroadphase::BruteForce => {
+            pass.set_iteration_space(l, 1);
+            pass.max_collision_results = l.max(1);
+            pass.config = brute_force_config(job, true, false, continuous.0);
+        }
+        GpuBroadphase::SpatialHash => {
+            pass.set_iteration_space(l, NEIGHBORHOOD_SIZE);
+            pass.max_collision_results = (l * NEIGHBORHOOD_SIZE).max(1);
+            pass.config = spatial_hash_config(job, &grid, true, false);
+        }
+    }
+}
+
+pub fn read_collision_count_from_gpu(
+    batch_jobs: Res<GpuCollisionBatchJobs>,
+    batch_manager: Res<GpuCollisionBatchManager>,
+    output: Res<CollisionShaderOutput>,
+    mut results_count: ResMut<ResultsCountFromGpu>,
+) {
+    let job = &batch_jobs.0[batch_manager.current_batch_job];
+    results_count.0 = output
+        .collision_counts
+        .iter()
+        .map(|count| *count as usize)
+        .sum();
+    log::info!(
+        "{} collisions counted for job: {}",
+        results_count.0,
+        job.name
+    );
+}
@@ -0,0 +1,520 @@
+use bevy::prelude::{Res, ResMut};
+use bevy_gpu_compute::prelude::{IterationSpace, WgslIterationPosition};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::gpu_collision_detection::{
+    resources::GpuBroadphase, shader::collision_detection_module,
+    spatial_hash::grid::SpatialHashGrid,
+};
+
+use super::{
+    resources::{
+        CollisionShaderOutput, CollisionShaderPass, ShaderCollisionResult, SingleBatchDataForWgsl,
+    },
+    run_collision_pass_on_gpu::grid_cell_input,
+};
+
+/// Runs the collision shader's `main` on the CPU, once per point of the iteration space, in parallel with rayon.
+///
+/// `#[wgsl_shader_module]` generates the CPU version of `main`, but it takes every input array by value, so each call gets its own copy of the inputs. To keep that to one copy per collidable instead of one per pair, brute force detection passes are dispatched like the counting pass, see `emulated_dispatch`. This is still far slower than the GPU; it is meant for exercising the batching pipeline in tests and on machines without a GPU adapter, not for performance.
+pub fn emulate_collision_pass(
+    pass: Res<CollisionShaderPass>,
+    wgsl_data: Res<SingleBatchDataForWgsl>,
+    broadphase: Res<GpuBroadphase>,
+    grid: Res<SpatialHashGrid>,
+    mut output: ResMut<CollisionShaderOutput>,
+) {
+    let grid_cells = grid_cell_input(*broadphase, &grid);
+    let (iteration_space, config) = emulated_dispatch(&pass);
+    let max_lengths = pass.output_lengths(config.pushes_contacts());
+    let (results, contacts, pushed_count) = emulate_shader(
+        iteration_space,
+        max_lengths,
+        |iter_pos, results, contacts| {
+            collision_detection_module::main(
+                iter_pos,
+                config,
+                wgsl_data.positions.clone(),
+                wgsl_data.shapes.clone(),
+                wgsl_data.vertices.clone(),
+                grid_cells.clone(),
+                results,
+                contacts,
+            );
+        },
+    );
+    let pushed = results
+        .iter()
+        .map(ShaderCollisionResult::from)
+        .chain(contacts.iter().map(ShaderCollisionResult::from))
+        .collect();
+    output.set_pushed(config.count_only == 1, pushed, pushed_count);
+}
+
+/// The iteration space and config to emulate `pass` with. Brute force detection passes get one thread per collidable of the first range with `whole_rows`, instead of one per pair, which finds the same results
+pub fn emulated_dispatch(
+    pass: &CollisionShaderPass,
+) -> (IterationSpace, collision_detection_module::BroadphaseConfig) {
+    let mut config = pass.config;
+    if config.mode != GpuBroadphase::BruteForce.shader_mode() || config.count_only == 1 {
+        return (pass.iteration_space, config);
+    }
+    config.whole_rows = 1;
+    (IterationSpace::new(pass.iteration_space.x(), 1, 1), config)
+}
+
+/// Calls `main` (a shader module's CPU version of it) for every point of `space`, and combines what the calls pushed to `CollisionResult` and `CollisionContact`, no longer than their maximum lengths. Also returns how many results were pushed, like the push counter does, before the ones past the maximum length are dropped
+pub fn emulate_shader<R: Send, C: Send>(
+    space: IterationSpace,
+    (max_results, max_contacts): (usize, usize),
+    main: impl Fn(WgslIterationPosition, &mut Vec<R>, &mut Vec<C>) + Sync,
+) -> (Vec<R>, Vec<C>, usize) {
+    let (mut results, mut contacts) = (0..space.x() * space.y() * space.z())
+        .into_par_iter()
+        .fold(
+            || (Vec::new(), Vec::new()),
+            |(mut results, mut contacts), i| {
+                let iter_pos = WgslIterationPosition {
+                    x: (i % space.x()) as u32,
+                    y: ((i / space.x()) % space.y()) as u32,
+                    z: (i / (space.x() * space.y())) as u32,
+                };
+                main(iter_pos, &mut results, &mut contacts);
+                (results, contacts)
+            },
+        )
+        .reduce(
+            || (Vec::new(), Vec::new()),
+            |(mut results, mut contacts), (other_results, other_contacts)| {
+                results.extend(other_results);
+                contacts.extend(other_contacts);
+                (results, contacts)
+            },
+        );
+    // a pass only pushes to one of the outputs
+    let pushed = results.len() + contacts.len();
+    // on the GPU pushes past the maximum length are dropped
+    results.truncate(max_results);
+    contacts.truncate(max_contacts);
+    (results, contacts, pushed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+    use bevy::{
+        app::Plugin,
+        ecs::event::Events,
+        math::{
+            Vec2,
+            bounding::{Aabb2d, BoundingCircle},
+            primitives::Capsule2d,
+        },
+        prelude::{Entity, Transform},
+    };
+
+    use crate::{
+        colliding_pair::{CollidingPairs, Contact},
+        components_and_resources::{
+            BoundingBoxComponent, BoundingCircleComponent, CapsuleComponent, CollisionLayers,
+            ConvexPolygonComponent,
+        },
+        cpu_collision_detection::{
+            collider::Collider, cpu_collision_detection::CpuCollisionDetectionPlugin,
+        },
+        gpu_collision_detection::{
+            plugin::GpuCollisionDetectionPlugin,
+            resources::{
+                CollisionDimensions, CollisionOverflowPolicy, GpuBroadphase,
+                MaxDetectableCollisionsScale,
+            },
+            single_batch::{
+                convert_collidables_to_wgsl_types::CollidableShape, overflow::CollisionOverflow,
+                resources::ResultsCountFromGpu,
+            },
+            test_helpers::{self, detect_spawned, expected_pairs, test_app, unordered},
+        },
+    };
+
+    type Collidable = (Vec2, f32, CollidableShape, CollisionLayers);
+
+    /// Circles, boxes, capsules and triangles. Some collidables are on every layer and some only collide with the next layer over
+    fn collidables() -> Vec<Collidable> {
+        (0..60)
+            .map(|i| {
+                let position = Vec2::new((i * 37 % 100) as f32, (i * 61 % 100) as f32);
+                let radius = 4. + (i % 5) as f32;
+                let shape = match i % 6 {
+                    0 => CollidableShape::Box {
+                        half_size: Vec2::new(radius, 2. + (i % 4) as f32),
+                    },
+                    1 => CollidableShape::Capsule {
+                        radius: radius * 0.5,
+                        half_length: radius,
+                    },
+                    2 => CollidableShape::ConvexPolygon {
+                        vertices: vec![
+                            Vec2::new(-radius, -radius * 0.5),
+                            Vec2::new(radius, -radius * 0.5),
+                            Vec2::new(0., radius),
+                        ],
+                    },
+                    _ => CollidableShape::Circle,
+                };
+                let layers = match i % 4 {
+                    3 => CollisionLayers::ALL,
+                    layer => CollisionLayers::new(1 << layer, 1 << ((layer + 1) % 3)),
+                };
+                (position, radius, shape, layers)
+            })
+            // a circle that is inside a triangle without being close to any of its edges, and overlapping boxes and a capsule crossing a triangle, whose contacts come from their overlap instead of their closest points
+            .chain([
+                (
+                    Vec2::new(150., 150.),
+                    30.,
+                    CollidableShape::ConvexPolygon {
+                        vertices: vec![
+                            Vec2::new(-30., -15.),
+                            Vec2::new(30., -15.),
+                            Vec2::new(0., 30.),
+                        ],
+                    },
+                    CollisionLayers::ALL,
+                ),
+                (
+                    Vec2::new(150., 150.),
+                    2.,
+                    CollidableShape::Circle,
+                    CollisionLayers::ALL,
+                ),
+                (
+                    Vec2::new(200., 150.),
+                    8.,
+                    CollidableShape::Box {
+                        half_size: Vec2::splat(5.),
+                    },
+                    CollisionLayers::ALL,
+                ),
+                (
+                    Vec2::new(207., 152.),
+                    6.,
+                    CollidableShape::Box {
+                        half_size: Vec2::splat(4.),
+                    },
+                    CollisionLayers::ALL,
+                ),
+                (
+                    Vec2::new(250., 150.),
+                    12.,
+                    CollidableShape::ConvexPolygon {
+                        vertices: vec![
+                            Vec2::new(-10., -5.),
+                            Vec2::new(10., -5.),
+                            Vec2::new(0., 10.),
+                        ],
+                    },
+                    CollisionLayers::ALL,
+                ),
+                (
+                    Vec2::new(253., 157.),
+                    4.,
+                    CollidableShape::Capsule {
+                        radius: 1.,
+                        half_length: 3.,
+                    },
+                    CollisionLayers::ALL,
+                ),
+            ])
+            .collect()
+    }
+
+    fn collider((position, radius, shape, _): &Collidable) -> Collider {
+        match shape {
+            CollidableShape::Circle => Collider::Circle(BoundingCircle::new(*position, *radius)),
+            CollidableShape::Box { half_size } => Collider::Box(Aabb2d::new(*position, *half_size)),
+            CollidableShape::Capsule {
+                radius,
+                half_length,
+            } => Collider::Capsule {
+                center: *position,
+                capsule: Capsule2d {
+                    radius: *radius,
+                    half_length: *half_length,
+                },
+            },
+            CollidableShape::ConvexPolygon { vertices } => Collider::ConvexPolygon {
+                center: *position,
+                vertices: vertices.clone(),
+            },
+            CollidableShape::Sphere | CollidableShape::Cuboid { .. } => unreachable!(),
+        }
+    }
+
+    fn intersect(a: &Collidable, b: &Collidable) -> bool {
+        collider(a).intersects(&collider(b))
+    }
+
+    fn expected(collidables: &[Collidable]) -> BTreeSet<(usize, usize)> {
+        expected_pairs(collidables, |collidable| collidable.3, intersect)
+    }
+
+    fn detect_pairs(
+        plugin: GpuCollisionDetectionPlugin,
+        collidables: &[Collidable],
+    ) -> BTreeSet<(usize, usize)> {
+        unordered(&detect(plugin, collidables))
+    }
+
+    /// The indices of each pair's collidables, in the pair's order
+    fn detect(
+        plugin: GpuCollisionDetectionPlugin,
+        collidables: &[Collidable],
+    ) -> Vec<(usize, usize, Option<Contact>)> {
+        let mut app = test_app(plugin);
+        let spawn_order: HashMap<_, _> = collidables
+            .iter()
+            .enumerate()
+            .map(|(i, (position, radius, shape, layers))| {
+                let mut entity = app
+                    .world_mut()
+                    .spawn((Transform::from_xyz(position.x, position.y, 0.), *layers));
+                match shape {
+                    CollidableShape::Circle => entity.insert(BoundingCircleComponent(
+                        BoundingCircle::new(*position, *radius),
+                    )),
+                    CollidableShape::Box { half_size } => {
+                        entity.insert(BoundingBoxComponent(Aabb2d::new(*position, *half_size)))
+                    }
+                    CollidableShape::Capsule {
+                        radius,
+                        half_length,
+                    } => entity.insert(CapsuleComponent(Capsule2d {
+                        radius: *radius,
+                        half_length: *half_length,
+                    })),
+                    CollidableShape::ConvexPolygon { vertices } => {
+                        entity.insert(ConvexPolygonComponent(vertices.clone()))
+                    }
+                    CollidableShape::Sphere | CollidableShape::Cuboid { .. } => unreachable!(),
+                };
+                (entity.id(), i)
+            })
+            .collect();
+        detect_spawned(&mut app, &spawn_order)
+    }
+
+    fn emulated_plugin() -> GpuCollisionDetectionPlugin {
+        test_helpers::emulated_plugin(CollisionDimensions::Two)
+    }
+
+    #[test]
+    fn test_emulated_pipeline_matches_brute_force() {
+        let collidables = collidables();
+        let expected = expected(&collidables);
+        assert!(!expected.is_empty());
+        for broadphase in [GpuBroadphase::BruteForce, GpuBroadphase::SpatialHash] {
+            // a scale too small for the batches, so overflowing batches are rerun
+            let plugin = emulated_plugin().with_broadphase(broadphase);
+            assert_eq!(
+                detect_pairs(plugin, &collidables),
+                expected,
+                "{:?}",
+                broadphase
+            );
+            let plugin = emulated_plugin()
+                .with_broadphase(broadphase)
+                .with_exact_output_sizing();
+            assert_eq!(
+                detect_pairs(plugin, &collidables),
+                expected,
+                "{:?}",
+                broadphase
+            );
+        }
+    }
+
+    #[test]
+    fn test_emulated_contacts_match_the_cpu() {
+        let collidables = collidables();
+        for broadphase in [GpuBroadphase::BruteForce, GpuBroadphase::SpatialHash] {
+            let plugin = emulated_plugin()
+                .with_broadphase(broadphase)
+                .with_contacts();
+            let pairs = detect(plugin, &collidables);
+            assert!(!pairs.is_empty());
+            for (a, b, contact) in pairs {
+                let contact = contact.expect("contacts were turned on");
+                let expected = collider(&collidables[a]).contact(&collider(&collidables[b]));
+                assert!(
+                    contact.normal.abs_diff_eq(expected.normal, 1e-4)
+                        && (contact.depth - expected.depth).abs() < 1e-4
+                        && contact.point.abs_diff_eq(expected.point, 1e-4),
+                    "{:?} {:?}: {:?} != {:?}",
+                    collidables[a],
+                    collidables[b],
+                    contact,
+                    expected
+                );
+            }
+        }
+        assert!(
+            detect(emulated_plugin(), &collidables)
+                .iter()
+                .all(|(_, _, contact)| contact.is_none())
+        );
+    }
+
+    #[test]
+    fn test_emulated_pipeline_skips_invalid_polygons() {
+        let mut collidables = collidables();
+        let expected = detect_pairs(emulated_plugin(), &collidables);
+        // an empty polygon and one with all of its vertices on a line, on top of other collidables
+        for vertices in [Vec::new(), vec![Vec2::ZERO, Vec2::X, Vec2::X * 2.]] {
+            collidables.push((
+                collidables[0].0,
+                1.,
+                CollidableShape::ConvexPolygon { vertices },
+                CollisionLayers::ALL,
+            ));
+        }
+        assert_eq!(detect_pairs(emulated_plugin(), &collidables), expected);
+    }
+
+    /// Runs one frame over circles of radius 1 and returns the overflow events, the scale afterwards and whether the counting pass ran
+    fn overflow_after_a_frame(
+        centers: impl Iterator<Item = Vec2>,
+        scale: f32,
+    ) -> (Vec<CollisionOverflow>, f32, bool) {
+        let mut app = test_app(
+            emulated_plugin()
+                .with_overflow_policy(CollisionOverflowPolicy::GrowScale)
+                .with_max_detectable_collisions_scale(scale),
+        );
+        for center in centers {
+            app.world_mut().spawn((
+                Transform::from_translation(center.extend(0.)),
+                BoundingCircleComponent(BoundingCircle::new(center, 1.)),
+            ));
+        }
+        app.update();
+        let events = app.world().resource::<Events<CollisionOverflow>>();
+        (
+            events.iter_current_update_events().cloned().collect(),
+            app.world().resource::<MaxDetectableCollisionsScale>().0,
+            app.world().resource::<ResultsCountFromGpu>().0 > 0,
+        )
+    }
+
+    #[test]
+    fn test_emulated_overflow_grows_the_scale() {
+        // 20 circles have 190 pairs, so a scale of 0.1 has room for 19 results
+        let row = (0..20).map(|i| Vec2::new(i as f32 * 1.5, 0.));
+        let (events, scale, counted) = overflow_after_a_frame(row, 0.1);
+        // exactly filling the buffer is not an overflow, and needs no counting pass
+        assert!(events.is_empty() && !counted);
+        assert_eq!(scale, 0.1);
+        let pile = (0..20).map(|i| Vec2::new(i as f32 * 0.01, 0.));
+        let (events, scale, counted) = overflow_after_a_frame(pile, 0.1);
+        assert!(counted);
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].requested, events[0].available), (190, 19));
+        assert_eq!(scale, 1.);
+    }
+
+    /// Spawns circles at their starts, detects once, moves them to their ends and detects again. Returns every pair's time of impact
+    fn swept_pairs(
+        plugin: impl Plugin,
+        circles: &[(Vec2, Vec2, f32)],
+    ) -> BTreeMap<(usize, usize), f32> {
+        let mut app = test_app(plugin);
+        let entities: Vec<Entity> = circles
+            .iter()
+            .map(|(start, _, radius)| {
+                app.world_mut()
+                    .spawn((
+                        Transform::from_xyz(start.x, start.y, 0.),
+                        BoundingCircleComponent(BoundingCircle::new(*start, *radius)),
+                    ))
+                    .id()
+            })
+            .collect();
+        app.update();
+        for (entity, (_, end, _)) in entities.iter().zip(circles) {
+            let mut entity = app.world_mut().entity_mut(*entity);
+            entity.get_mut::<Transform>().unwrap().translation = end.extend(0.);
+            entity
+                .get_mut::<BoundingCircleComponent>()
+                .unwrap()
+                .0
+                .center = *end;
+        }
+        app.update();
+        app.world()
+            .resource::<CollidingPairs>()
+            .0
+            .iter()
+            .map(|pair| {
+                let a = entities
+                    .iter()
+                    .position(|e| *e == pair.metadata1.entity)
+                    .unwrap();
+                let b = entities
+                    .iter()
+                    .position(|e| *e == pair.metadata2.entity)
+                    .unwrap();
+                let time_of_impact = pair.time_of_impact.expect("circles are swept");
+                ((a.min(b), a.max(b)), time_of_impact)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_emulated_sweep_matches_the_cpu() {
+        // every third circle jumps across the area in one frame
+        let circles: Vec<(Vec2, Vec2, f32)> = (0..40)
+            .map(|i| {
+                let start = Vec2::new((i * 37 % 100) as f32, (i * 61 % 100) as f32);
+                let end = match i % 3 {
+                    0 => Vec2::new(100. - start.x, start.y + (i % 7) as f32),
+                    _ => start,
+                };
+                (start, end, 1. + (i % 3) as f32)
+            })
+            // and one only touches near the end of the frame
+            .chain([
+                (Vec2::new(290., 0.), Vec2::new(298.5, 0.), 1.),
+                (Vec2::new(300., 0.), Vec2::new(300., 0.), 1.),
+            ])
+            .collect();
+        let expected = swept_pairs(
+            CpuCollisionDetectionPlugin::default().with_continuous_collision_detection(),
+            &circles,
+        );
+        let overlaps = |(a, b): (usize, usize), end: bool| {
+            let position = |i: usize| if end { circles[i].1 } else { circles[i].0 };
+            position(a).distance(position(b)) < circles[a].2 + circles[b].2
+        };
+        // pairs that only overlapped during the frame, which detection without sweeping would miss
+        assert!(
+            expected
+                .keys()
+                .any(|pair| !overlaps(*pair, false) && !overlaps(*pair, true))
+        );
+        let detected = swept_pairs(
+            emulated_plugin().with_continuous_collision_detection(),
+            &circles,
+        );
+        assert_eq!(
+            detected.keys().collect::<Vec<_>>(),
+            expected.keys().collect::<Vec<_>>()
+        );
+        for (pair, time_of_impact) in detected {
+            assert!(
+                (time_of_impact - expected[&pair]).abs() < 1e-4,
+                "{:?}",
+                pair
+            );
+        }
+    }
+}
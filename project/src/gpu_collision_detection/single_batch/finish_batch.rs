@@ -2,6 +2,17 @@ use bevy::prelude::ResMut;
 
 use crate::gpu_collision_detection::multi_batch_manager::resources::GpuCollisionBatchManager;
 
-pub fn finish_batch(mut batch_manager: ResMut<GpuCollisionBatchManager>) {
+use super::overflow::BatchOverflow;
+
+pub fn finish_batch(
+    mut batch_manager: ResMut<GpuCollisionBatchManager>,
+    mut overflow: ResMut<BatchOverflow>,
+) {
+    // run the same job again to handle the overflow
+    if let Some(available) = overflow.detected.take() {
+        overflow.pending = Some(available);
+        return;
+    }
+    overflow.pending = None;
     batch_manager.current_batch_job += 1;
 }
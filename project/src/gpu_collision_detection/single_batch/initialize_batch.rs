@@ -1,58 +1,79 @@
 use bevy::{
     log,
-    prelude::{Commands, Query, Res, ResMut},
+    prelude::{Res, ResMut},
 };
-use bevy_gpu_compute::prelude::{GpuTaskRunner, IterationSpace};
 
 use crate::{
+    components_and_resources::{ComputeContacts, ContinuousCollisionDetection},
     gpu_collision_detection::{
-        multi_batch_manager::resources::{GpuCollisionBatchJobs, GpuCollisionBatchManager},
-        resources::{AllCollidablesThisFrame, MaxDetectableCollisionsScale},
+        multi_batch_manager::resources::{
+            GpuCollisionBatchJob, GpuCollisionBatchJobs, GpuCollisionBatchManager,
+        },
+        resources::{GpuBroadphase, MaxDetectableCollisionsScale},
         shader::collision_detection_module,
     },
     helpers::math::max_collisions::max_collisions,
 };
 
 use super::{
-    convert_collidables_to_wgsl_types::{
-        PerCollidableDataRequiredByGpu, convert_collidables_to_wgsl_types,
-    },
-    resources::WgslIdToMetadataMap,
+    overflow::BatchOverflow,
+    resources::{CollisionResultCapacity, CollisionShaderPass, ResultsCountFromGpu},
 };
 
+#[allow(clippy::too_many_arguments)]
 pub fn initialize_batch(
-    mut commands: Commands,
     batch_manager: Res<GpuCollisionBatchManager>,
-    mut jobs: ResMut<GpuCollisionBatchJobs>,
-    all_collidables: Res<AllCollidablesThisFrame>,
-    mut wgsl_id_to_metadata: ResMut<WgslIdToMetadataMap>,
-    max_detectable_collisions_scale: Res<MaxDetectableCollisionsScale>,
-    mut gpu_tasks: GpuTaskRunner,
+    jobs: Res<GpuCollisionBatchJobs>,
+    max_detectable_collisions_scale: Option<Res<MaxDetectableCollisionsScale>>,
+    results_count: Res<ResultsCountFromGpu>,
+    overflow: Res<BatchOverflow>,
+    compute_contacts: Res<ComputeContacts>,
+    continuous: Res<ContinuousCollisionDetection>,
+    mut capacity: ResMut<CollisionResultCapacity>,
+    mut pass: ResMut<CollisionShaderPass>,
 ) {
-    log::info!("initialize_batch");
-    let job = &mut jobs.0[batch_manager.current_batch_job];
-    let batch: Vec<PerCollidableDataRequiredByGpu> =
-        all_collidables.0[job.start_index_incl..job.end_index_excl].to_vec();
-    let input = convert_collidables_to_wgsl_types(batch, &mut wgsl_id_to_metadata);
-    log::info!(
-        "initialize_batch: input.positions.positions.len() = {}",
-        input.positions.len()
-    );
-    let l = input.positions.len();
-    let r = max_collisions(l as u128) as f32 * max_detectable_collisions_scale.0;
-    let i_space = IterationSpace::new(l, l, 1);
-    let maxes = collision_detection_module::MaxOutputLengthsBuilder::new()
-        .set_collision_result(r as usize)
-        .finish();
-    let queued_commands = gpu_tasks
-        .task("collision_detection")
-        .mutate(Some(i_space), Some(maxes))
-        .set_inputs(
-            collision_detection_module::InputDataBuilder::new()
-                .set_position(input.positions)
-                .set_radius(input.radii)
-                .finish(),
-        )
-        .run();
-    gpu_tasks.run_commands(queued_commands);
+    let job = &jobs.0[batch_manager.current_batch_job];
+    log::info!("initialize_batch: {}", job.name);
+    let first_len = job.end_index_excl - job.start_index_incl;
+    // cross-batch jobs test every pair with one collidable in each range, so they dispatch over a rectangle
+    let (second_len, pairs) = match job.second_range() {
+        Some(second_range) => (second_range.len(), first_len * second_range.len()),
+        None => (first_len, max_collisions(first_len as u128)),
+    };
+    // without a scale, or when rerunning a batch that overflowed, the counting pass has already found the exact number of results
+    let r = match max_detectable_collisions_scale {
+        Some(scale) if overflow.pending.is_none() => (pairs as f32 * scale.0) as usize,
+        _ => results_count.0,
+    };
+    *capacity = CollisionResultCapacity { len: r.max(1) };
+    pass.set_iteration_space(first_len, second_len);
+    // the extra slot shows whether more than `capacity.len` results were pushed
+    pass.max_collision_results = capacity.len + 1;
+    pass.config = brute_force_config(job, false, compute_contacts.0, continuous.0);
+}
+
+/// The job's first range is uploaded at the start of the input, and a second range straight after it
+pub fn brute_force_config(
+    job: &GpuCollisionBatchJob,
+    count_only: bool,
+    contacts: bool,
+    continuous: bool,
+) -> collision_detection_module::BroadphaseConfig {
+    let first_len = (job.end_index_excl - job.start_index_incl) as u32;
+    collision_detection_module::BroadphaseConfig {
+        mode: GpuBroadphase::BruteForce.shader_mode(),
+        query_start: 0,
+        query_end: first_len,
+        second_range_start: if job.second_range().is_some() {
+            first_len
+        } else {
+            0
+        },
+        cells_x: 1,
+        cells_y: 1,
+        count_only: count_only as u32,
+        contacts: contacts as u32,
+        continuous: continuous as u32,
+        whole_rows: 0,
+    }
 }
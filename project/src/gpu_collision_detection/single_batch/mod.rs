@@ -1,6 +1,10 @@
 pub mod convert_collidables_to_wgsl_types;
+pub mod count_collisions_in_batch;
+pub mod emulate_collision_pass;
 pub mod finish_batch;
 pub mod initialize_batch;
+pub mod overflow;
 pub mod plugin;
 pub mod read_results_from_gpu;
 pub mod resources;
+pub mod run_collision_pass_on_gpu;
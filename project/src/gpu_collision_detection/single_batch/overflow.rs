@@ -0,0 +1,79 @@
+use bevy::{
+    log,
+    prelude::{Event, EventWriter, Res, ResMut, Resource},
+};
+
+use crate::gpu_collision_detection::{
+    multi_batch_manager::resources::{GpuCollisionBatchJobs, GpuCollisionBatchManager},
+    resources::{CollisionOverflowPolicy, MaxDetectableCollisionsScale},
+};
+
+use super::resources::ResultsCountFromGpu;
+
+/// A batch found more collisions than its result buffer could hold, so without recovery `requested - available` collisions are lost
+#[derive(Event, Debug, Clone)]
+pub struct CollisionOverflow {
+    pub job_name: String,
+    pub requested: usize,
+    pub available: usize,
+}
+
+/// Overflow handling state for the current batch job.
+///
+/// When a batch fills its buffer, `finish_batch` does not move on to the next job; the job runs again with the counting pass so the requested length is known, and the policy decides whether the detection pass runs again as well.
+#[derive(Debug, Default, Resource)]
+pub struct BatchOverflow {
+    /// Buffer length of the detection pass that just filled up
+    pub detected: Option<usize>,
+    /// Buffer length of the detection pass that filled up on the previous run of this job
+    pub pending: Option<usize>,
+}
+
+pub fn overflow_pending(overflow: Res<BatchOverflow>) -> bool {
+    overflow.pending.is_some()
+}
+
+/// With `GrowScale` the truncated results are kept, so the job only runs again to count
+pub fn skip_detection_pass(
+    overflow: Res<BatchOverflow>,
+    policy: Res<CollisionOverflowPolicy>,
+) -> bool {
+    overflow.pending.is_some() && *policy == CollisionOverflowPolicy::GrowScale
+}
+
+pub fn report_collision_overflow(
+    batch_jobs: Res<GpuCollisionBatchJobs>,
+    batch_manager: Res<GpuCollisionBatchManager>,
+    overflow: Res<BatchOverflow>,
+    results_count: Res<ResultsCountFromGpu>,
+    policy: Res<CollisionOverflowPolicy>,
+    scale: Option<ResMut<MaxDetectableCollisionsScale>>,
+    mut overflow_events: EventWriter<CollisionOverflow>,
+) {
+    let job = &batch_jobs.0[batch_manager.current_batch_job];
+    let available = overflow.pending.unwrap();
+    let requested = results_count.0;
+    // the buffer can be exactly full without anything being lost
+    if requested <= available {
+        return;
+    }
+    log::warn!(
+        "{} found {} collisions but only had room for {}, handling with {:?}",
+        job.name,
+        requested,
+        available,
+        *policy
+    );
+    overflow_events.send(CollisionOverflow {
+        job_name: job.name.clone(),
+        requested,
+        available,
+    });
+    if *policy == CollisionOverflowPolicy::GrowScale
+        && let Some(mut scale) = scale
+    {
+        let safety_factor = 1.1;
+        scale.0 = (scale.0 * (requested as f32 / available as f32) * safety_factor).min(1.);
+        log::info!("max_detectable_collisions_scale grown to {}", scale.0);
+    }
+}
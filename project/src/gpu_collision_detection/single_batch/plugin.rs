@@ -1,33 +1,121 @@
 use bevy::{
     app::{App, Plugin, Startup},
-    prelude::{Commands, IntoSystemConfigs, Schedule, SystemSet},
+    ecs::schedule::SystemConfigs,
+    prelude::{Commands, Condition, IntoSystemConfigs, Schedule, SystemSet, not, resource_exists},
 };
 
-use crate::gpu_collision_detection::custom_schedule::BatchedCollisionDetectionSchedule;
+use crate::collision_detection_plugin::gpu_emulated;
+use crate::gpu_collision_detection::{
+    custom_schedule::BatchedCollisionDetectionSchedule,
+    resources::{CollisionDimensions, GpuBroadphase, MaxDetectableCollisionsScale},
+    spatial_hash::initialize_spatial_hash_batch::initialize_spatial_hash_batch,
+    three_d::{
+        convert_collidables_to_wgsl_types::convert_batch_to_wgsl_types_3d,
+        emulate_collision_pass::emulate_collision_pass_3d,
+        resources::SingleBatchDataForWgsl3d,
+        run_collision_pass_on_gpu::{
+            read_collision_pass_from_gpu_3d, run_collision_pass_on_gpu_3d,
+        },
+    },
+};
 
 use super::{
-    convert_collidables_to_wgsl_types::convert_collidables_to_wgsl_types,
+    convert_collidables_to_wgsl_types::convert_batch_to_wgsl_types,
+    count_collisions_in_batch::{count_collisions_in_batch, read_collision_count_from_gpu},
+    emulate_collision_pass::emulate_collision_pass,
     finish_batch::finish_batch,
     initialize_batch::initialize_batch,
+    overflow::{
+        BatchOverflow, CollisionOverflow, overflow_pending, report_collision_overflow,
+        skip_detection_pass,
+    },
     read_results_from_gpu::read_results_from_gpu,
     resources::{
-        CollidablesBatch, ResultsCountFromGpu, SingleBatchBindGroup, SingleBatchBuffers,
-        SingleBatchDataForWgsl, WgslIdToMetadataMap,
+        CollidablesBatch, CollisionResultCapacity, CollisionShaderOutput, CollisionShaderPass,
+        ResultsCountFromGpu, SingleBatchBindGroup, SingleBatchBuffers, SingleBatchDataForWgsl,
+        WgslIdToMetadataMap,
     },
+    run_collision_pass_on_gpu::{read_collision_pass_from_gpu, run_collision_pass_on_gpu},
 };
 
 #[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct SingleBatchGpuCollisionDetectionSystemSet;
 
-pub struct GpuCollisionSingleBatchRunnerPlugin;
+pub struct GpuCollisionSingleBatchRunnerPlugin {
+    /// Run the shader's CPU version with rayon instead of dispatching it to the GPU. Without it the CPU version still runs while the `CollisionDetectionMethod` is `GpuEmulated`
+    pub emulate_on_cpu: bool,
+    pub dimensions: CollisionDimensions,
+}
+
+impl GpuCollisionSingleBatchRunnerPlugin {
+    /// Converts the current brute force batch job for the 2D or 3D shader
+    fn convert_batch(&self) -> SystemConfigs {
+        match self.dimensions {
+            CollisionDimensions::Two => convert_batch_to_wgsl_types.into_configs(),
+            CollisionDimensions::Three => convert_batch_to_wgsl_types_3d.into_configs(),
+        }
+    }
+
+    /// Runs the `CollisionShaderPass` that the systems before it set up, and writes `CollisionShaderOutput`
+    fn run_collision_pass(&self) -> SystemConfigs {
+        match (self.dimensions, self.emulate_on_cpu) {
+            (CollisionDimensions::Two, true) => emulate_collision_pass.into_configs(),
+            (CollisionDimensions::Two, false) => (
+                emulate_collision_pass.run_if(gpu_emulated),
+                (run_collision_pass_on_gpu, read_collision_pass_from_gpu)
+                    .chain()
+                    .run_if(not(gpu_emulated)),
+            )
+                .into_configs(),
+            (CollisionDimensions::Three, true) => emulate_collision_pass_3d.into_configs(),
+            (CollisionDimensions::Three, false) => (
+                emulate_collision_pass_3d.run_if(gpu_emulated),
+                (
+                    run_collision_pass_on_gpu_3d,
+                    read_collision_pass_from_gpu_3d,
+                )
+                    .chain()
+                    .run_if(not(gpu_emulated)),
+            )
+                .into_configs(),
+        }
+    }
+}
 
 impl Plugin for GpuCollisionSingleBatchRunnerPlugin {
     fn build(&self, app: &mut App) {
+        let brute_force = GpuBroadphase::BruteForce.selected(self.dimensions);
+        let spatial_hash = GpuBroadphase::SpatialHash.selected(self.dimensions);
         let mut batched_collision_detection_schedule =
             Schedule::new(BatchedCollisionDetectionSchedule);
-        batched_collision_detection_schedule
-            .add_systems((initialize_batch, read_results_from_gpu, finish_batch).chain());
+        batched_collision_detection_schedule.add_systems(
+            (
+                self.convert_batch().run_if(brute_force.clone()),
+                // exact output sizing, when there is no scale to size the output with or the last run of this job overflowed
+                (
+                    count_collisions_in_batch,
+                    self.run_collision_pass(),
+                    read_collision_count_from_gpu,
+                )
+                    .chain()
+                    .run_if(
+                        not(resource_exists::<MaxDetectableCollisionsScale>).or(overflow_pending),
+                    ),
+                report_collision_overflow.run_if(overflow_pending),
+                (
+                    initialize_batch.run_if(brute_force),
+                    initialize_spatial_hash_batch.run_if(spatial_hash),
+                    self.run_collision_pass(),
+                    read_results_from_gpu,
+                )
+                    .chain()
+                    .run_if(not(skip_detection_pass)),
+                finish_batch,
+            )
+                .chain(),
+        );
         app.add_schedule(batched_collision_detection_schedule)
+            .add_event::<CollisionOverflow>()
             .add_systems(Startup, setup_single_batch_resources);
     }
 }
@@ -36,7 +124,12 @@ fn setup_single_batch_resources(mut commands: Commands) {
     commands.insert_resource(SingleBatchBuffers::default());
     commands.insert_resource(SingleBatchBindGroup(None));
     commands.insert_resource(SingleBatchDataForWgsl::default());
+    commands.insert_resource(SingleBatchDataForWgsl3d::default());
     commands.insert_resource(CollidablesBatch(Vec::new()));
     commands.insert_resource(ResultsCountFromGpu(0));
+    commands.insert_resource(CollisionResultCapacity { len: 0 });
+    commands.insert_resource(BatchOverflow::default());
+    commands.insert_resource(CollisionShaderPass::default());
+    commands.insert_resource(CollisionShaderOutput::default());
     commands.insert_resource(WgslIdToMetadataMap(Vec::new()));
 }
@@ -1,15 +1,21 @@
 use bevy::{
+    math::Vec3,
     prelude::Resource,
     render::render_resource::{BindGroup, Buffer},
 };
+use bevy_gpu_compute::prelude::IterationSpace;
 
-use crate::gpu_collision_detection::{
-    entity_metadata::CollidableMetadata, shader::collision_detection_module,
+use crate::{
+    colliding_pair::Contact,
+    gpu_collision_detection::{
+        entity_metadata::CollidableMetadata, shader::collision_detection_module,
+        three_d::shader::collision_detection_3d_module,
+    },
 };
 
 use super::convert_collidables_to_wgsl_types::PerCollidableDataRequiredByGpu;
 
-#[derive(Debug, Resource)]
+#[derive(Debug, Default, Resource)]
 pub struct SingleBatchBuffers {
     pub positions_buffer: Option<Buffer>,
     pub radii_buffer: Option<Buffer>,
@@ -17,33 +23,15 @@ pub struct SingleBatchBuffers {
     pub results_staging_buffer: Option<Buffer>,
     pub counter_buffer: Option<Buffer>,
 }
-impl Default for SingleBatchBuffers {
-    fn default() -> Self {
-        SingleBatchBuffers {
-            positions_buffer: None,
-            radii_buffer: None,
-            results_buffer: None,
-            results_staging_buffer: None,
-            counter_buffer: None,
-        }
-    }
-}
 
 #[derive(Debug, Resource)]
 pub struct SingleBatchBindGroup(pub Option<BindGroup>);
 
-#[derive(Resource)]
+#[derive(Default, Clone, Resource)]
 pub struct SingleBatchDataForWgsl {
     pub positions: Vec<collision_detection_module::Position>,
-    pub radii: Vec<collision_detection_module::Radius>,
-}
-impl Default for SingleBatchDataForWgsl {
-    fn default() -> Self {
-        SingleBatchDataForWgsl {
-            positions: Vec::new(),
-            radii: Vec::new(),
-        }
-    }
+    pub shapes: Vec<collision_detection_module::Shape>,
+    pub vertices: Vec<collision_detection_module::Vertex>,
 }
 
 #[derive(Resource)]
@@ -51,6 +39,130 @@ pub struct CollidablesBatch(pub Vec<PerCollidableDataRequiredByGpu>);
 
 #[derive(Resource)]
 pub struct ResultsCountFromGpu(pub usize);
+
+/// How many results the last detection pass had room for.
+///
+/// The pass's result buffer is one longer than this. The compute crate only reads the push counter back clamped to the buffer length, so a result in the extra slot is how a pass that pushed more than `len` shows up
+#[derive(Debug, Resource)]
+pub struct CollisionResultCapacity {
+    pub len: usize,
+}
 #[derive(Resource)]
 /// Not necessary to add a dummy value at index zero. Its true that if the GPU cant find a collision it returns ID zero, but it will always return both entities with the same ID = 0, so as long as we check for duplicate entities we will never incorrectly find a collision due to this.
 pub struct WgslIdToMetadataMap(pub Vec<CollidableMetadata>);
+
+/// One dispatch of the collision shader. Written by the systems that set up a pass, and run by either the GPU or the CPU emulation.
+///
+/// Only the parts that are the same in 2D and 3D, the inputs are read from `SingleBatchDataForWgsl` (or `SingleBatchDataForWgsl3d`) by whatever runs the pass
+#[derive(Resource)]
+pub struct CollisionShaderPass {
+    pub iteration_space: IterationSpace,
+    /// The maximum length of the output the pass pushes to, see `output_lengths`
+    pub max_collision_results: usize,
+    pub config: collision_detection_module::BroadphaseConfig,
+}
+impl Default for CollisionShaderPass {
+    fn default() -> Self {
+        CollisionShaderPass {
+            iteration_space: IterationSpace::default(),
+            max_collision_results: 1,
+            config: bytemuck::Zeroable::zeroed(),
+        }
+    }
+}
+
+impl CollisionShaderPass {
+    /// The compute task is created with a 2D iteration space, and bevy_gpu_compute can't change its number of dimensions, where a length of 1 doesn't count as one. So both lengths are at least 2, and the shader skips the threads past the end
+    pub fn set_iteration_space(&mut self, x: usize, y: usize) {
+        self.iteration_space = IterationSpace::new(x.max(2), y.max(2), 1);
+    }
+
+    /// The maximum lengths of `CollisionResult` and `CollisionContact`. A pass only pushes to one of them, and the other gets a single slot since outputs can't be empty
+    pub fn output_lengths(&self, pushes_contacts: bool) -> (usize, usize) {
+        if pushes_contacts {
+            (1, self.max_collision_results)
+        } else {
+            (self.max_collision_results, 1)
+        }
+    }
+}
+
+/// What the last `CollisionShaderPass` wrote, no longer than the maximum lengths it was given
+#[derive(Default, Resource)]
+pub struct CollisionShaderOutput {
+    pub collision_results: Vec<ShaderCollisionResult>,
+    /// The value of the result push counter, how many results the pass found including any that didn't fit. Only exact up to the buffer length on the GPU
+    pub collision_result_count: usize,
+    /// The number of collisions of every thread of a counting pass that found any
+    pub collision_counts: Vec<u32>,
+}
+
+impl CollisionShaderOutput {
+    /// Sets the output from what a pass pushed, and how many pushes there were. The counting pass pushes counts as `CollisionResult`s, see the shader
+    pub fn set_pushed(
+        &mut self,
+        count_only: bool,
+        pushed: Vec<ShaderCollisionResult>,
+        pushed_count: usize,
+    ) {
+        if count_only {
+            self.collision_counts = pushed.iter().map(|result| result.entity2).collect();
+            self.collision_results = Vec::new();
+        } else {
+            self.collision_counts = Vec::new();
+            self.collision_results = pushed;
+        }
+        self.collision_result_count = pushed_count;
+    }
+}
+
+/// The size of a result in the output that detection passes push to. Contacts and times of impact need `CollisionContact`, which is a little bigger in 3D, and either shader can be running
+pub fn result_size(contacts_or_continuous: bool) -> usize {
+    if contacts_or_continuous {
+        std::mem::size_of::<collision_detection_module::CollisionContact>().max(
+            std::mem::size_of::<collision_detection_3d_module::CollisionContact>(),
+        )
+    } else {
+        std::mem::size_of::<collision_detection_module::CollisionResult>()
+    }
+}
+
+/// A `CollisionResult` or `CollisionContact` of either shader. The contact is all zeros unless the pass computed contacts
+#[derive(Debug, Clone, Copy)]
+pub struct ShaderCollisionResult {
+    pub entity1: u32,
+    pub entity2: u32,
+    pub contact: Contact,
+    pub time_of_impact: Option<f32>,
+}
+impl collision_detection_module::BroadphaseConfig {
+    /// Whether the pass pushes its results to `CollisionContact` instead of `CollisionResult`
+    pub fn pushes_contacts(&self) -> bool {
+        self.count_only == 0 && (self.contacts == 1 || self.continuous == 1)
+    }
+}
+
+impl From<&collision_detection_module::CollisionResult> for ShaderCollisionResult {
+    fn from(result: &collision_detection_module::CollisionResult) -> Self {
+        ShaderCollisionResult {
+            entity1: result.entity1,
+            entity2: result.entity2,
+            contact: Contact::default(),
+            time_of_impact: None,
+        }
+    }
+}
+impl From<&collision_detection_module::CollisionContact> for ShaderCollisionResult {
+    fn from(result: &collision_detection_module::CollisionContact) -> Self {
+        ShaderCollisionResult {
+            entity1: result.entity1,
+            entity2: result.entity2,
+            contact: Contact {
+                normal: Vec3::new(result.normal.x, result.normal.y, 0.),
+                depth: result.depth,
+                point: Vec3::new(result.point.x, result.point.y, 0.),
+            },
+            time_of_impact: (result.time_of_impact >= 0.).then_some(result.time_of_impact),
+        }
+    }
+}
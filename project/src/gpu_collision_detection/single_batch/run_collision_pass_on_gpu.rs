@@ -0,0 +1,78 @@
+use bevy::prelude::{Res, ResMut};
+use bevy_gpu_compute::prelude::{GpuTaskReader, GpuTaskRunner};
+
+use crate::gpu_collision_detection::{
+    resources::GpuBroadphase, shader::collision_detection_module,
+    spatial_hash::grid::SpatialHashGrid,
+};
+
+use super::resources::{
+    CollisionShaderOutput, CollisionShaderPass, ShaderCollisionResult, SingleBatchDataForWgsl,
+};
+
+pub fn run_collision_pass_on_gpu(
+    pass: Res<CollisionShaderPass>,
+    mut wgsl_data: ResMut<SingleBatchDataForWgsl>,
+    broadphase: Res<GpuBroadphase>,
+    grid: Res<SpatialHashGrid>,
+    mut gpu_tasks: GpuTaskRunner,
+) {
+    let (max_results, max_contacts) = pass.output_lengths(pass.config.pushes_contacts());
+    let maxes = collision_detection_module::MaxOutputLengthsBuilder::new()
+        .set_collision_result(max_results)
+        .set_collision_contact(max_contacts)
+        .finish();
+    // a brute force batch is converted again before it is rerun, so its detection pass is the last to upload it. The spatial hash uploads the whole frame for every batch
+    let inputs = if *broadphase == GpuBroadphase::BruteForce && pass.config.count_only == 0 {
+        std::mem::take(&mut *wgsl_data)
+    } else {
+        wgsl_data.clone()
+    };
+    let queued_commands = gpu_tasks
+        .task("collision_detection")
+        .mutate(Some(pass.iteration_space), Some(maxes))
+        .set_config_inputs(
+            collision_detection_module::ConfigInputDataBuilder::new()
+                .set_broadphase_config(pass.config)
+                .finish(),
+        )
+        .set_inputs(
+            collision_detection_module::InputDataBuilder::new()
+                .set_position(inputs.positions)
+                .set_shape(inputs.shapes)
+                .set_vertex(inputs.vertices)
+                .set_grid_cell(grid_cell_input(*broadphase, &grid))
+                .finish(),
+        )
+        .run();
+    gpu_tasks.run_commands(queued_commands);
+}
+
+/// The spatial hash's cells, or a placeholder since the brute force broadphase doesn't use them and inputs can't be empty
+pub fn grid_cell_input(broadphase: GpuBroadphase, grid: &SpatialHashGrid) -> Vec<u32> {
+    match broadphase {
+        GpuBroadphase::BruteForce => vec![0],
+        GpuBroadphase::SpatialHash => grid.shader_cells(),
+    }
+}
+
+/// Has to be separate from `run_collision_pass_on_gpu`, the task runner and reader both need the task mutably
+pub fn read_collision_pass_from_gpu(
+    pass: Res<CollisionShaderPass>,
+    mut gpu_task_reader: GpuTaskReader,
+    mut output: ResMut<CollisionShaderOutput>,
+) {
+    let result = gpu_task_reader
+        .latest_results::<collision_detection_module::OutputDataBuilder>("collision_detection")
+        .expect("No output found for the collision detection task");
+    let pushed: Vec<ShaderCollisionResult> = if pass.config.pushes_contacts() {
+        let contacts = result.collision_contact.unwrap_or_default();
+        contacts.iter().map(ShaderCollisionResult::from).collect()
+    } else {
+        let results = result.collision_result.unwrap_or_default();
+        results.iter().map(ShaderCollisionResult::from).collect()
+    };
+    // the compute crate reads back no more than the buffer length of results, however many were pushed
+    let pushed_count = pushed.len();
+    output.set_pushed(pass.config.count_only == 1, pushed, pushed_count);
+}
@@ -0,0 +1,78 @@
+use bevy::{
+    log,
+    prelude::{Res, ResMut},
+};
+
+use crate::{
+    components_and_resources::ComputeContacts,
+    gpu_collision_detection::{
+        multi_batch_manager::{
+            population::CollidablePopulation,
+            resources::{GpuCollisionBatchJob, GpuCollisionBatchJobs},
+        },
+        resources::MaxStorageBufferBindingSize,
+        single_batch::resources::result_size,
+    },
+};
+
+use super::grid::SpatialHashGrid;
+
+/// Splits the cell-sorted collidables into consecutive query ranges.
+///
+/// Unlike the brute force batching every job sees the whole population, only the collidables it queries from are limited, so no cross-batch jobs are needed. The grid tells us exactly how many pairs each collidable will test, so each job's output buffer is sized to that bound and is kept under the device's storage buffer limit.
+pub fn generate_spatial_hash_batch_jobs(
+    max_storage_buffer_binding_size: Res<MaxStorageBufferBindingSize>,
+    population: Res<CollidablePopulation>,
+    grid: Res<SpatialHashGrid>,
+    compute_contacts: Res<ComputeContacts>,
+    mut batch_jobs: ResMut<GpuCollisionBatchJobs>,
+) {
+    batch_jobs.0.clear();
+    let safety_factor = 1.1;
+    // the spatial hash doesn't sweep, so only contacts need the bigger results
+    let per_result_size = result_size(compute_contacts.0);
+    let max_results_per_job = (max_storage_buffer_binding_size.0 as f32
+        / (per_result_size as f32 * safety_factor)) as usize;
+    let mut start = 0;
+    let mut candidate_pairs = 0;
+    for i in 0..population.0 {
+        let pairs = grid.candidate_pairs_for(i);
+        if i > start && candidate_pairs + pairs > max_results_per_job {
+            push_job(&mut batch_jobs, start, i, candidate_pairs);
+            start = i;
+            candidate_pairs = 0;
+        }
+        candidate_pairs += pairs;
+    }
+    if start < population.0 {
+        push_job(&mut batch_jobs, start, population.0, candidate_pairs);
+    }
+    for job in batch_jobs.0.iter() {
+        if job.candidate_pairs.unwrap_or(0) > max_results_per_job {
+            log::warn!(
+                "{} tests {} pairs but the output buffer can only hold {}, collisions may be dropped",
+                job.name,
+                job.candidate_pairs.unwrap_or(0),
+                max_results_per_job
+            );
+        }
+    }
+}
+
+fn push_job(
+    batch_jobs: &mut GpuCollisionBatchJobs,
+    start_index_incl: usize,
+    end_index_excl: usize,
+    candidate_pairs: usize,
+) {
+    let id = batch_jobs.0.len();
+    batch_jobs.0.push(GpuCollisionBatchJob {
+        name: format!("spatial_hash_batch_{}", id),
+        run_id: None,
+        start_index_incl,
+        end_index_excl,
+        second_start_index_incl: None,
+        second_end_index_excl: None,
+        candidate_pairs: Some(candidate_pairs),
+    });
+}
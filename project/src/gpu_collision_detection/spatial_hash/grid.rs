@@ -0,0 +1,242 @@
+use bevy::{log, prelude::Resource};
+
+use crate::gpu_collision_detection::single_batch::convert_collidables_to_wgsl_types::PerCollidableDataRequiredByGpu;
+
+/// The cell size is grown until the grid has at most this many cells per collidable, so sparse worlds don't produce huge, mostly empty grids.
+const MAX_CELLS_PER_COLLIDABLE: u64 = 4;
+
+/// Uniform grid over the bounding box of this frame's collidables.
+///
+/// The collidables it was built from are sorted by cell, so the members of cell `c` are the contiguous range `cell_starts[c]..cell_starts[c + 1]`.
+#[derive(Debug, Clone, Resource)]
+pub struct SpatialHashGrid {
+    pub origin_x: f32,
+    pub origin_y: f32,
+    /// At least twice the largest radius, so two colliding circles are never more than one cell apart.
+    pub cell_size: f32,
+    pub cells_x: u32,
+    pub cells_y: u32,
+    /// The cell of each collidable, in sorted order
+    pub cell_indices: Vec<u32>,
+    /// Prefix sum of the per-cell counts, has one more entry than there are cells
+    pub cell_starts: Vec<u32>,
+}
+
+impl Default for SpatialHashGrid {
+    fn default() -> Self {
+        SpatialHashGrid {
+            origin_x: 0.,
+            origin_y: 0.,
+            cell_size: 1.,
+            cells_x: 1,
+            cells_y: 1,
+            cell_indices: Vec::new(),
+            cell_starts: vec![0, 0],
+        }
+    }
+}
+
+impl SpatialHashGrid {
+    /// Assigns every collidable to a cell, counts the members of each cell, prefix sums the counts and then sorts the collidables in place by cell (a counting sort, so O(n)).
+    ///
+    /// Collidables whose center is not finite are removed.
+    pub fn build(collidables: &mut Vec<PerCollidableDataRequiredByGpu>) -> Self {
+        let count = collidables.len();
+        collidables.retain(|c| c.center_x.is_finite() && c.center_y.is_finite());
+        if collidables.len() < count {
+            log::warn!(
+                "{} collidables with a non-finite center are skipped by the spatial hash",
+                count - collidables.len()
+            );
+        }
+        if collidables.is_empty() {
+            return SpatialHashGrid::default();
+        }
+        let mut min_x = f32::MAX;
+        let mut min_y = f32::MAX;
+        let mut max_x = f32::MIN;
+        let mut max_y = f32::MIN;
+        let mut max_radius: f32 = 0.;
+        for collidable in collidables.iter() {
+            min_x = min_x.min(collidable.center_x);
+            min_y = min_y.min(collidable.center_y);
+            max_x = max_x.max(collidable.center_x);
+            max_y = max_y.max(collidable.center_y);
+            max_radius = max_radius.max(collidable.radius);
+        }
+        let mut cell_size = if max_radius > 0. { 2. * max_radius } else { 1. };
+        let max_cells = (collidables.len() as u64 * MAX_CELLS_PER_COLLIDABLE).max(1);
+        // start with at most `max_cells` cells along each axis, so far apart collidables don't need hundreds of doublings
+        cell_size = cell_size.max((max_x - min_x).max(max_y - min_y) / max_cells as f32);
+        let (mut cells_x, mut cells_y) = cells_per_axis(max_x - min_x, max_y - min_y, cell_size);
+        while cells_x.saturating_mul(cells_y) > max_cells {
+            cell_size *= 2.;
+            (cells_x, cells_y) = cells_per_axis(max_x - min_x, max_y - min_y, cell_size);
+        }
+        let mut grid = SpatialHashGrid {
+            origin_x: min_x,
+            origin_y: min_y,
+            cell_size,
+            cells_x: cells_x as u32,
+            cells_y: cells_y as u32,
+            cell_indices: Vec::with_capacity(collidables.len()),
+            cell_starts: vec![0; (cells_x * cells_y) as usize + 1],
+        };
+        // cell assignment and per-cell counts
+        let cells: Vec<u32> = collidables
+            .iter()
+            .map(|c| grid.cell_of(c.center_x, c.center_y))
+            .collect();
+        for cell in cells.iter() {
+            grid.cell_starts[*cell as usize + 1] += 1;
+        }
+        // prefix sum
+        for i in 1..grid.cell_starts.len() {
+            grid.cell_starts[i] += grid.cell_starts[i - 1];
+        }
+        // cell-sorted index list
+        let mut next_slot = grid.cell_starts.clone();
+        let mut sorted_indices = vec![0; collidables.len()];
+        for (i, cell) in cells.iter().enumerate() {
+            sorted_indices[next_slot[*cell as usize] as usize] = i;
+            next_slot[*cell as usize] += 1;
+        }
+        grid.cell_indices = sorted_indices.iter().map(|i| cells[*i]).collect();
+        *collidables = sorted_indices
+            .iter()
+            .map(|i| collidables[*i].clone())
+            .collect();
+        grid
+    }
+
+    pub fn cell_of(&self, x: f32, y: f32) -> u32 {
+        let cell_x = (((x - self.origin_x) / self.cell_size) as u32).min(self.cells_x - 1);
+        let cell_y = (((y - self.origin_y) / self.cell_size) as u32).min(self.cells_y - 1);
+        cell_y * self.cells_x + cell_x
+    }
+
+    /// The sorted index ranges of the (up to 9) cells around and including `cell`
+    pub fn neighborhood(&self, cell: u32) -> impl Iterator<Item = std::ops::Range<usize>> + '_ {
+        let cell_x = (cell % self.cells_x) as i64;
+        let cell_y = (cell / self.cells_x) as i64;
+        (-1..=1)
+            .flat_map(move |dy| (-1..=1).map(move |dx| (cell_x + dx, cell_y + dy)))
+            .filter(|(x, y)| {
+                *x >= 0 && *y >= 0 && *x < self.cells_x as i64 && *y < self.cells_y as i64
+            })
+            .map(|(x, y)| {
+                let neighbor = (y * self.cells_x as i64 + x) as usize;
+                self.cell_starts[neighbor] as usize..self.cell_starts[neighbor + 1] as usize
+            })
+    }
+
+    /// How many pairs the narrow phase tests for the collidable at `sorted_index`, which is also the most collisions it can report
+    pub fn candidate_pairs_for(&self, sorted_index: usize) -> usize {
+        self.neighborhood(self.cell_indices[sorted_index])
+            .map(|range| range.end.saturating_sub(range.start.max(sorted_index + 1)))
+            .sum()
+    }
+
+    /// The shader's `GridCell` input: the cell of every collidable, followed by the cell starts
+    pub fn shader_cells(&self) -> Vec<u32> {
+        [self.cell_indices.as_slice(), self.cell_starts.as_slice()].concat()
+    }
+}
+
+fn cells_per_axis(width: f32, height: f32, cell_size: f32) -> (u64, u64) {
+    (
+        ((width / cell_size) as u64).saturating_add(1),
+        ((height / cell_size) as u64).saturating_add(1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Entity;
+
+    use super::*;
+    use crate::{
+        components_and_resources::CollisionLayers,
+        gpu_collision_detection::single_batch::convert_collidables_to_wgsl_types::CollidableShape,
+    };
+
+    fn collidable(i: u32, x: f32, y: f32, radius: f32) -> PerCollidableDataRequiredByGpu {
+        PerCollidableDataRequiredByGpu {
+            center_x: x,
+            center_y: y,
+            center_z: 0.,
+            previous_x: x,
+            previous_y: y,
+            radius,
+            shape: CollidableShape::Circle,
+            entity: Entity::from_raw(i),
+            is_sensor: false,
+            layers: CollisionLayers::ALL,
+            user_data: None,
+        }
+    }
+
+    #[test]
+    fn test_sorted_by_cell() {
+        let mut collidables = vec![
+            collidable(0, 10., 10., 1.),
+            collidable(1, 0., 0., 1.),
+            collidable(2, 10., 0., 1.),
+            collidable(3, 0.5, 0.5, 1.),
+        ];
+        let grid = SpatialHashGrid::build(&mut collidables);
+        // 2 * radius would give a 6x6 grid, more than 4 cells per collidable
+        assert_eq!(grid.cell_size, 4.);
+        assert_eq!((grid.cells_x, grid.cells_y), (3, 3));
+        assert_eq!(*grid.cell_starts.last().unwrap(), 4);
+        assert!(grid.cell_indices.windows(2).all(|w| w[0] <= w[1]));
+        for (i, c) in collidables.iter().enumerate() {
+            assert_eq!(grid.cell_of(c.center_x, c.center_y), grid.cell_indices[i]);
+        }
+    }
+
+    #[test]
+    fn test_every_colliding_pair_is_a_candidate() {
+        let mut collidables: Vec<_> = (0..200)
+            .map(|i| {
+                let x = ((i * 37) % 101) as f32 * 0.7;
+                let y = ((i * 53) % 97) as f32 * 0.45;
+                collidable(i, x, y, 0.5 + (i % 7) as f32 * 0.4)
+            })
+            .collect();
+        let grid = SpatialHashGrid::build(&mut collidables);
+        for i in 0..collidables.len() {
+            let candidates: Vec<usize> = grid
+                .neighborhood(grid.cell_indices[i])
+                .flat_map(|range| range.filter(|j| *j > i))
+                .collect();
+            assert_eq!(candidates.len(), grid.candidate_pairs_for(i));
+            for j in i + 1..collidables.len() {
+                let (a, b) = (&collidables[i], &collidables[j]);
+                let dx = a.center_x - b.center_x;
+                let dy = a.center_y - b.center_y;
+                let r = a.radius + b.radius;
+                if dx * dx + dy * dy < r * r {
+                    assert!(candidates.contains(&j), "missed pair {} {}", i, j);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_far_away_and_non_finite_collidables() {
+        let mut collidables = vec![
+            collidable(0, 0., 0., 1.),
+            collidable(1, 1e30, 1e30, 1.),
+            collidable(2, f32::NAN, 0., 1.),
+            collidable(3, 0., f32::INFINITY, 1.),
+        ];
+        let grid = SpatialHashGrid::build(&mut collidables);
+        assert_eq!(collidables.len(), 2);
+        assert!(u64::from(grid.cells_x) * u64::from(grid.cells_y) <= 8);
+        assert_eq!(*grid.cell_starts.last().unwrap(), 2);
+        let mut single = vec![collidable(0, 1e30, 1e30, 1.)];
+        let grid = SpatialHashGrid::build(&mut single);
+        assert_eq!((grid.cells_x, grid.cells_y), (1, 1));
+    }
+}
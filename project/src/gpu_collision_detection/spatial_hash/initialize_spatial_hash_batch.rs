@@ -0,0 +1,83 @@
+use bevy::{
+    log,
+    prelude::{Res, ResMut},
+};
+
+use crate::{
+    components_and_resources::ComputeContacts,
+    gpu_collision_detection::{
+        multi_batch_manager::resources::{
+            GpuCollisionBatchJob, GpuCollisionBatchJobs, GpuCollisionBatchManager,
+        },
+        resources::{GpuBroadphase, MaxDetectableCollisionsScale, MaxStorageBufferBindingSize},
+        shader::collision_detection_module,
+        single_batch::{
+            overflow::BatchOverflow,
+            resources::{
+                CollisionResultCapacity, CollisionShaderPass, ResultsCountFromGpu, result_size,
+            },
+        },
+    },
+};
+
+use super::grid::SpatialHashGrid;
+
+/// Each collidable checks the cell it is in plus the 8 around it
+pub const NEIGHBORHOOD_SIZE: usize = 9;
+
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_spatial_hash_batch(
+    batch_manager: Res<GpuCollisionBatchManager>,
+    jobs: Res<GpuCollisionBatchJobs>,
+    grid: Res<SpatialHashGrid>,
+    max_storage_buffer_binding_size: Res<MaxStorageBufferBindingSize>,
+    max_detectable_collisions_scale: Option<Res<MaxDetectableCollisionsScale>>,
+    results_count: Res<ResultsCountFromGpu>,
+    overflow: Res<BatchOverflow>,
+    compute_contacts: Res<ComputeContacts>,
+    mut capacity: ResMut<CollisionResultCapacity>,
+    mut pass: ResMut<CollisionShaderPass>,
+) {
+    let job = &jobs.0[batch_manager.current_batch_job];
+    log::info!(
+        "initialize_spatial_hash_batch: {} queries {}..{}",
+        job.name,
+        job.start_index_incl,
+        job.end_index_excl
+    );
+    let per_result_size = result_size(compute_contacts.0);
+    let max_results = max_storage_buffer_binding_size.0 as usize / per_result_size;
+    // the scale is not used here since the grid gives a tighter bound, but without it, or when rerunning a batch that overflowed, the counting pass has already found the exact number of results
+    let expected_results = match max_detectable_collisions_scale {
+        Some(_) if overflow.pending.is_none() => job.candidate_pairs.unwrap_or(0),
+        _ => results_count.0,
+    };
+    // leaves room for the extra slot
+    *capacity = CollisionResultCapacity {
+        len: expected_results.clamp(1, max_results - 1),
+    };
+    pass.set_iteration_space(job.end_index_excl - job.start_index_incl, NEIGHBORHOOD_SIZE);
+    pass.max_collision_results = capacity.len + 1;
+    pass.config = spatial_hash_config(job, &grid, false, compute_contacts.0);
+}
+
+pub fn spatial_hash_config(
+    job: &GpuCollisionBatchJob,
+    grid: &SpatialHashGrid,
+    count_only: bool,
+    contacts: bool,
+) -> collision_detection_module::BroadphaseConfig {
+    collision_detection_module::BroadphaseConfig {
+        mode: GpuBroadphase::SpatialHash.shader_mode(),
+        query_start: job.start_index_incl as u32,
+        query_end: job.end_index_excl as u32,
+        cells_x: grid.cells_x,
+        cells_y: grid.cells_y,
+        second_range_start: 0,
+        count_only: count_only as u32,
+        contacts: contacts as u32,
+        // swept pairs can be further apart than neighboring cells, so the spatial hash doesn't sweep
+        continuous: 0,
+        whole_rows: 0,
+    }
+}
@@ -0,0 +1,4 @@
+pub mod generate_spatial_hash_batch_jobs;
+pub mod grid;
+pub mod initialize_spatial_hash_batch;
+pub mod sort_collidables_by_cell;
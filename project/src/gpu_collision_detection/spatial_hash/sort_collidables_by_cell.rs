@@ -0,0 +1,26 @@
+use bevy::prelude::ResMut;
+
+use crate::gpu_collision_detection::{
+    resources::AllCollidablesThisFrame,
+    single_batch::{
+        convert_collidables_to_wgsl_types::convert_collidables_to_wgsl_types,
+        resources::{SingleBatchDataForWgsl, WgslIdToMetadataMap},
+    },
+};
+
+use super::grid::SpatialHashGrid;
+
+/// Broadphase preparation for the spatial hash: bins and sorts this frame's collidables by cell.
+///
+/// This runs on the CPU as a counting sort, the shader DSL has no atomics on user buffers so per-cell counts cannot be accumulated on the GPU, and the sort is O(n) while the narrow phase it feeds is the expensive part.
+/// Every batch uploads the whole sorted population, so it is converted to WGSL types once here instead of per batch.
+pub fn sort_collidables_by_cell(
+    mut all_collidables: ResMut<AllCollidablesThisFrame>,
+    mut grid: ResMut<SpatialHashGrid>,
+    mut wgsl_data: ResMut<SingleBatchDataForWgsl>,
+    mut wgsl_id_to_metadata: ResMut<WgslIdToMetadataMap>,
+) {
+    *grid = SpatialHashGrid::build(&mut all_collidables.0);
+    *wgsl_data =
+        convert_collidables_to_wgsl_types(all_collidables.0.clone(), &mut wgsl_id_to_metadata);
+}
@@ -0,0 +1,114 @@
+use std::collections::{BTreeSet, HashMap};
+
+use bevy::{
+    MinimalPlugins,
+    app::{App, Plugin},
+    prelude::Entity,
+};
+use bevy_gpu_compute_core::{
+    IterSpaceDimmension,
+    wgsl::shader_module::{
+        complete_shader_module::WgslShaderModule, user_defined_portion::WgslShaderModuleUserPortion,
+    },
+};
+
+use crate::{
+    colliding_entities::TrackCollidingEntities,
+    colliding_pair::{CollidingPairs, Contact},
+    components_and_resources::{CollisionLayers, SysInfo},
+};
+
+use super::{
+    plugin::GpuCollisionDetectionPlugin,
+    resources::{CollisionDimensions, CollisionOverflowPolicy, GpuBroadphase, MaxBatchSize},
+};
+
+/// The CPU emulation runs the rust version of a shader module, so this is the only check of the WGSL that actually goes to the GPU
+pub fn assert_valid_wgsl(parsed: WgslShaderModuleUserPortion) {
+    let wgsl = WgslShaderModule::new(parsed).wgsl_code(IterSpaceDimmension::TwoD);
+    let module = naga::front::wgsl::parse_str(&wgsl)
+        .unwrap_or_else(|e| panic!("{}", e.emit_to_string(&wgsl)));
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .unwrap_or_else(|e| panic!("{}", e.emit_to_string(&wgsl)));
+}
+
+/// Emulated on the CPU, with a scale too small for the batches so overflowing batches are rerun
+pub fn emulated_plugin(dimensions: CollisionDimensions) -> GpuCollisionDetectionPlugin {
+    GpuCollisionDetectionPlugin {
+        max_detectable_collisions_scale: Some(0.01),
+        broadphase: GpuBroadphase::BruteForce,
+        overflow_policy: CollisionOverflowPolicy::RerunBatch,
+        assert_batch_plan: true,
+        emulate_on_cpu: true,
+        dimensions,
+        compute_contacts: false,
+        continuous: false,
+        collision_observers: false,
+        colliding_entities: TrackCollidingEntities::Off,
+        default_layers: CollisionLayers::ALL,
+        max_batch_size: None,
+        deterministic: false,
+    }
+}
+
+/// An app with the plugin and the resources that the binary inserts
+pub fn test_app(plugin: impl Plugin) -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, plugin))
+        .insert_resource(SysInfo {
+            total_mem: u64::MAX,
+        })
+        .insert_resource(CollidingPairs(Vec::new()));
+    app
+}
+
+/// Runs two frames, with a small batch size on the second one so it is split into several batch jobs. Returns the spawn order of each pair's entities, in the pair's order
+pub fn detect_spawned(
+    app: &mut App,
+    spawn_order: &HashMap<Entity, usize>,
+) -> Vec<(usize, usize, Option<Contact>)> {
+    app.update();
+    app.insert_resource(MaxBatchSize(7));
+    app.update();
+    app.world()
+        .resource::<CollidingPairs>()
+        .0
+        .iter()
+        .map(|pair| {
+            (
+                spawn_order[&pair.metadata1.entity],
+                spawn_order[&pair.metadata2.entity],
+                pair.contact,
+            )
+        })
+        .collect()
+}
+
+/// The pairs with the lower index first
+pub fn unordered(pairs: &[(usize, usize, Option<Contact>)]) -> BTreeSet<(usize, usize)> {
+    pairs
+        .iter()
+        .map(|(a, b, _)| (*a.min(b), *a.max(b)))
+        .collect()
+}
+
+/// Every pair of collidables whose layers interact and that intersect
+pub fn expected_pairs<T>(
+    collidables: &[T],
+    layers: impl Fn(&T) -> CollisionLayers,
+    intersect: impl Fn(&T, &T) -> bool,
+) -> BTreeSet<(usize, usize)> {
+    let mut pairs = BTreeSet::new();
+    for (i, a) in collidables.iter().enumerate() {
+        for (j, b) in collidables.iter().enumerate().skip(i + 1) {
+            if layers(a).interacts_with(&layers(b)) && intersect(a, b) {
+                pairs.insert((i, j));
+            }
+        }
+    }
+    pairs
+}
@@ -0,0 +1,68 @@
+use bevy::{
+    math::Vec3,
+    prelude::{Res, ResMut},
+};
+
+use crate::gpu_collision_detection::{
+    entity_metadata::CollidableMetadata,
+    multi_batch_manager::resources::{GpuCollisionBatchJobs, GpuCollisionBatchManager},
+    resources::AllCollidablesThisFrame,
+    single_batch::{
+        convert_collidables_to_wgsl_types::{CollidableShape, PerCollidableDataRequiredByGpu},
+        resources::WgslIdToMetadataMap,
+    },
+};
+
+use super::{resources::SingleBatchDataForWgsl3d, shader::collision_detection_3d_module};
+
+pub fn convert_collidables_to_wgsl_types_3d(
+    collidables: &[PerCollidableDataRequiredByGpu],
+    wgsl_id_to_metadata: &mut WgslIdToMetadataMap,
+) -> SingleBatchDataForWgsl3d {
+    let mut data = SingleBatchDataForWgsl3d::default();
+    wgsl_id_to_metadata.0 = Vec::with_capacity(collidables.len());
+    for collidable in collidables {
+        data.positions
+            .push(collision_detection_3d_module::Position {
+                x: collidable.center_x,
+                y: collidable.center_y,
+                z: collidable.center_z,
+            });
+        let half_size = match &collidable.shape {
+            CollidableShape::Sphere => Vec3::ZERO,
+            CollidableShape::Cuboid { half_size } => *half_size,
+            _ => unreachable!("2D shapes are converted by convert_collidables_to_wgsl_types"),
+        };
+        data.shapes.push(collision_detection_3d_module::Shape {
+            half_x: half_size.x,
+            half_y: half_size.y,
+            half_z: half_size.z,
+            radius: collidable.radius,
+            kind: collidable.shape.shader_kind(),
+        });
+        data.layers.push(collision_detection_3d_module::Layers {
+            memberships: collidable.layers.memberships,
+            filters: collidable.layers.filters,
+        });
+        wgsl_id_to_metadata
+            .0
+            .push(CollidableMetadata::from(collidable));
+    }
+    data
+}
+
+/// `convert_batch_to_wgsl_types` for the 3D shader
+pub fn convert_batch_to_wgsl_types_3d(
+    batch_manager: Res<GpuCollisionBatchManager>,
+    jobs: Res<GpuCollisionBatchJobs>,
+    all_collidables: Res<AllCollidablesThisFrame>,
+    mut wgsl_id_to_metadata: ResMut<WgslIdToMetadataMap>,
+    mut wgsl_data: ResMut<SingleBatchDataForWgsl3d>,
+) {
+    let job = &jobs.0[batch_manager.current_batch_job];
+    let mut batch = all_collidables.0[job.start_index_incl..job.end_index_excl].to_vec();
+    if let Some(second_range) = job.second_range() {
+        batch.extend_from_slice(&all_collidables.0[second_range]);
+    }
+    *wgsl_data = convert_collidables_to_wgsl_types_3d(&batch, &mut wgsl_id_to_metadata);
+}
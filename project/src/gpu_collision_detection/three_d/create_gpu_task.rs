@@ -0,0 +1,23 @@
+use bevy::prelude::ResMut;
+use bevy_gpu_compute::prelude::{BevyGpuComputeTaskCreator, IterationSpace};
+
+use super::shader::collision_detection_3d_module;
+use crate::gpu_collision_detection::create_gpu_task::GpuTaskCreated;
+
+pub fn create_gpu_task_3d(
+    mut gpu_task_creator: BevyGpuComputeTaskCreator,
+    mut created: ResMut<GpuTaskCreated>,
+) {
+    let initial_iteration_space = IterationSpace::new(100, 100, 1);
+    let initial_max_output_lengths = collision_detection_3d_module::MaxOutputLengthsBuilder::new()
+        .set_collision_result(100)
+        .set_collision_contact(100)
+        .finish();
+    gpu_task_creator.create_task_from_rust_shader::<collision_detection_3d_module::Types>(
+        "collision_detection_3d", // ensure name is unique
+        collision_detection_3d_module::parsed(),
+        initial_iteration_space,
+        initial_max_output_lengths,
+    );
+    created.0 = true;
+}
@@ -0,0 +1,261 @@
+use bevy::prelude::{Res, ResMut};
+
+use crate::gpu_collision_detection::single_batch::{
+    emulate_collision_pass::{emulate_shader, emulated_dispatch},
+    resources::{CollisionShaderOutput, CollisionShaderPass, ShaderCollisionResult},
+};
+
+use super::{
+    resources::SingleBatchDataForWgsl3d, run_collision_pass_on_gpu::batch_config,
+    shader::collision_detection_3d_module,
+};
+
+/// `emulate_collision_pass` for the 3D shader
+pub fn emulate_collision_pass_3d(
+    pass: Res<CollisionShaderPass>,
+    wgsl_data: Res<SingleBatchDataForWgsl3d>,
+    mut output: ResMut<CollisionShaderOutput>,
+) {
+    let (iteration_space, config) = emulated_dispatch(&pass);
+    let config = batch_config(&config);
+    let max_lengths = pass.output_lengths(config.pushes_contacts());
+    let (results, contacts, pushed_count) = emulate_shader(
+        iteration_space,
+        max_lengths,
+        |iter_pos, results, contacts| {
+            collision_detection_3d_module::main(
+                iter_pos,
+                config,
+                wgsl_data.positions.clone(),
+                wgsl_data.shapes.clone(),
+                wgsl_data.layers.clone(),
+                results,
+                contacts,
+            );
+        },
+    );
+    let pushed = results
+        .iter()
+        .map(ShaderCollisionResult::from)
+        .chain(contacts.iter().map(ShaderCollisionResult::from))
+        .collect();
+    output.set_pushed(config.count_only == 1, pushed, pushed_count);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use bevy::{
+        math::{
+            Vec3,
+            bounding::{Aabb3d, BoundingSphere, IntersectsVolume},
+        },
+        prelude::{App, Entity, Transform},
+    };
+
+    use crate::{
+        colliding_pair::{CollidingPairs, Contact},
+        components_and_resources::{
+            BoundingBox3dComponent, BoundingSphereComponent, CollisionLayers,
+        },
+        gpu_collision_detection::{
+            plugin::GpuCollisionDetectionPlugin,
+            resources::{CollisionDimensions, GpuBroadphase},
+            test_helpers::{detect_spawned, emulated_plugin, expected_pairs, test_app, unordered},
+        },
+    };
+
+    /// A sphere, or a box with these half sizes
+    type Collidable = (Vec3, f32, Option<Vec3>, CollisionLayers);
+
+    fn collidables() -> Vec<Collidable> {
+        (0..60)
+            .map(|i| {
+                let position = Vec3::new(
+                    (i * 37 % 40) as f32,
+                    (i * 61 % 40) as f32,
+                    (i * 23 % 40) as f32,
+                );
+                let radius = 4. + (i % 5) as f32;
+                let half_size = (i % 3 == 0).then(|| Vec3::new(radius, 3., 2. + (i % 4) as f32));
+                let layers = match i % 4 {
+                    3 => CollisionLayers::ALL,
+                    layer => CollisionLayers::new(1 << layer, 1 << ((layer + 1) % 3)),
+                };
+                (position, radius, half_size, layers)
+            })
+            // boxes that overlap the least along y, and a sphere with its center inside a box
+            .chain([
+                (
+                    Vec3::new(120., 0., 0.),
+                    6.,
+                    Some(Vec3::splat(3.)),
+                    CollisionLayers::ALL,
+                ),
+                (
+                    Vec3::new(121., 4., 0.),
+                    6.,
+                    Some(Vec3::splat(3.)),
+                    CollisionLayers::ALL,
+                ),
+                (
+                    Vec3::new(100., 0., 0.),
+                    4.,
+                    Some(Vec3::splat(2.)),
+                    CollisionLayers::ALL,
+                ),
+                (Vec3::new(100.5, 0., 0.), 1., None, CollisionLayers::ALL),
+            ])
+            .collect()
+    }
+
+    fn intersect(a: &Collidable, b: &Collidable) -> bool {
+        let sphere = |(position, radius, ..): &Collidable| BoundingSphere::new(*position, *radius);
+        match (a.2, b.2) {
+            (None, None) => sphere(a).intersects(&sphere(b)),
+            (Some(h1), Some(h2)) => Aabb3d::new(a.0, h1).intersects(&Aabb3d::new(b.0, h2)),
+            (Some(half_size), None) => Aabb3d::new(a.0, half_size).intersects(&sphere(b)),
+            (None, Some(_)) => intersect(b, a),
+        }
+    }
+
+    fn detect_pairs(
+        plugin: GpuCollisionDetectionPlugin,
+        collidables: &[Collidable],
+    ) -> BTreeSet<(usize, usize)> {
+        unordered(&detect(plugin, collidables))
+    }
+
+    /// The indices of each pair's collidables, in the pair's order
+    fn detect(
+        plugin: GpuCollisionDetectionPlugin,
+        collidables: &[Collidable],
+    ) -> Vec<(usize, usize, Option<Contact>)> {
+        let mut app = test_app(plugin);
+        let spawn_order = spawn(&mut app, collidables);
+        let pairs = detect_spawned(&mut app, &spawn_order);
+        assert!(
+            app.world()
+                .resource::<CollidingPairs>()
+                .0
+                .iter()
+                .all(|pair| {
+                    pair.metadata1.z == collidables[spawn_order[&pair.metadata1.entity]].0.z
+                })
+        );
+        pairs
+    }
+
+    /// Each entity's index in `collidables`
+    fn spawn(app: &mut App, collidables: &[Collidable]) -> HashMap<Entity, usize> {
+        collidables
+            .iter()
+            .enumerate()
+            .map(|(i, (position, radius, half_size, layers))| {
+                let mut entity = app
+                    .world_mut()
+                    .spawn((Transform::from_translation(*position), *layers));
+                match half_size {
+                    None => entity.insert(BoundingSphereComponent(BoundingSphere::new(
+                        *position, *radius,
+                    ))),
+                    Some(half_size) => {
+                        entity.insert(BoundingBox3dComponent(Aabb3d::new(*position, *half_size)))
+                    }
+                };
+                (entity.id(), i)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_emulated_3d_pipeline_matches_brute_force() {
+        let collidables = collidables();
+        let expected = expected_pairs(&collidables, |collidable| collidable.3, intersect);
+        assert!(!expected.is_empty());
+        assert_eq!(detect_pairs(plugin(), &collidables), expected);
+        assert_eq!(
+            detect_pairs(plugin().with_exact_output_sizing(), &collidables),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_3d_falls_back_to_brute_force_when_switched_to_spatial_hash() {
+        let collidables = collidables();
+        let mut app = test_app(plugin());
+        let spawn_order = spawn(&mut app, &collidables);
+        app.update();
+        app.insert_resource(GpuBroadphase::SpatialHash);
+        let pairs = detect_spawned(&mut app, &spawn_order);
+        assert_eq!(
+            *app.world().resource::<GpuBroadphase>(),
+            GpuBroadphase::BruteForce
+        );
+        assert_eq!(
+            unordered(&pairs),
+            expected_pairs(&collidables, |collidable| collidable.3, intersect)
+        );
+    }
+
+    fn plugin() -> GpuCollisionDetectionPlugin {
+        emulated_plugin(CollisionDimensions::Three)
+    }
+
+    /// The shortest distance `b` has to move for the pair to only touch
+    fn expected_depth(a: &Collidable, b: &Collidable) -> f32 {
+        let offset = b.0 - a.0;
+        match (a.2, b.2) {
+            (None, None) => a.1 + b.1 - offset.length(),
+            (Some(h1), Some(h2)) => (h1 + h2 - offset.abs()).min_element(),
+            (Some(half_size), None) => {
+                let outside = offset - offset.clamp(-half_size, half_size);
+                if outside == Vec3::ZERO {
+                    b.1 + (half_size - offset.abs()).min_element()
+                } else {
+                    b.1 - outside.length()
+                }
+            }
+            (None, Some(_)) => expected_depth(b, a),
+        }
+    }
+
+    /// Spheres and boxes have exact contacts: moving the second collidable by the depth along the normal leaves the pair just touching
+    #[test]
+    fn test_emulated_3d_contacts() {
+        let mut collidables = collidables();
+        // collidables are queried one archetype at a time, so reversing them (which spawns a sphere first) puts the spheres first in the sphere and box pairs
+        for _ in 0..2 {
+            let pairs = detect(plugin().with_contacts(), &collidables);
+            assert!(!pairs.is_empty());
+            for (a, b, contact) in pairs {
+                let contact = contact.expect("contacts were turned on");
+                let (a, b) = (&collidables[a], &collidables[b]);
+                let moved = |distance: f32| {
+                    let mut moved = *b;
+                    moved.0 += contact.normal * distance;
+                    moved
+                };
+                assert!((contact.normal.length() - 1.).abs() < 1e-5);
+                assert!((contact.depth - expected_depth(a, b)).abs() < 1e-4);
+                assert!(!intersect(a, &moved(contact.depth + 0.01)));
+                if contact.depth > 0.01 {
+                    assert!(intersect(a, &moved(contact.depth - 0.01)));
+                }
+                // halfway between the surfaces, so half the depth along the normal leaves the first collidable, and half the depth back leaves the second
+                let point = |distance: f32| {
+                    (
+                        contact.point + contact.normal * distance,
+                        0.,
+                        None,
+                        CollisionLayers::ALL,
+                    )
+                };
+                assert!(!intersect(a, &point(contact.depth * 0.5 + 0.01)));
+                assert!(!intersect(b, &point(-contact.depth * 0.5 - 0.01)));
+            }
+            collidables.reverse();
+        }
+    }
+}
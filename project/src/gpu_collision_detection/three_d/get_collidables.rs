@@ -0,0 +1,76 @@
+use bevy::{
+    math::{Vec3, bounding::BoundingVolume},
+    prelude::{Entity, Or, Query, Res, ResMut, Transform, With},
+};
+
+use crate::{
+    components_and_resources::{
+        BoundingBox3dComponent, BoundingSphereComponent, CollisionLayers, CollisionUserData,
+        DefaultCollisionLayers, Sensor, SysInfo,
+    },
+    gpu_collision_detection::{
+        get_collidables::check_memory_for_collisions,
+        multi_batch_manager::population::CollidablePopulation,
+        resources::{AllCollidablesThisFrame, MaxDetectableCollisionsScale},
+        single_batch::convert_collidables_to_wgsl_types::{
+            CollidableShape, PerCollidableDataRequiredByGpu,
+        },
+    },
+};
+
+/// `get_collidables` for the 3D plugin, which reads spheres and boxes and the `Transform`'s z
+#[allow(clippy::type_complexity)]
+pub fn get_collidables_3d(
+    query: Query<
+        (
+            Entity,
+            &Transform,
+            Option<&BoundingSphereComponent>,
+            Option<&BoundingBox3dComponent>,
+            Option<&Sensor>,
+            Option<&CollisionLayers>,
+            Option<&CollisionUserData>,
+        ),
+        Or<(With<BoundingSphereComponent>, With<BoundingBox3dComponent>)>,
+    >,
+    max_detectable_collisions_scale: Option<Res<MaxDetectableCollisionsScale>>,
+    sys_info: Res<SysInfo>,
+    default_layers: Res<DefaultCollisionLayers>,
+
+    mut population: ResMut<CollidablePopulation>,
+    mut all_collidables: ResMut<AllCollidablesThisFrame>,
+) {
+    let collidables: Vec<PerCollidableDataRequiredByGpu> = query
+        .iter()
+        .map(
+            |(entity, transform, bounding_sphere, bounding_box, sensor, layers, user_data)| {
+                let (radius, shape) = match (bounding_sphere, bounding_box) {
+                    (Some(bounding_sphere), _) => {
+                        (bounding_sphere.0.radius(), CollidableShape::Sphere)
+                    }
+                    (None, Some(bounding_box)) => {
+                        let half_size = Vec3::from(bounding_box.0.half_size());
+                        (half_size.length(), CollidableShape::Cuboid { half_size })
+                    }
+                    (None, None) => unreachable!("filtered out by the query"),
+                };
+                PerCollidableDataRequiredByGpu {
+                    entity,
+                    center_x: transform.translation.x,
+                    center_y: transform.translation.y,
+                    center_z: transform.translation.z,
+                    previous_x: transform.translation.x,
+                    previous_y: transform.translation.y,
+                    radius,
+                    shape,
+                    is_sensor: sensor.is_some(),
+                    layers: layers.copied().unwrap_or(default_layers.0),
+                    user_data: user_data.cloned(),
+                }
+            },
+        )
+        .collect();
+    population.0 = collidables.len();
+    check_memory_for_collisions(population.0, max_detectable_collisions_scale, &sys_info);
+    all_collidables.0 = collidables;
+}
@@ -0,0 +1,7 @@
+pub mod convert_collidables_to_wgsl_types;
+pub mod create_gpu_task;
+pub mod emulate_collision_pass;
+pub mod get_collidables;
+pub mod resources;
+pub mod run_collision_pass_on_gpu;
+pub mod shader;
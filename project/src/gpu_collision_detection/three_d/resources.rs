@@ -0,0 +1,11 @@
+use bevy::prelude::Resource;
+
+use super::shader::collision_detection_3d_module;
+
+/// The 3D counterpart of `SingleBatchDataForWgsl`
+#[derive(Default, Clone, Resource)]
+pub struct SingleBatchDataForWgsl3d {
+    pub positions: Vec<collision_detection_3d_module::Position>,
+    pub shapes: Vec<collision_detection_3d_module::Shape>,
+    pub layers: Vec<collision_detection_3d_module::Layers>,
+}
@@ -0,0 +1,126 @@
+use bevy::{
+    math::Vec3,
+    prelude::{Res, ResMut},
+};
+use bevy_gpu_compute::prelude::{GpuTaskReader, GpuTaskRunner};
+
+use crate::{
+    colliding_pair::Contact,
+    gpu_collision_detection::{
+        resources::GpuBroadphase,
+        shader::collision_detection_module,
+        single_batch::resources::{
+            CollisionShaderOutput, CollisionShaderPass, ShaderCollisionResult,
+        },
+    },
+};
+
+use super::{resources::SingleBatchDataForWgsl3d, shader::collision_detection_3d_module};
+
+pub fn run_collision_pass_on_gpu_3d(
+    pass: Res<CollisionShaderPass>,
+    mut wgsl_data: ResMut<SingleBatchDataForWgsl3d>,
+    mut gpu_tasks: GpuTaskRunner,
+) {
+    let config = batch_config(&pass.config);
+    let (max_results, max_contacts) = pass.output_lengths(config.pushes_contacts());
+    let maxes = collision_detection_3d_module::MaxOutputLengthsBuilder::new()
+        .set_collision_result(max_results)
+        .set_collision_contact(max_contacts)
+        .finish();
+    // the batch is converted again before it is rerun, so the detection pass is the last to upload it
+    let inputs = if pass.config.count_only == 0 {
+        std::mem::take(&mut *wgsl_data)
+    } else {
+        wgsl_data.clone()
+    };
+    let queued_commands = gpu_tasks
+        .task("collision_detection_3d")
+        .mutate(Some(pass.iteration_space), Some(maxes))
+        .set_config_inputs(
+            collision_detection_3d_module::ConfigInputDataBuilder::new()
+                .set_batch_config(config)
+                .finish(),
+        )
+        .set_inputs(
+            collision_detection_3d_module::InputDataBuilder::new()
+                .set_position(inputs.positions)
+                .set_shape(inputs.shapes)
+                .set_layers(inputs.layers)
+                .finish(),
+        )
+        .run();
+    gpu_tasks.run_commands(queued_commands);
+}
+
+pub fn read_collision_pass_from_gpu_3d(
+    pass: Res<CollisionShaderPass>,
+    mut gpu_task_reader: GpuTaskReader,
+    mut output: ResMut<CollisionShaderOutput>,
+) {
+    let result = gpu_task_reader
+        .latest_results::<collision_detection_3d_module::OutputDataBuilder>(
+            "collision_detection_3d",
+        )
+        .expect("No output found for the 3D collision detection task");
+    let pushed: Vec<ShaderCollisionResult> = if batch_config(&pass.config).pushes_contacts() {
+        let contacts = result.collision_contact.unwrap_or_default();
+        contacts.iter().map(ShaderCollisionResult::from).collect()
+    } else {
+        let results = result.collision_result.unwrap_or_default();
+        results.iter().map(ShaderCollisionResult::from).collect()
+    };
+    // the compute crate reads back no more than the buffer length of results, however many were pushed
+    let pushed_count = pushed.len();
+    output.set_pushed(pass.config.count_only == 1, pushed, pushed_count);
+}
+
+/// The 3D shader only has the brute force broadphase, so it only needs the brute force part of the config that the batch set up
+pub fn batch_config(
+    config: &collision_detection_module::BroadphaseConfig,
+) -> collision_detection_3d_module::BatchConfig {
+    debug_assert_eq!(
+        (config.mode, config.query_start),
+        (GpuBroadphase::BruteForce.shader_mode(), 0),
+        "3D batches are always brute force"
+    );
+    collision_detection_3d_module::BatchConfig {
+        query_end: config.query_end,
+        second_range_start: config.second_range_start,
+        count_only: config.count_only,
+        contacts: config.contacts,
+        whole_rows: config.whole_rows,
+    }
+}
+
+impl collision_detection_3d_module::BatchConfig {
+    /// Whether the pass pushes its results to `CollisionContact` instead of `CollisionResult`
+    pub fn pushes_contacts(&self) -> bool {
+        self.count_only == 0 && self.contacts == 1
+    }
+}
+
+impl From<&collision_detection_3d_module::CollisionResult> for ShaderCollisionResult {
+    fn from(result: &collision_detection_3d_module::CollisionResult) -> Self {
+        ShaderCollisionResult {
+            entity1: result.entity1,
+            entity2: result.entity2,
+            contact: Contact::default(),
+            time_of_impact: None,
+        }
+    }
+}
+impl From<&collision_detection_3d_module::CollisionContact> for ShaderCollisionResult {
+    fn from(result: &collision_detection_3d_module::CollisionContact) -> Self {
+        ShaderCollisionResult {
+            entity1: result.entity1,
+            entity2: result.entity2,
+            contact: Contact {
+                normal: Vec3::new(result.normal_x, result.normal_y, result.normal_z),
+                depth: result.depth,
+                point: Vec3::new(result.point_x, result.point_y, result.point_z),
+            },
+            time_of_impact: None,
+        }
+    }
+}
@@ -0,0 +1,397 @@
+// wgsl has no implicit returns, so the shader functions need explicit ones, and the generated cpu version of main takes every input and output as an argument
+#![allow(clippy::needless_return, clippy::too_many_arguments)]
+use bevy_gpu_compute::prelude::wgsl_shader_module;
+// the 3D version of `collision_detection_module`, for spheres and boxes. Only has the brute force broadphase, so its config is the brute force part of `BroadphaseConfig`
+#[wgsl_shader_module]
+pub mod collision_detection_3d_module {
+    use bevy_gpu_compute::prelude::*;
+
+    use crate::gpu_collision_detection::wgsl_helpers::sqrt;
+
+    // must match CollidableShape::shader_kind, every other kind is a sphere
+    const SHAPE_BOX: u32 = 1;
+    #[wgsl_config]
+    struct BatchConfig {
+        pub query_end: u32,
+        // where the second range of collidables starts in the uploaded data for cross-batch jobs, 0 for single range jobs
+        pub second_range_start: u32,
+        // 1 for the counting pass, which only pushes the number of collisions each thread found, see the 2D shader
+        pub count_only: u32,
+        // 1 to push every collision with its contact to CollisionContact instead of CollisionResult
+        pub contacts: u32,
+        // 1 for one thread per collidable in the detection pass too, see the 2D shader
+        pub whole_rows: u32,
+    }
+    // separate floats instead of a vec3, which would be padded to 16 bytes in wgsl but not in rust
+    #[wgsl_input_array]
+    struct Position {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+    }
+    #[wgsl_input_array]
+    struct Shape {
+        // half the size of a box
+        pub half_x: f32,
+        pub half_y: f32,
+        pub half_z: f32,
+        // radius of a sphere, unused by boxes
+        pub radius: f32,
+        pub kind: u32,
+    }
+    #[wgsl_input_array]
+    struct Layers {
+        pub memberships: u32,
+        pub filters: u32,
+    }
+    // the counting pass pushes a result for every thread that found collisions, with the number it found as entity2
+    #[wgsl_output_vec]
+    struct CollisionResult {
+        pub entity1: u32,
+        pub entity2: u32,
+    }
+    // a CollisionResult with room for the contact, only pushed to when contacts are on. The normal points from entity1 towards entity2, see Contact
+    #[wgsl_output_vec]
+    struct CollisionContact {
+        pub entity1: u32,
+        pub entity2: u32,
+        pub normal_x: f32,
+        pub normal_y: f32,
+        pub normal_z: f32,
+        pub point_x: f32,
+        pub point_y: f32,
+        pub point_z: f32,
+        pub depth: f32,
+    }
+    fn absolute(x: f32) -> f32 {
+        if x < 0.0 {
+            return -x;
+        }
+        return x;
+    }
+    // 1 or -1, with 0 counting as positive
+    fn direction(x: f32) -> f32 {
+        if x < 0.0 {
+            return -1.0;
+        }
+        return 1.0;
+    }
+    fn clamp_to(d: f32, half: f32) -> f32 {
+        if d < -half {
+            return -half;
+        }
+        if d > half {
+            return half;
+        }
+        return d;
+    }
+    // the middle of where two boxes overlap on one axis
+    fn overlap_middle(c1: f32, h1: f32, c2: f32, h2: f32) -> f32 {
+        let mut low = c1 - h1;
+        if c2 - h2 > low {
+            low = c2 - h2;
+        }
+        let mut high = c1 + h1;
+        if c2 + h2 < high {
+            high = c2 + h2;
+        }
+        return (low + high) * 0.5;
+    }
+    // how far d is outside of -half..half, 0 if it is inside
+    fn outside_by(d: f32, half: f32) -> f32 {
+        let mut distance = d;
+        if distance < 0.0 {
+            distance = -distance;
+        }
+        distance -= half;
+        if distance < 0.0 {
+            return 0.0;
+        }
+        return distance;
+    }
+    // touching shapes intersect, to match bevy's IntersectsVolume
+    fn sphere_box_intersect(
+        dx: f32,
+        dy: f32,
+        dz: f32,
+        radius: f32,
+        half_x: f32,
+        half_y: f32,
+        half_z: f32,
+    ) -> u32 {
+        let ox = outside_by(dx, half_x);
+        let oy = outside_by(dy, half_y);
+        let oz = outside_by(dz, half_z);
+        if ox * ox + oy * oy + oz * oz <= radius * radius {
+            return 1u32;
+        }
+        return 0u32;
+    }
+    // d is the offset between the centers, the halves are the box half sizes, or the radius on every axis for spheres
+    fn shapes_intersect(
+        dx: f32,
+        dy: f32,
+        dz: f32,
+        kind1: u32,
+        r1: f32,
+        h1x: f32,
+        h1y: f32,
+        h1z: f32,
+        kind2: u32,
+        r2: f32,
+        h2x: f32,
+        h2y: f32,
+        h2z: f32,
+    ) -> u32 {
+        if kind1 == SHAPE_BOX && kind2 == SHAPE_BOX {
+            if outside_by(dx, h1x + h2x) > 0.0
+                || outside_by(dy, h1y + h2y) > 0.0
+                || outside_by(dz, h1z + h2z) > 0.0
+            {
+                return 0u32;
+            }
+            return 1u32;
+        }
+        if kind1 == SHAPE_BOX {
+            return sphere_box_intersect(dx, dy, dz, r2, h1x, h1y, h1z);
+        }
+        if kind2 == SHAPE_BOX {
+            return sphere_box_intersect(dx, dy, dz, r1, h2x, h2y, h2z);
+        }
+        let radius_sum = r1 + r2;
+        if dx * dx + dy * dy + dz * dz <= radius_sum * radius_sum {
+            return 1u32;
+        }
+        return 0u32;
+    }
+    // must match CollisionLayers::interacts_with
+    fn layers_interact(memberships1: u32, filters1: u32, memberships2: u32, filters2: u32) -> u32 {
+        if (memberships1 & filters2) != 0 && (memberships2 & filters1) != 0 {
+            return 1u32;
+        }
+        return 0u32;
+    }
+    pub fn main(iter_pos: WgslIterationPosition) {
+        let config = WgslConfigInput::get::<BatchConfig>();
+        let num_collidables = WgslVecInput::vec_len::<Position>();
+        // the same brute force ranges as the 2D shader, every thread tests current_entity against other_entity..range_end
+        let current_entity = iter_pos.x;
+        let mut other_entity: u32 = 0;
+        let mut range_end: u32 = 0;
+        let mut first_other = current_entity + 1;
+        if config.second_range_start > 0 {
+            first_other = config.second_range_start;
+        }
+        if config.count_only == 1 || config.whole_rows == 1 {
+            if current_entity < config.query_end && iter_pos.y == 0 {
+                other_entity = first_other;
+                range_end = num_collidables;
+            }
+        } else {
+            other_entity = config.second_range_start + iter_pos.y;
+            if current_entity < config.query_end
+                && other_entity >= first_other
+                && other_entity < num_collidables
+            {
+                range_end = other_entity + 1;
+            }
+        }
+        let mut count: u32 = 0;
+        while other_entity < range_end {
+            let current_pos = WgslVecInput::vec_val::<Position>(current_entity);
+            let current_shape = WgslVecInput::vec_val::<Shape>(current_entity);
+            let other_pos = WgslVecInput::vec_val::<Position>(other_entity);
+            let other_shape = WgslVecInput::vec_val::<Shape>(other_entity);
+            let current_layers = WgslVecInput::vec_val::<Layers>(current_entity);
+            let other_layers = WgslVecInput::vec_val::<Layers>(other_entity);
+            let dx = other_pos.x - current_pos.x;
+            let dy = other_pos.y - current_pos.y;
+            let dz = other_pos.z - current_pos.z;
+            let mut hit: u32 = 0;
+            if layers_interact(
+                current_layers.memberships,
+                current_layers.filters,
+                other_layers.memberships,
+                other_layers.filters,
+            ) == 1
+                && shapes_intersect(
+                    dx,
+                    dy,
+                    dz,
+                    current_shape.kind,
+                    current_shape.radius,
+                    current_shape.half_x,
+                    current_shape.half_y,
+                    current_shape.half_z,
+                    other_shape.kind,
+                    other_shape.radius,
+                    other_shape.half_x,
+                    other_shape.half_y,
+                    other_shape.half_z,
+                ) == 1
+            {
+                hit = 1u32;
+            }
+            // the contact along the axis of least overlap: the line between the centers for spheres, out of a box's faces for a sphere and a box, and the x, y or z axis for two boxes
+            let mut normal_x: f32 = 0.0;
+            let mut normal_y: f32 = 0.0;
+            let mut normal_z: f32 = 0.0;
+            let mut point_x: f32 = 0.0;
+            let mut point_y: f32 = 0.0;
+            let mut point_z: f32 = 0.0;
+            let mut depth: f32 = 0.0;
+            if hit == 1 && config.count_only == 0 && config.contacts == 1 {
+                if current_shape.kind == SHAPE_BOX && other_shape.kind == SHAPE_BOX {
+                    depth = current_shape.half_x + other_shape.half_x - absolute(dx);
+                    normal_x = direction(dx);
+                    let overlap_y = current_shape.half_y + other_shape.half_y - absolute(dy);
+                    if overlap_y < depth {
+                        depth = overlap_y;
+                        normal_x = 0.0;
+                        normal_y = direction(dy);
+                    }
+                    let overlap_z = current_shape.half_z + other_shape.half_z - absolute(dz);
+                    if overlap_z < depth {
+                        depth = overlap_z;
+                        normal_x = 0.0;
+                        normal_y = 0.0;
+                        normal_z = direction(dz);
+                    }
+                    point_x = overlap_middle(
+                        current_pos.x,
+                        current_shape.half_x,
+                        other_pos.x,
+                        other_shape.half_x,
+                    );
+                    point_y = overlap_middle(
+                        current_pos.y,
+                        current_shape.half_y,
+                        other_pos.y,
+                        other_shape.half_y,
+                    );
+                    point_z = overlap_middle(
+                        current_pos.z,
+                        current_shape.half_z,
+                        other_pos.z,
+                        other_shape.half_z,
+                    );
+                } else if current_shape.kind == SHAPE_BOX || other_shape.kind == SHAPE_BOX {
+                    // s is the sphere's center relative to the box's, the normal is found pointing from the box to the sphere
+                    let mut sx = dx;
+                    let mut sy = dy;
+                    let mut sz = dz;
+                    let mut sphere_x = other_pos.x;
+                    let mut sphere_y = other_pos.y;
+                    let mut sphere_z = other_pos.z;
+                    let mut radius = other_shape.radius;
+                    let mut half_x = current_shape.half_x;
+                    let mut half_y = current_shape.half_y;
+                    let mut half_z = current_shape.half_z;
+                    let mut towards_other: f32 = 1.0;
+                    if other_shape.kind == SHAPE_BOX {
+                        sx = -dx;
+                        sy = -dy;
+                        sz = -dz;
+                        sphere_x = current_pos.x;
+                        sphere_y = current_pos.y;
+                        sphere_z = current_pos.z;
+                        radius = current_shape.radius;
+                        half_x = other_shape.half_x;
+                        half_y = other_shape.half_y;
+                        half_z = other_shape.half_z;
+                        towards_other = -1.0;
+                    }
+                    let ox = sx - clamp_to(sx, half_x);
+                    let oy = sy - clamp_to(sy, half_y);
+                    let oz = sz - clamp_to(sz, half_z);
+                    let outside_squared = ox * ox + oy * oy + oz * oz;
+                    if outside_squared > 0.0 {
+                        let outside = sqrt(outside_squared);
+                        normal_x = ox / outside;
+                        normal_y = oy / outside;
+                        normal_z = oz / outside;
+                        depth = radius - outside;
+                    } else {
+                        // the center is inside the box, so the sphere leaves through the closest face
+                        depth = radius + half_x - absolute(sx);
+                        normal_x = direction(sx);
+                        let through_y = radius + half_y - absolute(sy);
+                        if through_y < depth {
+                            depth = through_y;
+                            normal_x = 0.0;
+                            normal_y = direction(sy);
+                        }
+                        let through_z = radius + half_z - absolute(sz);
+                        if through_z < depth {
+                            depth = through_z;
+                            normal_x = 0.0;
+                            normal_y = 0.0;
+                            normal_z = direction(sz);
+                        }
+                    }
+                    point_x = sphere_x - normal_x * radius + normal_x * depth * 0.5;
+                    point_y = sphere_y - normal_y * radius + normal_y * depth * 0.5;
+                    point_z = sphere_z - normal_z * radius + normal_z * depth * 0.5;
+                    normal_x *= towards_other;
+                    normal_y *= towards_other;
+                    normal_z *= towards_other;
+                } else {
+                    let distance = sqrt(dx * dx + dy * dy + dz * dz);
+                    // any direction works for spheres with the same center
+                    normal_y = 1.0;
+                    if distance > 0.0 {
+                        normal_x = dx / distance;
+                        normal_y = dy / distance;
+                        normal_z = dz / distance;
+                    }
+                    depth = current_shape.radius + other_shape.radius - distance;
+                    let from_current = current_shape.radius - depth * 0.5;
+                    point_x = current_pos.x + normal_x * from_current;
+                    point_y = current_pos.y + normal_y * from_current;
+                    point_z = current_pos.z + normal_z * from_current;
+                }
+            }
+            if hit == 1 {
+                if config.count_only == 1 {
+                    count += 1u32;
+                } else if config.contacts == 1 {
+                    WgslOutput::push::<CollisionContact>(CollisionContact {
+                        entity1: current_entity,
+                        entity2: other_entity,
+                        normal_x,
+                        normal_y,
+                        normal_z,
+                        point_x,
+                        point_y,
+                        point_z,
+                        depth,
+                    });
+                } else {
+                    WgslOutput::push::<CollisionResult>(CollisionResult {
+                        entity1: current_entity,
+                        entity2: other_entity,
+                    });
+                }
+            }
+            other_entity += 1u32;
+        }
+        if count > 0 {
+            WgslOutput::push::<CollisionResult>(CollisionResult {
+                entity1: current_entity,
+                entity2: count,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gpu_collision_detection::test_helpers::assert_valid_wgsl;
+
+    use super::collision_detection_3d_module;
+
+    #[test]
+    fn test_generated_wgsl_is_valid() {
+        assert_valid_wgsl(collision_detection_3d_module::parsed());
+    }
+}
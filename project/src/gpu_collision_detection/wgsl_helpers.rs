@@ -0,0 +1,6 @@
+//! WGSL built-ins for the CPU versions of the shader modules. The modules may only import from paths with a `wgsl_helpers` in them, and the imports are left out of the WGSL, where the calls go to the real built-ins
+
+/// WGSL's `sqrt`
+pub fn sqrt(x: f32) -> f32 {
+    x.sqrt()
+}
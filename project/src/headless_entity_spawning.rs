@@ -5,11 +5,12 @@ use bevy::{
     utils::default,
 };
 
-use crate::{
-    components_and_resources::{BoundingCircleComponent, NumEntitiesSpawned, Sensor},
-    config::RunConfig,
+use gpu_accelerated_collision_detection::components_and_resources::{
+    BoundingCircleComponent, NumEntitiesSpawned, Sensor,
 };
 
+use crate::config::RunConfig;
+
 pub fn spawn_entities_headless(mut commands: Commands, run_config: Res<RunConfig>) {
     let mut count = 0;
     for x in run_config.bottom_left_x..run_config.top_right_x {
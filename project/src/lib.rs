@@ -0,0 +1,15 @@
+//! Collision detection for Bevy, on the CPU or in a compute shader on the GPU. Add a `CollisionDetectionPlugin` built with `CollisionDetectionPlugin::builder()` and read `CollidingPairs` after `CollisionDetectionSet`
+
+pub mod auto_method_selection;
+pub mod colliding_entities;
+pub mod colliding_pair;
+pub mod collision_core;
+pub mod collision_detection_plugin;
+pub mod collision_events;
+pub mod components_and_resources;
+pub mod contact_graph;
+pub mod continuous_collision_detection;
+pub mod cpu_collision_detection;
+pub mod deterministic_collisions;
+pub mod gpu_collision_detection;
+pub mod helpers;
@@ -1,21 +1,15 @@
-use collision_detection_plugin::CollisionDetectionMethod;
 use config::RunConfig;
+use gpu_accelerated_collision_detection::collision_detection_plugin::CollisionDetectionMethod;
 
 use crate::collision_detection_performance_test::collision_detection_performance_test;
 
-pub mod colliding_pair;
 pub mod collision_detection_performance_test;
-pub mod collision_detection_plugin;
 pub mod collision_processing;
-pub mod components_and_resources;
 pub mod config;
-pub mod cpu_collision_detection;
 pub mod entity_movement;
 pub mod entity_spawning;
-pub mod gpu_collision_detection;
 pub mod graphics;
 pub mod headless_entity_spawning;
-pub mod helpers;
 pub mod performance;
 
 fn main() {
@@ -31,5 +25,8 @@ fn main() {
     if run_config.use_gpu {
         method = CollisionDetectionMethod::Gpu;
     }
+    if let Some(config_method) = run_config.method {
+        method = config_method;
+    }
     collision_detection_performance_test(method, run_config);
 }
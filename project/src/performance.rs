@@ -7,9 +7,9 @@ use std::fs::{File, OpenOptions};
 use std::io::{self, Read};
 use std::time::Instant;
 
-use crate::collision_detection_plugin::CollisionDetectionMethod;
-use crate::components_and_resources::NumEntitiesSpawned;
 use crate::config::RunConfig;
+use gpu_accelerated_collision_detection::collision_detection_plugin::CollisionDetectionMethod;
+use gpu_accelerated_collision_detection::components_and_resources::NumEntitiesSpawned;
 
 const FRAME_INITIAL_STARTUP_GRACE_PERIOD: f64 = 3.;
 